@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+use std::ops::AddAssign;
+
+use crate::hittables::HitRecord;
+use crate::ray::Ray;
+use crate::vector::{Color, Vector3D, N};
+
+/// An arbitrary output variable, rendered alongside the color for compositing
+/// or to guide a denoiser
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+    /// Base color of the surface seen first
+    Albedo,
+    /// Shading normal of the surface seen first, facing the camera
+    Normal,
+    /// Distance to the surface seen first, 0 where nothing is
+    Depth,
+    /// Light given off by whatever is seen first, lights or the background
+    Emission,
+    /// Light reaching the camera after one bounce off a diffuse surface
+    DirectDiffuse,
+    /// Light reaching the camera after more bounces, the first diffuse
+    IndirectDiffuse,
+    /// Light reaching the camera after one bounce off a specular surface
+    DirectSpecular,
+    /// Light reaching the camera after more bounces, the first specular
+    IndirectSpecular,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Emission,
+        Aov::DirectDiffuse,
+        Aov::IndirectDiffuse,
+        Aov::DirectSpecular,
+        Aov::IndirectSpecular,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Emission => "emission",
+            Aov::DirectDiffuse => "direct-diffuse",
+            Aov::IndirectDiffuse => "indirect-diffuse",
+            Aov::DirectSpecular => "direct-specular",
+            Aov::IndirectSpecular => "indirect-specular",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+}
+
+/// Values of every AOV for one camera sample. The light ones add up to the
+/// color for integrators that break it down, and are left black by others.
+#[derive(Clone, Copy, Default)]
+pub struct Aovs {
+    pub albedo: Color,
+    pub normal: Vector3D,
+    pub depth: N,
+    pub emission: Color,
+    pub direct_diffuse: Color,
+    pub indirect_diffuse: Color,
+    pub direct_specular: Color,
+    pub indirect_specular: Color,
+}
+
+impl Aovs {
+    /// Fill in the surface AOVs from the first hit of a camera ray
+    pub fn record_hit(&mut self, ray: &Ray, hit_record: &HitRecord) {
+        self.albedo = hit_record.material.albedo(hit_record);
+        self.normal = hit_record.normal;
+        self.depth = hit_record.t * ray.direction().length();
+    }
+
+    /// Add light that reached the camera after `bounces` scattering events,
+    /// the first of which off a specular surface if `specular`
+    pub fn add_light(&mut self, bounces: usize, specular: bool, light: Color) {
+        let channel = match (bounces, specular) {
+            (0, _) => &mut self.emission,
+            (1, false) => &mut self.direct_diffuse,
+            (1, true) => &mut self.direct_specular,
+            (_, false) => &mut self.indirect_diffuse,
+            (_, true) => &mut self.indirect_specular,
+        };
+        *channel += light;
+    }
+
+    pub fn get(&self, aov: Aov) -> Color {
+        match aov {
+            Aov::Albedo => self.albedo,
+            Aov::Normal => self.normal,
+            Aov::Depth => Color::new(self.depth, self.depth, self.depth),
+            Aov::Emission => self.emission,
+            Aov::DirectDiffuse => self.direct_diffuse,
+            Aov::IndirectDiffuse => self.indirect_diffuse,
+            Aov::DirectSpecular => self.direct_specular,
+            Aov::IndirectSpecular => self.indirect_specular,
+        }
+    }
+}
+
+impl AddAssign for Aovs {
+    fn add_assign(&mut self, other: Self) {
+        self.albedo += other.albedo;
+        self.normal += other.normal;
+        self.depth += other.depth;
+        self.emission += other.emission;
+        self.direct_diffuse += other.direct_diffuse;
+        self.indirect_diffuse += other.indirect_diffuse;
+        self.direct_specular += other.direct_specular;
+        self.indirect_specular += other.indirect_specular;
+    }
+}
+
+/// Write linear colors as a Portable Float Map, which keeps their full range.
+/// `pixels` go row by row from the top, as in the PNG output.
+pub fn write_pfm<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[Color],
+) -> io::Result<()> {
+    // A negative scale marks the data as little endian
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    // Rows are stored from the bottom
+    for row in pixels.chunks(width).rev() {
+        for pixel in row {
+            for &component in &[*pixel.x(), *pixel.y(), *pixel.z()] {
+                #[allow(clippy::unnecessary_cast)]
+                writer.write_all(&(component as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn pfm_rows_start_at_the_bottom() {
+    let pixels = [Color::new(1.0, 2.0, 3.0), Color::new(0.5, 0.0, -1.0)];
+    let mut file = Vec::new();
+    write_pfm(&mut file, 1, 2, &pixels).unwrap();
+
+    let header = b"PF\n1 2\n-1.0\n";
+    assert_eq!(&file[..header.len()], header);
+    let floats: Vec<f32> = file[header.len()..]
+        .chunks(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    assert_eq!(floats, vec![0.5, 0.0, -1.0, 1.0, 2.0, 3.0]);
+}
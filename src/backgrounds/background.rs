@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use crate::lights::LightSample;
+use crate::ray::Ray;
+use crate::vector::{Color, Vector3D, N};
+
+pub type SharedBackground = Arc<dyn Background + Sync + Send>;
+
+/// What rays that escape the scene see
+pub trait Background {
+    /// Light arriving along a ray that escapes the scene
+    fn radiance(&self, ray: &Ray) -> Color;
+
+    /// Pick a direction to sample the background as a light from. `None` if
+    /// it isn't worth sampling, and is only found by rays escaping.
+    fn sample_li(&self) -> Option<LightSample> {
+        None
+    }
+
+    /// Density over solid angle of `sample_li` picking `direction`
+    fn pdf_li(&self, _direction: &Vector3D) -> N {
+        0.0
+    }
+}
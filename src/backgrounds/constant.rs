@@ -0,0 +1,27 @@
+use super::Background;
+use crate::ray::Ray;
+use crate::vector::Color;
+
+/// The same color in every direction
+pub struct Constant(Color);
+
+impl Constant {
+    pub fn new(color: Color) -> Self {
+        Self(color)
+    }
+}
+
+impl Background for Constant {
+    fn radiance(&self, _: &Ray) -> Color {
+        self.0
+    }
+}
+
+/// No light at all, for closed rooms and scenes lit only by their lights
+pub struct Black;
+
+impl Background for Black {
+    fn radiance(&self, _: &Ray) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+}
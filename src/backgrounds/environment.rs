@@ -0,0 +1,137 @@
+use super::Background;
+use crate::distribution::Distribution2D;
+use crate::hdr::HdrImage;
+use crate::lights::LightSample;
+use crate::ray::Ray;
+use crate::utils::PI;
+use crate::vector::{Color, Vector3D, N};
+
+/// Light arriving from every direction, looked up in a lat-long image. Rows go
+/// from straight up to straight down, and columns once around the y axis.
+pub struct EnvironmentLight {
+    image: HdrImage,
+    distribution: Distribution2D,
+    // Rotation about the y axis, as its sine and cosine
+    sin_rotation: N,
+    cos_rotation: N,
+    intensity: N,
+}
+
+impl EnvironmentLight {
+    /// `rotation` turns the image about the y axis in degrees, and `intensity`
+    /// scales its values
+    pub fn new(image: HdrImage, rotation: N, intensity: N) -> Self {
+        // Sample pixels by brightness, weighted by how much of the sphere they
+        // cover, which shrinks towards the poles
+        let mut func = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            let sin_theta = (PI * (y as N + 0.5) / image.height as N).sin();
+            for x in 0..image.width {
+                func.push(image.get(x, y).luminance().max(0.0) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(&func, image.width, image.height);
+
+        let rotation = rotation.to_radians();
+        Self {
+            image,
+            distribution,
+            sin_rotation: rotation.sin(),
+            cos_rotation: rotation.cos(),
+            intensity,
+        }
+    }
+
+    // Image coordinates of a world space direction
+    fn direction_to_uv(&self, direction: &Vector3D) -> (N, N) {
+        let d = direction.unit();
+        let x = self.cos_rotation * d.x() - self.sin_rotation * d.z();
+        let z = self.sin_rotation * d.x() + self.cos_rotation * d.z();
+        let phi = z.atan2(x);
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+        // Rather than `acos`, which loses precision near the poles
+        let theta = (x * x + z * z).sqrt().atan2(*d.y());
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    // World space direction of image coordinates, and the sine of its angle from
+    // the y axis
+    fn uv_to_direction(&self, u: N, v: N) -> (Vector3D, N) {
+        let (phi, theta) = (u * 2.0 * PI, v * PI);
+        let sin_theta = theta.sin();
+        let (x, z) = (sin_theta * phi.cos(), sin_theta * phi.sin());
+        (
+            Vector3D::new(
+                self.cos_rotation * x + self.sin_rotation * z,
+                theta.cos(),
+                -self.sin_rotation * x + self.cos_rotation * z,
+            ),
+            sin_theta,
+        )
+    }
+
+    fn lookup(&self, u: N, v: N) -> Color {
+        let x = ((u * self.image.width as N) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as N) as usize).min(self.image.height - 1);
+        self.image.get(x, y) * self.intensity
+    }
+}
+
+impl Background for EnvironmentLight {
+    fn radiance(&self, ray: &Ray) -> Color {
+        let (u, v) = self.direction_to_uv(ray.direction());
+        self.lookup(u, v)
+    }
+
+    fn sample_li(&self) -> Option<LightSample> {
+        let ((u, v), pdf) = self.distribution.sample();
+        let (wi, sin_theta) = self.uv_to_direction(u, v);
+        if pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.lookup(u, v),
+            pdf: pdf / (2.0 * PI * PI * sin_theta),
+            distance: N::MAX,
+        })
+    }
+
+    fn pdf_li(&self, direction: &Vector3D) -> N {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        // The image spans 2π by π radians
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[test]
+fn samples_follow_brightness() {
+    // A dim sky with one bright pixel above the horizon
+    let (width, height) = (16, 8);
+    let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+    pixels[2 * width + 5] = Color::new(50.0, 40.0, 30.0);
+    let image = HdrImage {
+        width,
+        height,
+        pixels,
+    };
+    let environment = EnvironmentLight::new(image, 30.0, 2.0);
+
+    let mut bright = 0;
+    let samples = 10_000;
+    for _ in 0..samples {
+        let sample = environment.sample_li().unwrap();
+        let ray = Ray::new(Vector3D::new(0.0, 0.0, 0.0), sample.wi, 0.0);
+        assert_eq!(sample.radiance, environment.radiance(&ray));
+        let pdf = environment.pdf_li(&sample.wi);
+        assert!((pdf - sample.pdf).abs() <= 1e-3 * pdf);
+        if sample.radiance.x() > &1.0 {
+            bright += 1;
+        }
+    }
+    assert!(bright > samples / 2);
+}
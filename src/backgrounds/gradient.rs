@@ -0,0 +1,28 @@
+use super::Background;
+use crate::ray::Ray;
+use crate::vector::Color;
+
+/// Blends from one color straight down to another straight up
+pub struct Gradient {
+    bottom: Color,
+    top: Color,
+}
+
+impl Gradient {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self::new(Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for Gradient {
+    fn radiance(&self, ray: &Ray) -> Color {
+        let t = 0.5 * (ray.direction().unit().y() + 1.0);
+        self.bottom * (1.0 - t) + self.top * t
+    }
+}
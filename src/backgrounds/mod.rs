@@ -0,0 +1,11 @@
+mod background;
+mod constant;
+mod environment;
+mod gradient;
+mod sky;
+
+pub use background::*;
+pub use constant::*;
+pub use environment::*;
+pub use gradient::*;
+pub use sky::*;
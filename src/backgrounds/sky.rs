@@ -0,0 +1,244 @@
+use super::Background;
+use crate::lights::LightSample;
+use crate::ray::Ray;
+use crate::utils::{random_in_cone, PI};
+use crate::vector::{Color, Vector3D, N};
+
+// The model gives luminance in kcd/m², which this brings to around 1 for a
+// clear daytime sky
+const SCALE: N = 0.05;
+
+// Angular diameter of the sun, in degrees
+const SUN_DIAMETER: N = 0.53;
+
+// Luminance of the sun before the atmosphere, in kcd/m²
+const SUN_LUMINANCE: N = 1.6e6;
+
+// Perez luminance distribution, relative to the zenith
+#[derive(Clone, Copy)]
+struct Perez([N; 5]);
+
+impl Perez {
+    fn eval(&self, cos_theta: N, gamma: N) -> N {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta.max(1e-3)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Daylight from the Preetham sky model, with the sun as a disk in it. The
+/// sun is sampled as a light, the rest is smooth enough to be found by rays
+/// escaping. Below the horizon is a diffuse ground lit by both.
+pub struct Sky {
+    // Unit vector pointing towards the sun
+    to_sun: Vector3D,
+    sun_theta: N,
+    sun_radiance: Color,
+    one_minus_cos_sun: N,
+    sin_sq_sun: N,
+    // Zenith value and distribution of luminance and the two chromaticities
+    zenith: [N; 3],
+    perez: [Perez; 3],
+    ground: Color,
+}
+
+impl Sky {
+    /// `turbidity` is the haziness of the air, from 2 for a clear sky to 10
+    /// for a hazy one
+    pub fn new(to_sun: Vector3D, turbidity: N, ground_albedo: Color) -> Self {
+        let t = turbidity.clamp(1.7, 10.0);
+        let to_sun = to_sun.unit();
+        // Keep the sun just above the horizon, where the model is defined
+        let sun_theta = to_sun.y().max(0.01).acos();
+
+        let perez = [
+            Perez([
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ]),
+            Perez([
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ]),
+            Perez([
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ]),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_theta);
+        let (s, s2, s3) = (sun_theta, sun_theta * sun_theta, sun_theta.powi(3));
+        let zenith = [
+            (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192,
+            t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s)
+                + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394)
+                + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886),
+            t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s)
+                + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516)
+                + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688),
+        ];
+
+        let half_angle = SUN_DIAMETER.to_radians() / 2.0;
+        let sin_half = (half_angle / 2.0).sin();
+        let mut sky = Self {
+            to_sun,
+            sun_theta,
+            sun_radiance: sun_radiance(sun_theta, t) * SCALE,
+            one_minus_cos_sun: 2.0 * sin_half * sin_half,
+            sin_sq_sun: half_angle.sin().powi(2),
+            zenith,
+            perez,
+            ground: Color::new(0.0, 0.0, 0.0),
+        };
+
+        // The ground reflects the light arriving on it from the sky and sun
+        let solid_angle = 2.0 * PI * sky.one_minus_cos_sun;
+        let irradiance =
+            sky.sky_irradiance() + sky.sun_radiance * (solid_angle * to_sun.y().max(0.0));
+        sky.ground = ground_albedo * irradiance / PI;
+        sky
+    }
+
+    // Compares sines instead of cosines, which are too close to 1 to tell
+    // apart directions within the sun in single precision
+    fn in_sun(&self, direction: &Vector3D) -> bool {
+        direction.dot(&self.to_sun) > 0.0
+            && direction.cross(&self.to_sun).length_sq() < self.sin_sq_sun
+    }
+
+    // The sky without the sun, from a unit direction above the horizon
+    fn sky_radiance(&self, direction: &Vector3D) -> Color {
+        let cos_theta = *direction.y();
+        let gamma = direction.dot(&self.to_sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * self.perez[i].eval(cos_theta, gamma)
+                / self.perez[i].eval(1.0, self.sun_theta)
+        });
+        xyy_to_rgb(x, y, luminance) * SCALE
+    }
+
+    // Light arriving on an upward facing surface from the sky without the sun
+    fn sky_irradiance(&self) -> Color {
+        let (rows, columns) = (32, 64);
+        let mut irradiance = Color::new(0.0, 0.0, 0.0);
+        for i in 0..rows {
+            // Uniform in cos theta, so each cell covers the same solid angle
+            let cos_theta = (i as N + 0.5) / rows as N;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..columns {
+                let phi = 2.0 * PI * (j as N + 0.5) / columns as N;
+                let direction =
+                    Vector3D::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                irradiance += self.sky_radiance(&direction) * cos_theta;
+            }
+        }
+        irradiance * (2.0 * PI / (rows * columns) as N)
+    }
+
+    // Sky and sun from `direction`, or the ground below the horizon
+    fn radiance_from(&self, direction: &Vector3D) -> Color {
+        let direction = direction.unit();
+        if *direction.y() <= 0.0 {
+            return self.ground;
+        }
+        let sky = self.sky_radiance(&direction);
+        if self.in_sun(&direction) {
+            sky + self.sun_radiance
+        } else {
+            sky
+        }
+    }
+}
+
+impl Background for Sky {
+    fn radiance(&self, ray: &Ray) -> Color {
+        self.radiance_from(ray.direction())
+    }
+
+    fn sample_li(&self) -> Option<LightSample> {
+        let wi = random_in_cone(&self.to_sun, self.one_minus_cos_sun);
+        let pdf = self.pdf_li(&wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.radiance_from(&wi),
+            pdf,
+            distance: N::MAX,
+        })
+    }
+
+    fn pdf_li(&self, direction: &Vector3D) -> N {
+        if self.in_sun(&direction.unit()) {
+            1.0 / (2.0 * PI * self.one_minus_cos_sun)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Sun seen through the atmosphere at `theta` from the zenith, dimmed and
+// reddened by scattering off air molecules and haze
+fn sun_radiance(theta: N, turbidity: N) -> Color {
+    // Relative length of the path through the atmosphere
+    let degrees = theta.to_degrees();
+    let air_mass = 1.0 / (theta.cos() + 0.15 * (93.885 - degrees).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    // Red, green and blue wavelengths in micrometers
+    let transmittance = |lambda: N| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+        rayleigh * aerosol
+    };
+    Color::new(
+        transmittance(0.68),
+        transmittance(0.55),
+        transmittance(0.44),
+    ) * SUN_LUMINANCE
+}
+
+// CIE xyY to linear sRGB
+fn xyy_to_rgb(x: N, y: N, luminance: N) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let big_x = x * luminance / y;
+    let big_z = (1.0 - x - y) * luminance / y;
+    Color::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+}
+
+#[test]
+fn sun_samples_match_pdf_and_outshine_sky() {
+    let to_sun = Vector3D::new(1.0, 1.0, -0.5);
+    let sky = Sky::new(to_sun, 3.0, Color::new(0.3, 0.3, 0.3));
+    let sky_nearby = sky.radiance_from(&Vector3D::new(1.0, 1.1, -0.5));
+
+    // Only samples right on the edge of the disk may be lost to rounding
+    let samples = 1000;
+    let mut found = 0;
+    for _ in 0..samples {
+        if let Some(sample) = sky.sample_li() {
+            assert_eq!(sample.pdf, sky.pdf_li(&sample.wi));
+            assert!(sample.radiance.luminance() > 100.0 * sky_nearby.luminance());
+            found += 1;
+        }
+    }
+    assert!(found > samples * 99 / 100);
+    assert_eq!(sky.pdf_li(&Vector3D::new(0.0, 1.0, 0.0)), 0.0);
+}
@@ -2,6 +2,7 @@ use crate::ray::Ray;
 use crate::utils;
 use crate::vector::{Point3D, Vector3D, N};
 
+#[derive(Clone)]
 pub struct Camera {
     horizontal: Vector3D,
     vertical: Vector3D,
@@ -10,11 +11,14 @@ pub struct Camera {
     lens_radius: N,
     u: Vector3D,
     v: Vector3D,
+    w: Vector3D,
+    focus_distance: N,
     time_0: N,
     time_1: N,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Point3D,
         lookat: Point3D,
@@ -50,6 +54,8 @@ impl Camera {
             lower_left_corner,
             u,
             v,
+            w,
+            focus_distance,
             lens_radius,
             time_0: time_0.unwrap_or(0.0),
             time_1: time_1.unwrap_or(0.0),
@@ -58,13 +64,49 @@ impl Camera {
 
     #[inline]
     pub fn get_ray(&self, s: N, t: N) -> Ray {
-        let rd = utils::random_in_unit_disk() * self.lens_radius;
-        let offset = self.u * *rd.x() + self.v * *rd.y();
-
+        let origin = self.sample_lens();
         Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
+            origin,
+            self.lower_left_corner + self.horizontal * s + self.vertical * t - origin,
             utils::random_range(self.time_0, self.time_1),
         )
     }
+
+    /// Random point on the lens, where camera rays start
+    #[inline]
+    pub fn sample_lens(&self) -> Point3D {
+        let rd = utils::random_in_unit_disk() * self.lens_radius;
+        self.origin + self.u * *rd.x() + self.v * *rd.y()
+    }
+
+    /// Area of the part of the plane in focus that `get_ray` maps [0, 1]² to
+    pub fn viewport_area(&self) -> N {
+        self.horizontal.length() * self.vertical.length()
+    }
+
+    pub fn focus_distance(&self) -> N {
+        self.focus_distance
+    }
+
+    /// The `s` and `t` of the camera ray leaving `lens_point` along
+    /// `direction`, along with the cosine of its angle with the view
+    /// direction, or `None` if it points away from the view
+    pub fn film_coordinates(
+        &self,
+        lens_point: &Point3D,
+        direction: &Vector3D,
+    ) -> Option<(N, N, N)> {
+        let direction = direction.unit();
+        let cos_theta = -direction.dot(&self.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let on_plane =
+            *lens_point + direction * (self.focus_distance / cos_theta) - self.lower_left_corner;
+        Some((
+            on_plane.dot(&self.horizontal) / self.horizontal.length_sq(),
+            on_plane.dot(&self.vertical) / self.vertical.length_sq(),
+            cos_theta,
+        ))
+    }
 }
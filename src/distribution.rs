@@ -0,0 +1,145 @@
+use crate::utils::random_n;
+use crate::vector::N;
+
+/// Piecewise-constant distribution over [0, 1) proportional to a function
+/// given at evenly spaced steps
+pub struct Distribution1D {
+    func: Vec<N>,
+    cdf: Vec<N>,
+    func_int: N,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<N>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as N;
+        }
+        let func_int = cdf[n];
+        if func_int == 0.0 {
+            // Nothing to prefer, so fall back to uniform
+            for (i, value) in cdf.iter_mut().enumerate().skip(1) {
+                *value = i as N / n as N;
+            }
+        } else {
+            for value in cdf.iter_mut().skip(1) {
+                *value /= func_int;
+            }
+        }
+
+        Self {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Integral of the function over [0, 1)
+    #[inline]
+    pub fn integral(&self) -> N {
+        self.func_int
+    }
+
+    /// Pick a point with probability proportional to the function. Returns the
+    /// point, its density and the index of the step it falls in.
+    pub fn sample(&self) -> (N, N, usize) {
+        let u = random_n();
+        // Last step whose start is at or below `u`
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, self.count()) - 1;
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let mut x = ((offset as N + du) / self.count() as N).min(1.0 - N::EPSILON);
+        // Rounding can carry `x` over into the next step, so step back into
+        // the one whose density is returned
+        while self.offset(x) > offset {
+            x = N::from_bits(x.to_bits() - 1);
+        }
+        (x, self.pdf(offset), offset)
+    }
+
+    /// Density of `sample` picking a point in step `offset`
+    #[inline]
+    pub fn pdf(&self, offset: usize) -> N {
+        if self.func_int == 0.0 {
+            return 1.0;
+        }
+        self.func[offset].abs() / self.func_int
+    }
+
+    /// Index of the step containing `x`
+    #[inline]
+    pub fn offset(&self, x: N) -> usize {
+        ((x * self.count() as N) as usize).min(self.count() - 1)
+    }
+}
+
+/// Piecewise-constant distribution over [0, 1)² proportional to a function
+/// given on a grid, stored row by row
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[N], width: usize, height: usize) -> Self {
+        let conditional: Vec<_> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Pick a point `(u, v)`, where `v` selects the row, and its density
+    pub fn sample(&self) -> ((N, N), N) {
+        let (v, pdf_v, row) = self.marginal.sample();
+        let (u, pdf_u, _) = self.conditional[row].sample();
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    /// Density of `sample` picking `(u, v)`
+    pub fn pdf(&self, u: N, v: N) -> N {
+        let row = self.marginal.offset(v);
+        let column = self.conditional[row].offset(u);
+        self.marginal.pdf(row) * self.conditional[row].pdf(column)
+    }
+}
+
+#[test]
+fn sampled_densities_match_pdf() {
+    let (width, height) = (5, 3);
+    let func: Vec<N> = (0..width * height).map(|i| (i % 4) as N).collect();
+    let distribution = Distribution2D::new(&func, width, height);
+
+    let mut counts = vec![0usize; width * height];
+    let samples = 300_000;
+    for _ in 0..samples {
+        let ((u, v), pdf) = distribution.sample();
+        assert!(pdf > 0.0);
+        assert_eq!(pdf, distribution.pdf(u, v));
+        counts[(v * height as N) as usize * width + (u * width as N) as usize] += 1;
+    }
+
+    // Each cell covers 1 / (width * height) of the domain
+    for (i, &count) in counts.iter().enumerate() {
+        let expected = distribution.pdf(
+            ((i % width) as N + 0.5) / width as N,
+            ((i / width) as N + 0.5) / height as N,
+        ) / (width * height) as N;
+        assert!((count as N / samples as N - expected).abs() < 0.01);
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::vector::{Color, N};
+
+/// Image that any thread can add light to, for integrators whose paths land
+/// on other pixels than the camera ray they were traced for. Pixels are laid
+/// out like the rendered image, top row first.
+pub struct Film {
+    width: usize,
+    height: usize,
+    // Bits of each component as an f64, to be updated atomically
+    pixels: Vec<[AtomicU64; 3]>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        let zero = || AtomicU64::new(0.0f64.to_bits());
+        Self {
+            width,
+            height,
+            pixels: (0..width * height)
+                .map(|_| [zero(), zero(), zero()])
+                .collect(),
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn add(&self, index: usize, color: Color) {
+        let pixel = &self.pixels[index];
+        for (component, &value) in pixel.iter().zip(&[*color.x(), *color.y(), *color.z()]) {
+            let mut current = component.load(Ordering::Relaxed);
+            loop {
+                #[allow(clippy::unnecessary_cast)]
+                let sum = f64::from_bits(current) + value as f64;
+                match component.compare_exchange_weak(
+                    current,
+                    sum.to_bits(),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(actual) => current = actual,
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Color {
+        let [r, g, b] = &self.pixels[index];
+        #[allow(clippy::unnecessary_cast)]
+        let component = |c: &AtomicU64| f64::from_bits(c.load(Ordering::Relaxed)) as N;
+        Color::new(component(r), component(g), component(b))
+    }
+}
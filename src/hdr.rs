@@ -0,0 +1,298 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::vector::{Color, N};
+
+/// Linear color image with rows from top to bottom, read from high dynamic
+/// range files or decoded from 8-bit ones
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+// Longest side of an image read, so that a corrupt resolution can't ask for
+// more memory than any real image needs
+const MAX_SIDE: usize = 1 << 16;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl HdrImage {
+    /// Read an image by its extension: a `.png` or `.jpg`, taken to be sRGB
+    /// encoded like the color textures of glTF and MTL materials, or otherwise
+    /// a Radiance `.hdr` file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => Self::read_png(reader),
+            Some("jpg") | Some("jpeg") => Self::read_jpeg(reader),
+            _ => Self::read(reader),
+        }
+    }
+
+    /// Read a PNG, expanded to 8 bits per channel
+    pub fn read_png<R: Read>(reader: R) -> io::Result<Self> {
+        let (info, mut reader) = png::Decoder::new(reader)
+            .read_info()
+            .map_err(|error| invalid(&error.to_string()))?;
+        let mut buffer = vec![0; info.buffer_size()];
+        reader
+            .next_frame(&mut buffer)
+            .map_err(|error| invalid(&error.to_string()))?;
+        let channels = match reader.output_color_type() {
+            (_, png::BitDepth::Sixteen) => return Err(invalid("unsupported bit depth")),
+            (png::ColorType::Grayscale, _) => 1,
+            (png::ColorType::GrayscaleAlpha, _) => 2,
+            (png::ColorType::RGB, _) => 3,
+            (png::ColorType::RGBA, _) => 4,
+            (png::ColorType::Indexed, _) => return Err(invalid("unexpanded palette")),
+        };
+        Self::from_srgb8(info.width as usize, info.height as usize, channels, &buffer)
+    }
+
+    /// Read a baseline or progressive JPEG
+    pub fn read_jpeg<R: Read>(reader: R) -> io::Result<Self> {
+        let mut decoder = jpeg_decoder::Decoder::new(reader);
+        let buffer = decoder
+            .decode()
+            .map_err(|error| invalid(&error.to_string()))?;
+        let info = decoder
+            .info()
+            .ok_or_else(|| invalid("missing JPEG header"))?;
+        let channels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 => 1,
+            jpeg_decoder::PixelFormat::RGB24 => 3,
+            _ => return Err(invalid("unsupported pixel format")),
+        };
+        Self::from_srgb8(info.width as usize, info.height as usize, channels, &buffer)
+    }
+
+    // Interleaved 8-bit gray or RGB samples, each followed by any alpha
+    fn from_srgb8(
+        width: usize,
+        height: usize,
+        channels: usize,
+        samples: &[u8],
+    ) -> io::Result<Self> {
+        if width == 0 || height == 0 || samples.len() < width * height * channels {
+            return Err(invalid("bad image size"));
+        }
+        let linear = |sample: u8| {
+            let c = sample as N / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let pixels = samples
+            .chunks_exact(channels)
+            .take(width * height)
+            .map(|pixel| {
+                if channels < 3 {
+                    let gray = linear(pixel[0]);
+                    Color::new(gray, gray, gray)
+                } else {
+                    Color::new(linear(pixel[0]), linear(pixel[1]), linear(pixel[2]))
+                }
+            })
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Read a Radiance `.hdr` (RGBE) file
+    pub fn read<R: BufRead>(mut reader: R) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid("not a Radiance HDR file"));
+        }
+
+        // Header variables up to an empty line, then the resolution
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid("unexpected end of header"));
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(invalid("unsupported pixel format"));
+                }
+            }
+        }
+        line.clear();
+        reader.read_line(&mut line)?;
+        let (width, height): (usize, usize) = match line.split_whitespace().collect::<Vec<_>>()[..]
+        {
+            ["-Y", height, "+X", width] => (
+                width.parse().map_err(|_| invalid("bad width"))?,
+                height.parse().map_err(|_| invalid("bad height"))?,
+            ),
+            _ => return Err(invalid("unsupported image orientation")),
+        };
+        // Scanlines start with a pixel, so can't be empty
+        if !(1..=MAX_SIDE).contains(&width) || !(1..=MAX_SIDE).contains(&height) {
+            return Err(invalid("bad image size"));
+        }
+
+        // Grown as scanlines arrive, so a file claiming more than it holds
+        // runs out first
+        let mut pixels = Vec::new();
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            read_scanline(&mut reader, &mut scanline).map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => invalid("truncated image"),
+                _ => error,
+            })?;
+            pixels.extend(scanline.iter().map(rgbe_to_color));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+fn rgbe_to_color(rgbe: &[u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let scale = (2.0 as N).powi(rgbe[3] as i32 - (128 + 8));
+    Color::new(
+        rgbe[0] as N * scale,
+        rgbe[1] as N * scale,
+        rgbe[2] as N * scale,
+    )
+}
+
+// Scanlines are either flat or run-length encoded one component at a time,
+// which is flagged by a first pixel of 2, 2 and the width
+fn read_scanline<R: Read>(reader: &mut R, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut first = [0u8; 4];
+    reader.read_exact(&mut first)?;
+    let encoded = (8..0x8000).contains(&width)
+        && first[0] == 2
+        && first[1] == 2
+        && ((first[2] as usize) << 8 | first[3] as usize) == width;
+    if !encoded {
+        scanline[0] = first;
+        for pixel in scanline.iter_mut().skip(1) {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    let mut byte = [0u8; 1];
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            reader.read_exact(&mut byte)?;
+            let count = byte[0] as usize;
+            if count > 128 {
+                // A run of one value
+                let count = count - 128;
+                if x + count > width {
+                    return Err(invalid("run past end of scanline"));
+                }
+                reader.read_exact(&mut byte)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[component] = byte[0];
+                }
+                x += count;
+            } else {
+                // `count` literal values
+                if count == 0 || x + count > width {
+                    return Err(invalid("bad scanline length"));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    reader.read_exact(&mut byte)?;
+                    pixel[component] = byte[0];
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn reads_flat_and_run_length_encoded_scanlines() {
+    let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+    // Run-length encoded: red is a run of 8, green is 8 literals, blue is two
+    // runs of 4, and the exponent is a run of 8
+    file.extend_from_slice(&[2, 2, 0, 8]);
+    file.extend_from_slice(&[128 + 8, 128]);
+    file.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+    file.extend_from_slice(&[128 + 4, 64, 128 + 4, 0]);
+    file.extend_from_slice(&[128 + 8, 129]);
+    // Flat
+    for x in 0..8u8 {
+        file.extend_from_slice(&[x * 16, 128, 0, 128]);
+    }
+
+    let image = HdrImage::read(&file[..]).unwrap();
+    assert_eq!((image.width, image.height), (8, 2));
+    assert_eq!(image.get(0, 0), Color::new(1.0, 0.0, 0.5));
+    assert_eq!(image.get(7, 0), Color::new(1.0, 0.875, 0.0));
+    assert_eq!(image.get(3, 1), Color::new(0.1875, 0.5, 0.0));
+}
+
+#[test]
+fn rejects_empty_and_oversized_images() {
+    // The last two claim far more pixels than the file holds
+    for resolution in [
+        "-Y 0 +X 8",
+        "-Y 2 +X 0",
+        "-Y 18446744073709551615 +X 2",
+        "-Y 1000000 +X 1000000",
+        "-Y 60000 +X 60000",
+    ] {
+        let file = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution);
+        let error = HdrImage::read(file.as_bytes()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn decodes_srgb_png() {
+    // Two pixels, a middle gray and an opaque white
+    let mut file = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut file, 2, 1);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[188, 188, 188, 255, 255, 255, 255, 255])
+            .unwrap();
+    }
+
+    let image = HdrImage::read_png(&file[..]).unwrap();
+    assert_eq!((image.width, image.height), (2, 1));
+    assert!((*image.get(0, 0).x() - 0.5).abs() < 0.01);
+    assert!((image.get(1, 0) - Color::new(1.0, 1.0, 1.0)).length() < 1e-6);
+}
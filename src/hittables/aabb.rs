@@ -1,7 +1,10 @@
 use super::HitRecord;
+#[cfg(not(feature = "simd"))]
+use crate::packet::{lanes, RayPacket, PACKET_SIZE};
 use crate::ray::Ray;
 use crate::vector::{Point3D, N};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Default)]
 pub struct AABB {
     min: Point3D,
@@ -38,40 +41,63 @@ impl AABB {
     }
 
     #[inline]
-    pub fn hit(&self, ray: &Ray, t_min: N, t_max: N, _: &mut HitRecord) -> bool {
+    pub fn hit(&self, ray: &Ray, mut t_min: N, mut t_max: N, _: &mut HitRecord) -> bool {
         let dims = [
             (
                 self.min.x(),
                 self.max.x(),
                 ray.origin().x(),
-                ray.direction().x(),
+                ray.inv_direction().x(),
             ),
             (
                 self.min.y(),
                 self.max.y(),
                 ray.origin().y(),
-                ray.direction().y(),
+                ray.inv_direction().y(),
             ),
             (
                 self.min.z(),
                 self.max.z(),
                 ray.origin().z(),
-                ray.direction().z(),
+                ray.inv_direction().z(),
             ),
         ];
-        for (min, max, origin, dir) in &dims {
-            let inv_direction = 1.0 / *dir;
-            let mut t0 = (*min - *origin) * inv_direction;
-            let mut t1 = (*max - *origin) * inv_direction;
-            if inv_direction < 0.0 {
+        for (min, max, origin, inv_direction) in &dims {
+            let mut t0 = (*min - *origin) * *inv_direction;
+            let mut t1 = (*max - *origin) * *inv_direction;
+            if **inv_direction < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
             }
-            let new_min = if t0 > t_min { t0 } else { t_min };
-            let new_max = if t1 < t_max { t1 } else { t_max };
-            if new_max <= new_min {
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
                 return false;
             }
         }
         true
     }
+
+    /// Slab test for every active ray of a packet, returning the mask of lanes hit
+    #[cfg(not(feature = "simd"))]
+    pub fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &[N; PACKET_SIZE],
+    ) -> u64 {
+        let mut rec = HitRecord::default();
+        let mut hits = 0;
+        for lane in lanes(active) {
+            if self.hit(&packet.rays[lane], t_min, t_max[lane], &mut rec) {
+                hits |= 1 << lane;
+            }
+        }
+        hits
+    }
+
+    /// Center of the box, used as the split key when building hierarchies
+    pub fn centroid(&self) -> Point3D {
+        (self.min + self.max) * 0.5
+    }
 }
@@ -2,10 +2,10 @@ use std::cmp::Ordering;
 use std::sync::Arc;
 
 use super::{HitRecord, Hittable, SharedHittableTraitObj, AABB};
-use crate::materials::SharedMaterial;
+use crate::packet::{RayPacket, PACKET_SIZE};
 use crate::ray::Ray;
 use crate::utils::random_int_range;
-use crate::vector::{Point3D, Vector3D, N};
+use crate::vector::N;
 
 pub struct BvhNode {
     bounding_box: AABB,
@@ -105,7 +105,68 @@ impl Hittable for BvhNode {
         hit_left || hit_right
     }
 
-    fn bounding_box(&self, time0: N, time1: N, output_box: &mut AABB) -> bool {
+    fn hit_counted(
+        &self,
+        ray: &Ray,
+        t_min: N,
+        t_max: N,
+        rec: &mut HitRecord,
+        box_tests: &mut u64,
+    ) -> bool {
+        *box_tests += 1;
+        if !self.bounding_box.hit(ray, t_min, t_max, rec) {
+            return false;
+        }
+
+        let hit_left = self.left.hit_counted(ray, t_min, t_max, rec, box_tests);
+        let t_max = if hit_left { rec.t } else { t_max };
+        let hit_right = self.right.hit_counted(ray, t_min, t_max, rec, box_tests);
+
+        hit_left || hit_right
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &mut [N; PACKET_SIZE],
+        recs: &mut [HitRecord],
+    ) -> u64 {
+        let active = self.bounding_box.hit_packet(packet, active, t_min, t_max);
+        if active == 0 {
+            return 0;
+        }
+
+        // `t_max` is shrunk in place, so the right subtree only finds closer hits
+        let hit_left = self.left.hit_packet(packet, active, t_min, t_max, recs);
+        let hit_right = self.right.hit_packet(packet, active, t_min, t_max, recs);
+
+        hit_left | hit_right
+    }
+
+    fn occluded_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &[N; PACKET_SIZE],
+    ) -> u64 {
+        let active = self.bounding_box.hit_packet(packet, active, t_min, t_max);
+        if active == 0 {
+            return 0;
+        }
+
+        let occluded_left = self.left.occluded_packet(packet, active, t_min, t_max);
+        let remaining = active & !occluded_left;
+        if remaining == 0 {
+            return occluded_left;
+        }
+
+        occluded_left | self.right.occluded_packet(packet, remaining, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _: N, _: N, output_box: &mut AABB) -> bool {
         *output_box = self.bounding_box.clone();
         true
     }
@@ -0,0 +1,460 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::simd::{self, Bounds4, Triangle4, LANES};
+use super::{HitRecord, Hittable, SharedHittableTraitObj, Triangle, AABB};
+use crate::packet::{lanes, RayPacket, PACKET_SIZE};
+use crate::ray::Ray;
+use crate::vector::{Point3D, N};
+
+// Maximum number of primitives stored directly in a leaf
+const LEAF_SIZE: usize = 4;
+// Deep enough for any tree built from a 4-way median split
+const STACK_SIZE: usize = 64;
+
+#[derive(Clone, Copy)]
+enum Bvh4Child {
+    Empty,
+    Node(usize),
+    Leaf(usize),
+}
+
+// A primitive paired with its bounding box while building
+type BuildItem = (SharedHittableTraitObj, AABB);
+
+struct Bvh4Node {
+    bounds: Bounds4,
+    children: [Bvh4Child; LANES],
+}
+
+struct Bvh4Leaf {
+    // Indices into `Bvh4::packets`
+    packets: Range<usize>,
+    // Indices into `Bvh4::primitives`
+    primitives: Range<usize>,
+}
+
+/// A four-wide bounding volume hierarchy. Each node tests all four of its
+/// children's boxes at once, and triangles in a leaf are intersected in batches.
+pub struct Bvh4 {
+    root: Bvh4Child,
+    bounding_box: AABB,
+    nodes: Vec<Bvh4Node>,
+    leaves: Vec<Bvh4Leaf>,
+    // Triangles are copied out of their trait objects so they can be packed,
+    // packet `i` covers `triangles[i * LANES..]`
+    packets: Vec<Triangle4>,
+    triangles: Vec<Triangle>,
+    // IDs of the objects the triangles were copied from, to report on hits
+    triangle_ids: Vec<usize>,
+    primitives: Vec<SharedHittableTraitObj>,
+}
+
+impl Bvh4 {
+    pub fn new(src_objs: Vec<SharedHittableTraitObj>, time0: N, time1: N) -> Self {
+        let mut items: Vec<BuildItem> = src_objs
+            .into_iter()
+            .map(|obj| {
+                let mut output_box = AABB::default();
+                if !obj.bounding_box(time0, time1, &mut output_box) {
+                    panic!("no bounding box in bvh constructor");
+                }
+                (obj, output_box)
+            })
+            .collect();
+
+        let bounding_box = Self::surrounding(&items);
+        let mut bvh = Self {
+            root: Bvh4Child::Empty,
+            bounding_box,
+            nodes: Vec::new(),
+            leaves: Vec::new(),
+            packets: Vec::new(),
+            triangles: Vec::new(),
+            triangle_ids: Vec::new(),
+            primitives: Vec::new(),
+        };
+        bvh.root = bvh.build(&mut items);
+        bvh
+    }
+
+    fn surrounding(items: &[BuildItem]) -> AABB {
+        items.iter().skip(1).fold(
+            items.first().map(|i| i.1.clone()).unwrap_or_default(),
+            |acc, i| acc.surrounding_box(&i.1),
+        )
+    }
+
+    // Sort along the axis with the largest centroid spread and cut in half
+    fn split(items: &mut [BuildItem]) -> (&mut [BuildItem], &mut [BuildItem]) {
+        let first = items[0].1.centroid();
+        let (min, max) = items.iter().fold((first, first), |(min, max), item| {
+            let c = item.1.centroid();
+            (
+                Point3D::new(
+                    min.x().min(*c.x()),
+                    min.y().min(*c.y()),
+                    min.z().min(*c.z()),
+                ),
+                Point3D::new(
+                    max.x().max(*c.x()),
+                    max.y().max(*c.y()),
+                    max.z().max(*c.z()),
+                ),
+            )
+        });
+        let extent = max - min;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            0
+        } else if extent.y() >= extent.z() {
+            1
+        } else {
+            2
+        };
+
+        items.sort_unstable_by(|a, b| {
+            let (ca, cb) = (a.1.centroid(), b.1.centroid());
+            let (da, db) = match axis {
+                0 => (ca.x(), cb.x()),
+                1 => (ca.y(), cb.y()),
+                _ => (ca.z(), cb.z()),
+            };
+            da.partial_cmp(db).unwrap()
+        });
+        let mid = items.len() / 2;
+        items.split_at_mut(mid)
+    }
+
+    fn build(&mut self, items: &mut [BuildItem]) -> Bvh4Child {
+        if items.len() <= LEAF_SIZE {
+            return self.build_leaf(items);
+        }
+
+        let (left, right) = Self::split(items);
+        let (a, b) = if left.len() > 1 {
+            Self::split(left)
+        } else {
+            left.split_at_mut(left.len())
+        };
+        let (c, d) = if right.len() > 1 {
+            Self::split(right)
+        } else {
+            right.split_at_mut(right.len())
+        };
+
+        let index = self.nodes.len();
+        self.nodes.push(Bvh4Node {
+            bounds: Bounds4::default(),
+            children: [Bvh4Child::Empty; LANES],
+        });
+
+        let mut bounds = Bounds4::default();
+        let mut children = [Bvh4Child::Empty; LANES];
+        for (lane, group) in [a, b, c, d].iter_mut().enumerate() {
+            if group.is_empty() {
+                continue;
+            }
+            let aabb = Self::surrounding(group);
+            bounds.min_x[lane] = *aabb.min().x();
+            bounds.min_y[lane] = *aabb.min().y();
+            bounds.min_z[lane] = *aabb.min().z();
+            bounds.max_x[lane] = *aabb.max().x();
+            bounds.max_y[lane] = *aabb.max().y();
+            bounds.max_z[lane] = *aabb.max().z();
+            bounds.valid |= 1 << lane;
+            children[lane] = self.build(group);
+        }
+
+        self.nodes[index] = Bvh4Node { bounds, children };
+        Bvh4Child::Node(index)
+    }
+
+    fn build_leaf(&mut self, items: &[BuildItem]) -> Bvh4Child {
+        let (triangles, ids): (Vec<Triangle>, Vec<usize>) = items
+            .iter()
+            .filter_map(|(obj, _)| {
+                let id = Arc::as_ptr(obj) as *const () as usize;
+                obj.as_triangle().map(|triangle| (triangle.clone(), id))
+            })
+            .unzip();
+
+        // Keep packets aligned with `triangles` so lane `i` of packet `p` is
+        // `triangles[p * LANES + i]`
+        let packets_start = self.packets.len();
+        for (chunk, ids) in triangles.chunks(LANES).zip(ids.chunks(LANES)) {
+            self.packets.push(Triangle4::new(chunk));
+            self.triangles.extend_from_slice(chunk);
+            self.triangles
+                .extend(std::iter::repeat_n(chunk[0].clone(), LANES - chunk.len()));
+            self.triangle_ids.extend_from_slice(ids);
+            self.triangle_ids
+                .extend(std::iter::repeat_n(ids[0], LANES - ids.len()));
+        }
+
+        let primitives_start = self.primitives.len();
+        self.primitives.extend(
+            items
+                .iter()
+                .filter(|(obj, _)| obj.as_triangle().is_none())
+                .map(|(obj, _)| obj.clone()),
+        );
+
+        self.leaves.push(Bvh4Leaf {
+            packets: packets_start..self.packets.len(),
+            primitives: primitives_start..self.primitives.len(),
+        });
+        Bvh4Child::Leaf(self.leaves.len() - 1)
+    }
+
+    fn hit_leaf(&self, index: usize, ray: &Ray, t_min: N, t_max: N, rec: &mut HitRecord) -> bool {
+        let leaf = &self.leaves[index];
+        let mut closest_so_far = t_max;
+        let mut hit_anything = false;
+
+        for packet in leaf.packets.clone() {
+            let (mut t, mut u, mut v) = ([0.0; LANES], [0.0; LANES], [0.0; LANES]);
+            let mask = simd::hit_triangle4(
+                &self.packets[packet],
+                ray,
+                t_min,
+                closest_so_far,
+                &mut t,
+                &mut u,
+                &mut v,
+            );
+            let mut closest_lane = None;
+            for (lane, &t) in t.iter().enumerate() {
+                if mask & (1 << lane) != 0 && t <= closest_so_far {
+                    closest_so_far = t;
+                    closest_lane = Some(lane);
+                }
+            }
+            if let Some(lane) = closest_lane {
+                self.triangles[packet * LANES + lane]
+                    .set_hit_record(ray, t[lane], u[lane], v[lane], rec);
+                rec.object_id = self.triangle_ids[packet * LANES + lane];
+                hit_anything = true;
+            }
+        }
+
+        for primitive in &self.primitives[leaf.primitives.clone()] {
+            if primitive.hit(ray, t_min, closest_so_far, rec) {
+                closest_so_far = rec.t;
+                hit_anything = true;
+            }
+        }
+
+        hit_anything
+    }
+
+    // Closest hit of a single ray, counting the boxes tested into `box_tests`
+    // if given
+    fn traverse(
+        &self,
+        ray: &Ray,
+        t_min: N,
+        t_max: N,
+        rec: &mut HitRecord,
+        mut box_tests: Option<&mut u64>,
+    ) -> bool {
+        let mut stack = [(Bvh4Child::Empty, 0.0); STACK_SIZE];
+        stack[0] = (self.root, t_min);
+        let mut stack_len = 1;
+
+        let mut closest_so_far = t_max;
+        let mut hit_anything = false;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (child, t_near) = stack[stack_len];
+            if t_near > closest_so_far {
+                continue;
+            }
+
+            match child {
+                Bvh4Child::Empty => {}
+                Bvh4Child::Leaf(index) => {
+                    if self.hit_leaf(index, ray, t_min, closest_so_far, rec) {
+                        closest_so_far = rec.t;
+                        hit_anything = true;
+                    }
+                }
+                Bvh4Child::Node(index) => {
+                    let node = &self.nodes[index];
+                    let mut t_near = [0.0; LANES];
+                    if let Some(box_tests) = box_tests.as_deref_mut() {
+                        *box_tests += node.bounds.valid.count_ones() as u64;
+                    }
+                    let mask =
+                        simd::hit_aabb4(&node.bounds, ray, t_min, closest_so_far, &mut t_near);
+
+                    // Push the farthest children first so the nearest is popped next
+                    let mut hits = [(Bvh4Child::Empty, 0.0); LANES];
+                    let mut hit_count = 0;
+                    for (lane, &t_near) in t_near.iter().enumerate() {
+                        if mask & (1 << lane) == 0 {
+                            continue;
+                        }
+                        let mut i = hit_count;
+                        while i > 0 && hits[i - 1].1 < t_near {
+                            hits[i] = hits[i - 1];
+                            i -= 1;
+                        }
+                        hits[i] = (node.children[lane], t_near);
+                        hit_count += 1;
+                    }
+                    stack[stack_len..stack_len + hit_count].copy_from_slice(&hits[..hit_count]);
+                    stack_len += hit_count;
+                }
+            }
+        }
+
+        hit_anything
+    }
+
+    // Walk the tree once for a whole packet. A child is visited with the subset
+    // of rays that hit its box, and `leaf` is called with that subset for every
+    // leaf reached. `leaf` returns lanes which need no further traversal. The
+    // mask of all such lanes is returned.
+    fn traverse_packet<F>(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &mut [N; PACKET_SIZE],
+        mut leaf: F,
+    ) -> u64
+    where
+        F: FnMut(usize, u64, &mut [N; PACKET_SIZE]) -> u64,
+    {
+        let mut stack = [(Bvh4Child::Empty, 0); STACK_SIZE];
+        stack[0] = (self.root, active);
+        let mut stack_len = 1;
+        let mut finished = 0;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (child, mask) = stack[stack_len];
+            let mask = mask & !finished;
+            if mask == 0 {
+                continue;
+            }
+
+            match child {
+                Bvh4Child::Empty => {}
+                Bvh4Child::Leaf(index) => finished |= leaf(index, mask, t_max),
+                Bvh4Child::Node(index) => {
+                    let node = &self.nodes[index];
+                    let mut child_masks = [0u64; LANES];
+                    let mut child_t_near = [N::MAX; LANES];
+                    for lane in lanes(mask) {
+                        let mut t_near = [0.0; LANES];
+                        let hit = simd::hit_aabb4(
+                            &node.bounds,
+                            &packet.rays[lane],
+                            t_min,
+                            t_max[lane],
+                            &mut t_near,
+                        );
+                        for child in 0..LANES {
+                            if hit & (1 << child) != 0 {
+                                child_masks[child] |= 1 << lane;
+                                child_t_near[child] = child_t_near[child].min(t_near[child]);
+                            }
+                        }
+                    }
+
+                    // Push the farthest children first so the nearest is popped next
+                    let mut hits = [(Bvh4Child::Empty, 0, 0.0); LANES];
+                    let mut hit_count = 0;
+                    for child in 0..LANES {
+                        if child_masks[child] == 0 {
+                            continue;
+                        }
+                        let mut i = hit_count;
+                        while i > 0 && hits[i - 1].2 < child_t_near[child] {
+                            hits[i] = hits[i - 1];
+                            i -= 1;
+                        }
+                        hits[i] = (
+                            node.children[child],
+                            child_masks[child],
+                            child_t_near[child],
+                        );
+                        hit_count += 1;
+                    }
+                    for &(child, mask, _) in &hits[..hit_count] {
+                        stack[stack_len] = (child, mask);
+                        stack_len += 1;
+                    }
+                }
+            }
+        }
+
+        finished
+    }
+}
+
+impl Hittable for Bvh4 {
+    fn hit(&self, ray: &Ray, t_min: N, t_max: N, rec: &mut HitRecord) -> bool {
+        self.traverse(ray, t_min, t_max, rec, None)
+    }
+
+    fn hit_counted(
+        &self,
+        ray: &Ray,
+        t_min: N,
+        t_max: N,
+        rec: &mut HitRecord,
+        box_tests: &mut u64,
+    ) -> bool {
+        self.traverse(ray, t_min, t_max, rec, Some(box_tests))
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &mut [N; PACKET_SIZE],
+        recs: &mut [HitRecord],
+    ) -> u64 {
+        let mut hits = 0;
+        self.traverse_packet(packet, active, t_min, t_max, |leaf, mask, t_max| {
+            for lane in lanes(mask) {
+                let ray = &packet.rays[lane];
+                if self.hit_leaf(leaf, ray, t_min, t_max[lane], &mut recs[lane]) {
+                    t_max[lane] = recs[lane].t;
+                    hits |= 1 << lane;
+                }
+            }
+            0
+        });
+        hits
+    }
+
+    fn occluded_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &[N; PACKET_SIZE],
+    ) -> u64 {
+        let mut rec = HitRecord::default();
+        let mut t_max = *t_max;
+        self.traverse_packet(packet, active, t_min, &mut t_max, |leaf, mask, t_max| {
+            let mut occluded = 0;
+            for lane in lanes(mask) {
+                if self.hit_leaf(leaf, &packet.rays[lane], t_min, t_max[lane], &mut rec) {
+                    occluded |= 1 << lane;
+                }
+            }
+            occluded
+        })
+    }
+
+    fn bounding_box(&self, _: N, _: N, output_box: &mut AABB) -> bool {
+        *output_box = self.bounding_box.clone();
+        true
+    }
+}
@@ -1,27 +1,50 @@
 use std::sync::Arc;
 
+#[cfg(feature = "simd")]
+use super::Triangle;
 use super::AABB;
 use crate::materials::{Lambert, SharedMaterial};
-use crate::ray::Ray;
-use crate::vector::{Color, Point3D, Vector3D, N};
+use crate::packet::{lanes, RayPacket, PACKET_SIZE};
+use crate::ray::{offset_ray_origin, Ray};
+use crate::utils::random_int_range;
+use crate::vector::{Color, Onb, Point3D, Vector3D, N};
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3D,
+    /// Bound on the absolute floating point error of each coordinate of `p`
+    pub p_error: Vector3D,
     pub normal: Vector3D,
     pub t: N,
+    /// Distance travelled along the ray to `p`, which is `t` for rays of unit
+    /// length
+    pub distance: N,
     pub front_face: bool,
     pub material: SharedMaterial,
+    /// Surface coordinates of `p`, each in [0, 1]
+    pub u: N,
+    pub v: N,
+    /// Unit direction along the surface in which `u` increases, or zero where
+    /// the surface has no such direction
+    pub tangent: Vector3D,
+    /// Identifies the primitive hit, for telling objects apart
+    pub object_id: usize,
 }
 
 impl Default for HitRecord {
     fn default() -> Self {
         Self {
             p: Point3D::default(),
+            p_error: Vector3D::default(),
             normal: Vector3D::default(),
             t: N::default(),
+            distance: N::default(),
             front_face: false,
             material: Arc::new(Lambert::new(Color::new(0.0, 0.0, 0.0))),
+            u: 0.0,
+            v: 0.0,
+            tangent: Vector3D::default(),
+            object_id: 0,
         }
     }
 }
@@ -35,11 +58,135 @@ impl HitRecord {
             -outward_normal
         };
     }
+
+    /// Local frame around the normal with x along the tangent, for materials
+    /// that depend on which way they're turned about the normal
+    pub fn shading_frame(&self) -> Onb {
+        Onb::with_tangent(&self.normal, &self.tangent)
+    }
+
+    /// Start a new ray at the hit point, offset so it doesn't hit the same surface
+    pub fn spawn_ray(&self, direction: Vector3D, time: N) -> Ray {
+        Ray::new(
+            offset_ray_origin(&self.p, &self.p_error, &self.normal, &direction),
+            direction,
+            time,
+        )
+    }
+}
+
+/// An ID for a primitive, unique for as long as it isn't moved
+#[inline]
+pub fn object_id<T>(object: &T) -> usize {
+    object as *const T as usize
+}
+
+/// Convert the density of uniformly sampling a surface of `area` to a density
+/// per unit solid angle, given where a ray along `direction` hit it
+#[inline]
+pub fn area_to_solid_angle(area: N, direction: &Vector3D, rec: &HitRecord) -> N {
+    let distance_sq = rec.t * rec.t * direction.length_sq();
+    let cosine = (direction.dot(&rec.normal) / direction.length()).abs();
+    if cosine == 0.0 {
+        return 0.0;
+    }
+    distance_sq / (cosine * area)
 }
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray, t_min: N, t_max: N, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: N, time1: N, output_box: &mut AABB) -> bool;
+
+    /// `hit`, also adding the number of ray-box tests it took to `box_tests`,
+    /// for measuring the cost of traversing acceleration structures
+    fn hit_counted(
+        &self,
+        ray: &Ray,
+        t_min: N,
+        t_max: N,
+        rec: &mut HitRecord,
+        _box_tests: &mut u64,
+    ) -> bool {
+        self.hit(ray, t_min, t_max, rec)
+    }
+
+    /// Lets acceleration structures pack triangles for batched intersection
+    #[cfg(feature = "simd")]
+    fn as_triangle(&self) -> Option<&Triangle> {
+        None
+    }
+
+    /// Whether the object gives off light, and should be sampled as a light
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Direction from `origin` towards a random point on the object, used to
+    /// sample it as a light
+    fn sample(&self, _origin: &Point3D, _time: N) -> Vector3D {
+        Vector3D::new(1.0, 0.0, 0.0)
+    }
+
+    /// Density per unit solid angle with which `sample` picks `direction`
+    fn pdf(&self, _origin: &Point3D, _direction: &Vector3D, _time: N) -> N {
+        0.0
+    }
+
+    /// Surface area, or 0 for objects `sample_surface` can't pick points on
+    fn area(&self) -> N {
+        0.0
+    }
+
+    /// Normal of an object that is flat, facing the same way all over, for
+    /// grouping lights by the way they face
+    fn flat_normal(&self) -> Option<Vector3D> {
+        None
+    }
+
+    /// Fill `rec` in for a random point on the surface, uniform over its area,
+    /// with the outward normal. Used to start paths from lights.
+    fn sample_surface(&self, _time: N, _rec: &mut HitRecord) -> bool {
+        false
+    }
+
+    /// Find the closest hit for each active ray of a packet, shrinking `t_max`
+    /// and filling `recs` for every lane that hits. Returns the mask of lanes hit.
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &mut [N; PACKET_SIZE],
+        recs: &mut [HitRecord],
+    ) -> u64 {
+        let mut hits = 0;
+        for lane in lanes(active) {
+            if self.hit(&packet.rays[lane], t_min, t_max[lane], &mut recs[lane]) {
+                t_max[lane] = recs[lane].t;
+                hits |= 1 << lane;
+            }
+        }
+        hits
+    }
+
+    /// Any-hit test for each active ray of a packet, as used for shadow rays.
+    /// Returns the mask of lanes that are blocked before their `t_max`.
+    fn occluded_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &[N; PACKET_SIZE],
+    ) -> u64 {
+        let mut rec = HitRecord::default();
+        let mut occluded = 0;
+        for lane in lanes(active) {
+            if self.hit(&packet.rays[lane], t_min, t_max[lane], &mut rec) {
+                occluded |= 1 << lane;
+            }
+        }
+        occluded
+    }
 }
 
 pub type SharedHittableTraitObj = Arc<dyn Hittable + Sync + Send>;
@@ -58,9 +205,22 @@ impl Hittables {
         self.0.push(item);
     }
 
+    #[allow(dead_code)]
     pub fn clear(&mut self) {
         self.0.clear();
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SharedHittableTraitObj> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl Hittable for Hittables {
@@ -80,6 +240,81 @@ impl Hittable for Hittables {
         hit_anything
     }
 
+    fn hit_counted(
+        &self,
+        ray: &Ray,
+        t_min: N,
+        t_max: N,
+        rec: &mut HitRecord,
+        box_tests: &mut u64,
+    ) -> bool {
+        let mut new_rec = HitRecord::default();
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+
+        for object in &self.0 {
+            if object.hit_counted(ray, t_min, closest_so_far, &mut new_rec, box_tests) {
+                hit_anything = true;
+                closest_so_far = new_rec.t;
+                *rec = new_rec.clone();
+            }
+        }
+
+        hit_anything
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        active: u64,
+        t_min: N,
+        t_max: &mut [N; PACKET_SIZE],
+        recs: &mut [HitRecord],
+    ) -> u64 {
+        let mut hits = 0;
+        for object in &self.0 {
+            hits |= object.hit_packet(packet, active, t_min, t_max, recs);
+        }
+        hits
+    }
+
+    fn occluded_packet(
+        &self,
+        packet: &RayPacket,
+        mut active: u64,
+        t_min: N,
+        t_max: &[N; PACKET_SIZE],
+    ) -> u64 {
+        let mut occluded = 0;
+        for object in &self.0 {
+            if active == 0 {
+                break;
+            }
+            let blocked = object.occluded_packet(packet, active, t_min, t_max);
+            occluded |= blocked;
+            active &= !blocked;
+        }
+        occluded
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.0.iter().any(|object| object.is_emissive())
+    }
+
+    // Pick one of the objects uniformly
+    fn sample(&self, origin: &Point3D, time: N) -> Vector3D {
+        self.0[random_int_range(0, self.0.len())].sample(origin, time)
+    }
+
+    fn pdf(&self, origin: &Point3D, direction: &Vector3D, time: N) -> N {
+        let sum: N = self
+            .0
+            .iter()
+            .map(|object| object.pdf(origin, direction, time))
+            .sum();
+        sum / self.0.len() as N
+    }
+
     fn bounding_box(&self, time0: N, time1: N, output_box: &mut AABB) -> bool {
         if self.0.is_empty() {
             return false;
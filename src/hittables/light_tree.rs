@@ -0,0 +1,438 @@
+use super::{HitRecord, Hittable, SharedHittableTraitObj, AABB};
+use crate::ray::Ray;
+use crate::utils::{random_n, PI};
+use crate::vector::{Point3D, Vector3D, N};
+
+// Buckets along each axis that splits are considered at
+const BUCKETS: usize = 12;
+// Below this depth lists are halved rather than split by cost, which keeps the
+// tree shallow enough for a fixed size stack
+const MAX_COST_DEPTH: usize = 32;
+// Deep enough for any tree halved from `MAX_COST_DEPTH` down
+const STACK_SIZE: usize = 64;
+
+/// Bounding volume hierarchy over the emissive objects, for picking one to
+/// sample light from. Each node bounds where its emitters are, how much light
+/// they give off and which ways they face, which gives an estimate of how much
+/// of it reaches a point. Walking down, the child expected to light the point
+/// more is picked more often, so points mostly sample the emitters near and
+/// facing them, however many there are.
+///
+/// Emissive objects give off light from both sides, so only the line their
+/// normals lie along matters.
+pub struct LightTree {
+    emitters: Vec<SharedHittableTraitObj>,
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    bounds: LightBounds,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    // Index into `emitters`
+    Leaf(usize),
+    // Index of the second child, the first following the node itself
+    Interior(usize),
+}
+
+impl LightTree {
+    pub fn new(emitters: Vec<SharedHittableTraitObj>, time0: N, time1: N) -> Self {
+        let mut bounds: Vec<(usize, LightBounds)> = emitters
+            .iter()
+            .enumerate()
+            .map(|(i, emitter)| (i, LightBounds::new(emitter.as_ref(), time0, time1)))
+            .collect();
+        let mut tree = Self {
+            emitters,
+            nodes: Vec::new(),
+        };
+        if !bounds.is_empty() {
+            tree.build(&mut bounds, 0);
+        }
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emitters.is_empty()
+    }
+
+    // Add the nodes for `lights` depth first, splitting them where the
+    // surface area orientation heuristic is lowest
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], depth: usize) -> LightBounds {
+        if lights.len() == 1 {
+            let (index, bounds) = lights[0].clone();
+            self.nodes.push(Node {
+                bounds: bounds.clone(),
+                kind: NodeKind::Leaf(index),
+            });
+            return bounds;
+        }
+
+        let mut centroids = AABB::new(lights[0].1.centroid(), lights[0].1.centroid());
+        for (_, bounds) in lights.iter() {
+            centroids = centroids.surrounding_box(&AABB::new(bounds.centroid(), bounds.centroid()));
+        }
+        let total = lights[1..]
+            .iter()
+            .fold(lights[0].1.clone(), |total, (_, bounds)| {
+                total.union(bounds)
+            });
+
+        // Cheapest bucket boundary over all axes, or halving the list if the
+        // centroids all coincide or the tree is getting deep
+        let axes = if depth < MAX_COST_DEPTH { 0..3 } else { 0..0 };
+        let mut best: Option<(N, usize, N)> = None;
+        for axis in axes {
+            let (min, max) = (
+                component(&centroids.min(), axis),
+                component(&centroids.max(), axis),
+            );
+            if max <= min {
+                continue;
+            }
+            let bucket = |bounds: &LightBounds| {
+                let offset = (component(&bounds.centroid(), axis) - min) / (max - min);
+                ((offset * BUCKETS as N) as usize).min(BUCKETS - 1)
+            };
+            let mut buckets: Vec<Option<LightBounds>> = vec![None; BUCKETS];
+            for (_, bounds) in lights.iter() {
+                let b = &mut buckets[bucket(bounds)];
+                *b = Some(match b.take() {
+                    Some(existing) => existing.union(bounds),
+                    None => bounds.clone(),
+                });
+            }
+            for split in 1..BUCKETS {
+                let below = union_all(&buckets[..split]);
+                let above = union_all(&buckets[split..]);
+                let cost = match (below, above) {
+                    (Some(below), Some(above)) => {
+                        below.cost(&total.bounds, axis) + above.cost(&total.bounds, axis)
+                    }
+                    _ => continue,
+                };
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    let boundary = min + (max - min) * split as N / BUCKETS as N;
+                    best = Some((cost, axis, boundary));
+                }
+            }
+        }
+
+        let mid = match best {
+            Some((_, axis, boundary)) => {
+                let mut mid = 0;
+                for i in 0..lights.len() {
+                    if component(&lights[i].1.centroid(), axis) < boundary {
+                        lights.swap(i, mid);
+                        mid += 1;
+                    }
+                }
+                mid
+            }
+            None => lights.len() / 2,
+        };
+
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: total.clone(),
+            kind: NodeKind::Interior(0),
+        });
+        let (left, right) = lights.split_at_mut(mid);
+        self.build(left, depth + 1);
+        let second = self.nodes.len();
+        self.build(right, depth + 1);
+        self.nodes[node].kind = NodeKind::Interior(second);
+        total
+    }
+
+    /// Direction from `origin` towards a random point on an emitter, picked
+    /// by walking down the tree by the importance of each child. If no
+    /// emitter is expected to light `origin`, any direction will do, since
+    /// `pdf` is 0 for them all.
+    pub fn sample(&self, origin: &Point3D, time: N) -> Vector3D {
+        let mut node = 0;
+        loop {
+            match self.nodes.get(node).map(|node| &node.kind) {
+                Some(NodeKind::Leaf(index)) => return self.emitters[*index].sample(origin, time),
+                Some(&NodeKind::Interior(second)) => {
+                    match self.child_probabilities(node, second, origin) {
+                        Some([first, _]) if random_n() < first => node += 1,
+                        Some(_) => node = second,
+                        None => break,
+                    }
+                }
+                None => break,
+            }
+        }
+        Vector3D::new(1.0, 0.0, 0.0)
+    }
+
+    /// Density per unit solid angle with which `sample` picks `direction`.
+    /// Only the branches whose bounds it passes through can have picked it.
+    pub fn pdf(&self, origin: &Point3D, direction: &Vector3D, time: N) -> N {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let ray = Ray::new(*origin, *direction, time);
+        let mut rec = HitRecord::default();
+        let mut pdf = 0.0;
+        let mut stack = [(0, 0.0); STACK_SIZE];
+        stack[0] = (0, 1.0);
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (node, probability) = stack[stack_len];
+            if !self.nodes[node]
+                .bounds
+                .bounds
+                .hit(&ray, 0.0, N::MAX, &mut rec)
+            {
+                continue;
+            }
+            match self.nodes[node].kind {
+                NodeKind::Leaf(index) => {
+                    pdf += probability * self.emitters[index].pdf(origin, direction, time);
+                }
+                NodeKind::Interior(second) => {
+                    if let Some([first, other]) = self.child_probabilities(node, second, origin) {
+                        stack[stack_len] = (node + 1, probability * first);
+                        stack[stack_len + 1] = (second, probability * other);
+                        stack_len += 2;
+                    }
+                }
+            }
+        }
+        pdf
+    }
+
+    // Chance of picking each child of the interior node at `node` to light
+    // `origin`, or None if neither is expected to
+    fn child_probabilities(&self, node: usize, second: usize, origin: &Point3D) -> Option<[N; 2]> {
+        let first = self.nodes[node + 1].bounds.importance(origin);
+        let second = self.nodes[second].bounds.importance(origin);
+        let total = first + second;
+        if total <= 0.0 || !total.is_finite() {
+            return None;
+        }
+        Some([first / total, second / total])
+    }
+}
+
+// What a group of emitters is known by: where they are, how much light they
+// give off in total, and a cone around `axis` holding the lines their normals
+// lie along
+#[derive(Clone)]
+struct LightBounds {
+    bounds: AABB,
+    power: N,
+    axis: Vector3D,
+    cos_theta_o: N,
+}
+
+impl LightBounds {
+    fn new(emitter: &(dyn Hittable + Sync + Send), time0: N, time1: N) -> Self {
+        let mut bounds = AABB::default();
+        emitter.bounding_box(time0, time1, &mut bounds);
+        // Objects that can't be sampled by area count as giving off one unit
+        let mut rec = HitRecord::default();
+        let power = if emitter.sample_surface(time0, &mut rec) {
+            rec.material.emitted(&rec).luminance() * emitter.area()
+        } else {
+            1.0
+        };
+        let (axis, cos_theta_o) = match emitter.flat_normal() {
+            Some(normal) => (normal, 1.0),
+            None => (Vector3D::new(0.0, 0.0, 1.0), -1.0),
+        };
+        Self {
+            bounds,
+            power,
+            axis,
+            cos_theta_o,
+        }
+    }
+
+    fn centroid(&self) -> Point3D {
+        self.bounds.centroid()
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        if self.power == 0.0 {
+            return other.clone();
+        }
+        if other.power == 0.0 {
+            return self.clone();
+        }
+
+        // Normals only count up to sign, so turn the other cone to the same side
+        let other_axis = if self.axis.dot(&other.axis) < 0.0 {
+            -other.axis
+        } else {
+            other.axis
+        };
+        let (axis, cos_theta_o) = union_cones(
+            (self.axis, self.cos_theta_o),
+            (other_axis, other.cos_theta_o),
+        );
+        Self {
+            bounds: self.bounds.surrounding_box(&other.bounds),
+            power: self.power + other.power,
+            axis,
+            cos_theta_o,
+        }
+    }
+
+    // Estimate of how much light reaches `p`: the power over the squared
+    // distance, times the cosine of the smallest angle any emitter inside
+    // could face `p` at
+    fn importance(&self, p: &Point3D) -> N {
+        let center = self.centroid();
+        let radius = (self.bounds.max() - self.bounds.min()).length() / 2.0;
+        let to_p = *p - center;
+        let distance_sq = to_p.length_sq().max(radius * radius);
+        let distance = distance_sq.sqrt();
+
+        // Angle between `p` and the nearest of the axis and its opposite,
+        // less what the cone and the bounds seen from `p` cover
+        let cos_theta_w = if distance > 0.0 {
+            (self.axis.dot(&to_p) / to_p.length()).abs()
+        } else {
+            1.0
+        };
+        let theta_w = cos_theta_w.min(1.0).acos();
+        let theta_o = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_b = if to_p.length_sq() > radius * radius {
+            (radius / to_p.length()).asin()
+        } else {
+            PI
+        };
+        let theta = (theta_w - theta_o - theta_b).max(0.0);
+        if theta >= PI / 2.0 {
+            return 0.0;
+        }
+        self.power * theta.cos() / distance_sq
+    }
+
+    // Surface area orientation heuristic for a group split off from one with
+    // `parent` bounds along `axis`
+    fn cost(&self, parent: &AABB, axis: usize) -> N {
+        let theta_o = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_w = (theta_o + PI / 2.0).min(PI);
+        let sin_theta_o = theta_o.sin();
+        let m_omega = 2.0 * PI * (1.0 - self.cos_theta_o)
+            + PI / 2.0
+                * (2.0 * theta_w * sin_theta_o
+                    - (theta_o - 2.0 * theta_w).cos()
+                    - 2.0 * theta_o * sin_theta_o
+                    + self.cos_theta_o);
+        // Long thin parents are better split across
+        let diagonal = parent.max() - parent.min();
+        let extent = component(&diagonal, axis);
+        let k_r = if extent > 0.0 {
+            diagonal.max_element() / extent
+        } else {
+            1.0
+        };
+        let d = self.bounds.max() - self.bounds.min();
+        let area = 2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x());
+        self.power * m_omega * k_r * area
+    }
+}
+
+fn union_all(buckets: &[Option<LightBounds>]) -> Option<LightBounds> {
+    buckets
+        .iter()
+        .flatten()
+        .fold(None, |total, bounds| match total {
+            Some(total) => Some(bounds.union(&total)),
+            None => Some(bounds.clone()),
+        })
+}
+
+// Smallest cone holding two others, each an axis and the cosine of its half
+// angle
+fn union_cones(a: (Vector3D, N), b: (Vector3D, N)) -> (Vector3D, N) {
+    let everywhere = (a.0, -1.0);
+    let theta_a = a.1.clamp(-1.0, 1.0).acos();
+    let theta_b = b.1.clamp(-1.0, 1.0).acos();
+    let theta_d = a.0.dot(&b.0).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+        return everywhere;
+    }
+    // Turn `a`'s axis towards `b`'s until the cone reaches both
+    let theta_r = theta_o - theta_a;
+    let rotation_axis = a.0.cross(&b.0);
+    if rotation_axis.length_sq() == 0.0 {
+        return everywhere;
+    }
+    let k = rotation_axis.unit();
+    let v = a.0;
+    let axis =
+        v * theta_r.cos() + k.cross(&v) * theta_r.sin() + k * (k.dot(&v) * (1.0 - theta_r.cos()));
+    (axis.unit(), theta_o.cos())
+}
+
+fn component(v: &Vector3D, axis: usize) -> N {
+    match axis {
+        0 => *v.x(),
+        1 => *v.y(),
+        _ => *v.z(),
+    }
+}
+
+#[test]
+fn pdf_matches_sampling() {
+    use crate::hittables::Quad;
+    use crate::materials::DiffuseLight;
+    use std::sync::Arc;
+
+    // A row of small lights facing down, one much brighter, lighting a point
+    // below them
+    let mut emitters: Vec<SharedHittableTraitObj> = Vec::new();
+    for i in 0..8 {
+        let brightness = if i == 5 { 40.0 } else { 4.0 };
+        emitters.push(Arc::new(Quad::new(
+            Point3D::new(i as N * 2.0, 3.0, 0.0),
+            Vector3D::new(0.5, 0.0, 0.0),
+            Vector3D::new(0.0, 0.0, 0.5),
+            Arc::new(DiffuseLight::new(crate::vector::Color::new(
+                brightness, brightness, brightness,
+            ))),
+        )));
+    }
+    let tree = LightTree::new(emitters.clone(), 0.0, 0.0);
+    let origin = Point3D::new(3.0, 0.0, 0.25);
+
+    // How often each light is picked, against how the pdf of a direction at
+    // its center splits over the lights
+    let samples = 20_000;
+    let mut picked = [0usize; 8];
+    for _ in 0..samples {
+        let direction = tree.sample(&origin, 0.0);
+        let ray = Ray::new(origin, direction, 0.0);
+        for (i, emitter) in emitters.iter().enumerate() {
+            if emitter.hit(&ray, 0.0, N::MAX, &mut HitRecord::default()) {
+                picked[i] += 1;
+            }
+        }
+    }
+    for (i, emitter) in emitters.iter().enumerate() {
+        let center = Point3D::new(i as N * 2.0 + 0.25, 3.0, 0.25);
+        let direction = center - origin;
+        let chance = tree.pdf(&origin, &direction, 0.0) / emitter.pdf(&origin, &direction, 0.0);
+        let frequency = picked[i] as N / samples as N;
+        assert!((frequency - chance).abs() < 0.02);
+    }
+    // The bright light is picked more than its neighbours
+    assert!(picked[5] > picked[4] && picked[5] > picked[6]);
+}
@@ -1,11 +1,29 @@
 mod aabb;
+#[cfg(not(feature = "simd"))]
 mod bvh;
+#[cfg(feature = "simd")]
+mod bvh4;
 mod hittable;
+mod light_tree;
+#[allow(dead_code)]
 mod moving_sphere;
+mod quad;
+#[cfg(feature = "simd")]
+pub mod simd;
 mod sphere;
+#[allow(dead_code)]
+mod triangle;
 
 pub use aabb::*;
+#[cfg(not(feature = "simd"))]
 pub use bvh::*;
+#[cfg(feature = "simd")]
+pub use bvh4::*;
 pub use hittable::*;
+pub use light_tree::*;
+#[allow(unused_imports)]
 pub use moving_sphere::*;
+pub use quad::*;
 pub use sphere::*;
+#[allow(unused_imports)]
+pub use triangle::*;
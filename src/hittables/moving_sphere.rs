@@ -1,4 +1,5 @@
-use super::{HitRecord, Hittable, AABB};
+use super::sphere::{hit_sphere, pdf_sphere, sample_sphere};
+use super::{object_id, HitRecord, Hittable, AABB};
 use crate::materials::SharedMaterial;
 use crate::ray::Ray;
 use crate::vector::{Point3D, Vector3D, N};
@@ -40,32 +41,25 @@ impl MovingSphere {
 
 impl Hittable for MovingSphere {
     fn hit(&self, ray: &Ray, t_min: N, t_max: N, rec: &mut HitRecord) -> bool {
-        let oc = ray.origin() - &self.center(ray.time());
-        let a = ray.direction().length_sq();
-        let half_b = oc.dot(ray.direction());
-        let c = oc.length_sq() - self.radius * self.radius;
-        let discrim = half_b * half_b - a * c;
-
-        if discrim < 0.0 {
+        let center = self.center(ray.time());
+        if !hit_sphere(&center, self.radius, ray, t_min, t_max, rec) {
             return false;
         }
+        rec.material = self.material.clone();
+        rec.object_id = object_id(self);
+        true
+    }
 
-        let sqrt_d = N::sqrt(discrim);
-        let mut root = (-half_b - sqrt_d) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrt_d) / a;
-            if root < t_min || t_max < root {
-                return false;
-            }
-        }
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
 
-        rec.t = root;
-        rec.p = ray.at(rec.t);
-        let outward_normal = (rec.p - self.center(ray.time())) / self.radius;
-        rec.set_face_normal(ray, outward_normal);
-        rec.material = self.material.clone();
+    fn sample(&self, origin: &Point3D, time: N) -> Vector3D {
+        sample_sphere(&self.center(&time), self.radius, origin)
+    }
 
-        true
+    fn pdf(&self, origin: &Point3D, direction: &Vector3D, time: N) -> N {
+        pdf_sphere(&self.center(&time), self.radius, origin, direction, time)
     }
 
     fn bounding_box(&self, time0: N, time1: N, output_box: &mut AABB) -> bool {
@@ -0,0 +1,140 @@
+use super::{area_to_solid_angle, object_id, HitRecord, Hittable, AABB};
+use crate::materials::SharedMaterial;
+use crate::ray::Ray;
+use crate::utils::{gamma, random_n};
+use crate::vector::{Point3D, Vector3D, N};
+
+/// A parallelogram with corner `q` spanned by the edges `u` and `v`
+#[derive(Clone)]
+pub struct Quad {
+    q: Point3D,
+    u: Vector3D,
+    v: Vector3D,
+    normal: Vector3D,
+    tangent: Vector3D,
+    // Plane offset, such that points on the plane satisfy `normal . p = d`
+    d: N,
+    // Maps a point on the plane to its coordinates along `u` and `v`
+    w: Vector3D,
+    area: N,
+    material: SharedMaterial,
+}
+
+impl Quad {
+    pub fn new(q: Point3D, u: Vector3D, v: Vector3D, material: SharedMaterial) -> Self {
+        let n = u.cross(&v);
+        let normal = n.unit();
+        Self {
+            q,
+            u,
+            v,
+            normal,
+            tangent: u.unit(),
+            d: normal.dot(&q),
+            w: n / n.dot(&n),
+            area: n.length(),
+            material,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: N, t_max: N, rec: &mut HitRecord) -> bool {
+        let denom = self.normal.dot(ray.direction());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        // Hits exactly at `t_min` are rejected too, since a ray spawned on an
+        // axis-aligned quad can have no error to offset it by
+        let t = (self.d - self.normal.dot(ray.origin())) / denom;
+        if t <= t_min || t_max < t {
+            return false;
+        }
+
+        let planar = ray.at(t) - self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.distance = t * ray.direction().length();
+        // Interpolating the corner is more accurate than `ray.at(t)`
+        let u = self.u * alpha;
+        let v = self.v * beta;
+        rec.p = self.q + u + v;
+        rec.p_error = (self.q.abs() + u.abs() + v.abs()) * gamma(7);
+        rec.set_face_normal(ray, self.normal);
+        rec.material = self.material.clone();
+        rec.u = alpha;
+        rec.v = beta;
+        rec.tangent = self.tangent;
+        rec.object_id = object_id(self);
+        true
+    }
+
+    fn bounding_box(&self, _: N, _: N, output_box: &mut AABB) -> bool {
+        let corners = [self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let (min, max) = corners.iter().fold((self.q, self.q), |(min, max), c| {
+            (
+                Point3D::new(
+                    min.x().min(*c.x()),
+                    min.y().min(*c.y()),
+                    min.z().min(*c.z()),
+                ),
+                Point3D::new(
+                    max.x().max(*c.x()),
+                    max.y().max(*c.y()),
+                    max.z().max(*c.z()),
+                ),
+            )
+        });
+        // Pad the box so axis-aligned quads don't end up with zero thickness
+        let padding = Vector3D::new(1e-4, 1e-4, 1e-4);
+        *output_box = AABB::new(min - padding, max + padding);
+        true
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    // Uniform over the area of the quad
+    fn sample(&self, origin: &Point3D, _: N) -> Vector3D {
+        self.q + self.u * random_n() + self.v * random_n() - *origin
+    }
+
+    fn pdf(&self, origin: &Point3D, direction: &Vector3D, time: N) -> N {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(*origin, *direction, time), 0.0, N::MAX, &mut rec) {
+            return 0.0;
+        }
+        area_to_solid_angle(self.area, direction, &rec)
+    }
+
+    fn area(&self) -> N {
+        self.area
+    }
+
+    fn flat_normal(&self) -> Option<Vector3D> {
+        Some(self.normal)
+    }
+
+    fn sample_surface(&self, _: N, rec: &mut HitRecord) -> bool {
+        let (alpha, beta) = (random_n(), random_n());
+        let u = self.u * alpha;
+        let v = self.v * beta;
+        rec.p = self.q + u + v;
+        rec.p_error = (self.q.abs() + u.abs() + v.abs()) * gamma(7);
+        rec.normal = self.normal;
+        rec.front_face = true;
+        rec.material = self.material.clone();
+        rec.u = alpha;
+        rec.v = beta;
+        rec.tangent = self.tangent;
+        rec.object_id = object_id(self);
+        true
+    }
+}
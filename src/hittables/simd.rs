@@ -0,0 +1,581 @@
+//! Four-wide ray-box and ray-triangle kernels used by `Bvh4`.
+//!
+//! With the `simd` feature on x86_64 these dispatch to SSE when rendering in
+//! `f32`, or to AVX (checked at runtime) when rendering in `f64`. Otherwise the
+//! scalar versions below are used. Both paths compute the same thing, so
+//! renders can be compared between them.
+
+use super::{Triangle, TRIANGLE_EPSILON};
+use crate::ray::Ray;
+use crate::vector::N;
+
+pub const LANES: usize = 4;
+
+/// Bounding boxes of four BVH children in structure-of-arrays layout
+#[derive(Clone, Default)]
+pub struct Bounds4 {
+    pub min_x: [N; LANES],
+    pub min_y: [N; LANES],
+    pub min_z: [N; LANES],
+    pub max_x: [N; LANES],
+    pub max_y: [N; LANES],
+    pub max_z: [N; LANES],
+    /// Bit `i` is set if lane `i` holds a child
+    pub valid: u8,
+}
+
+/// Up to four triangles packed for a batched Möller–Trumbore test
+#[derive(Clone, Default)]
+pub struct Triangle4 {
+    pub v0_x: [N; LANES],
+    pub v0_y: [N; LANES],
+    pub v0_z: [N; LANES],
+    pub e1_x: [N; LANES],
+    pub e1_y: [N; LANES],
+    pub e1_z: [N; LANES],
+    pub e2_x: [N; LANES],
+    pub e2_y: [N; LANES],
+    pub e2_z: [N; LANES],
+    pub valid: u8,
+}
+
+impl Triangle4 {
+    pub fn new(triangles: &[Triangle]) -> Self {
+        assert!(triangles.len() <= LANES);
+        let mut packed = Self::default();
+        for (lane, triangle) in triangles.iter().enumerate() {
+            packed.v0_x[lane] = *triangle.v0().x();
+            packed.v0_y[lane] = *triangle.v0().y();
+            packed.v0_z[lane] = *triangle.v0().z();
+            packed.e1_x[lane] = *triangle.edge1().x();
+            packed.e1_y[lane] = *triangle.edge1().y();
+            packed.e1_z[lane] = *triangle.edge1().z();
+            packed.e2_x[lane] = *triangle.edge2().x();
+            packed.e2_y[lane] = *triangle.edge2().y();
+            packed.e2_z[lane] = *triangle.edge2().z();
+            packed.valid |= 1 << lane;
+        }
+        packed
+    }
+}
+
+/// Slab test against four boxes at once. Returns a bitmask of the boxes hit and
+/// writes the entry distance of each lane to `t_near`.
+#[inline]
+pub fn hit_aabb4(bounds: &Bounds4, ray: &Ray, t_min: N, t_max: N, t_near: &mut [N; LANES]) -> u8 {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if x86::available() {
+            return unsafe { x86::hit_aabb4(bounds, ray, t_min, t_max, t_near) };
+        }
+    }
+    scalar::hit_aabb4(bounds, ray, t_min, t_max, t_near)
+}
+
+/// Intersect four triangles at once. Returns a bitmask of the triangles hit
+/// within `(t_min, t_max]`, with distances and barycentrics written per lane.
+#[inline]
+pub fn hit_triangle4(
+    triangles: &Triangle4,
+    ray: &Ray,
+    t_min: N,
+    t_max: N,
+    t: &mut [N; LANES],
+    u: &mut [N; LANES],
+    v: &mut [N; LANES],
+) -> u8 {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if x86::available() {
+            return unsafe { x86::hit_triangle4(triangles, ray, t_min, t_max, t, u, v) };
+        }
+    }
+    scalar::hit_triangle4(triangles, ray, t_min, t_max, t, u, v)
+}
+
+mod scalar {
+    use super::{Bounds4, Triangle4, LANES, TRIANGLE_EPSILON};
+    use crate::ray::Ray;
+    use crate::vector::N;
+
+    #[inline]
+    fn slab(min: N, max: N, origin: N, inv_direction: N, near: &mut N, far: &mut N) {
+        let t0 = (min - origin) * inv_direction;
+        let t1 = (max - origin) * inv_direction;
+        *near = near.max(t0.min(t1));
+        *far = far.min(t0.max(t1));
+    }
+
+    pub fn hit_aabb4(
+        bounds: &Bounds4,
+        ray: &Ray,
+        t_min: N,
+        t_max: N,
+        t_near: &mut [N; LANES],
+    ) -> u8 {
+        let origin = ray.origin();
+        let inv_direction = ray.inv_direction();
+        let mut mask = 0;
+        for (lane, t_near) in t_near.iter_mut().enumerate() {
+            let mut near = t_min;
+            let mut far = t_max;
+            slab(
+                bounds.min_x[lane],
+                bounds.max_x[lane],
+                *origin.x(),
+                *inv_direction.x(),
+                &mut near,
+                &mut far,
+            );
+            slab(
+                bounds.min_y[lane],
+                bounds.max_y[lane],
+                *origin.y(),
+                *inv_direction.y(),
+                &mut near,
+                &mut far,
+            );
+            slab(
+                bounds.min_z[lane],
+                bounds.max_z[lane],
+                *origin.z(),
+                *inv_direction.z(),
+                &mut near,
+                &mut far,
+            );
+            *t_near = near;
+            if far > near {
+                mask |= 1 << lane;
+            }
+        }
+        mask & bounds.valid
+    }
+
+    pub fn hit_triangle4(
+        triangles: &Triangle4,
+        ray: &Ray,
+        t_min: N,
+        t_max: N,
+        t: &mut [N; LANES],
+        u: &mut [N; LANES],
+        v: &mut [N; LANES],
+    ) -> u8 {
+        let (ox, oy, oz) = (*ray.origin().x(), *ray.origin().y(), *ray.origin().z());
+        let (dx, dy, dz) = (
+            *ray.direction().x(),
+            *ray.direction().y(),
+            *ray.direction().z(),
+        );
+        let mut mask = 0;
+        for lane in 0..LANES {
+            let (e1x, e1y, e1z) = (
+                triangles.e1_x[lane],
+                triangles.e1_y[lane],
+                triangles.e1_z[lane],
+            );
+            let (e2x, e2y, e2z) = (
+                triangles.e2_x[lane],
+                triangles.e2_y[lane],
+                triangles.e2_z[lane],
+            );
+
+            // pvec = d x e2
+            let px = dy * e2z - dz * e2y;
+            let py = dz * e2x - dx * e2z;
+            let pz = dx * e2y - dy * e2x;
+            let det = e1x * px + e1y * py + e1z * pz;
+            let inv_det = 1.0 / det;
+
+            let tx = ox - triangles.v0_x[lane];
+            let ty = oy - triangles.v0_y[lane];
+            let tz = oz - triangles.v0_z[lane];
+            u[lane] = (tx * px + ty * py + tz * pz) * inv_det;
+
+            // qvec = tvec x e1
+            let qx = ty * e1z - tz * e1y;
+            let qy = tz * e1x - tx * e1z;
+            let qz = tx * e1y - ty * e1x;
+            v[lane] = (dx * qx + dy * qy + dz * qz) * inv_det;
+            t[lane] = (e2x * qx + e2y * qy + e2z * qz) * inv_det;
+
+            if det.abs() >= TRIANGLE_EPSILON
+                && u[lane] >= 0.0
+                && v[lane] >= 0.0
+                && u[lane] + v[lane] <= 1.0
+                && t[lane] > t_min
+                && t[lane] <= t_max
+            {
+                mask |= 1 << lane;
+            }
+        }
+        mask & triangles.valid
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(not(feature = "f32"))]
+    pub use self::avx::*;
+    #[cfg(feature = "f32")]
+    pub use self::sse::*;
+
+    /// Whether the kernels for the current precision can run on this CPU
+    #[inline]
+    pub fn available() -> bool {
+        // SSE is part of the x86_64 baseline, AVX is not
+        cfg!(feature = "f32") || is_x86_feature_detected!("avx")
+    }
+
+    // Four f64 lanes per AVX register
+    #[cfg(not(feature = "f32"))]
+    mod avx {
+        use std::arch::x86_64::*;
+
+        use super::super::{Bounds4, Triangle4, LANES, TRIANGLE_EPSILON};
+        use crate::ray::Ray;
+        use crate::vector::N;
+
+        #[target_feature(enable = "avx")]
+        pub unsafe fn hit_aabb4(
+            bounds: &Bounds4,
+            ray: &Ray,
+            t_min: N,
+            t_max: N,
+            t_near: &mut [N; LANES],
+        ) -> u8 {
+            let mut near = _mm256_set1_pd(t_min);
+            let mut far = _mm256_set1_pd(t_max);
+
+            macro_rules! slab {
+                ($min:expr, $max:expr, $origin:expr, $inv_direction:expr) => {
+                    let origin = _mm256_set1_pd(*$origin);
+                    let inv_direction = _mm256_set1_pd(*$inv_direction);
+                    let t0 = _mm256_mul_pd(
+                        _mm256_sub_pd(_mm256_loadu_pd($min.as_ptr()), origin),
+                        inv_direction,
+                    );
+                    let t1 = _mm256_mul_pd(
+                        _mm256_sub_pd(_mm256_loadu_pd($max.as_ptr()), origin),
+                        inv_direction,
+                    );
+                    near = _mm256_max_pd(near, _mm256_min_pd(t0, t1));
+                    far = _mm256_min_pd(far, _mm256_max_pd(t0, t1));
+                };
+            }
+
+            let origin = ray.origin();
+            let inv_direction = ray.inv_direction();
+            slab!(bounds.min_x, bounds.max_x, origin.x(), inv_direction.x());
+            slab!(bounds.min_y, bounds.max_y, origin.y(), inv_direction.y());
+            slab!(bounds.min_z, bounds.max_z, origin.z(), inv_direction.z());
+
+            _mm256_storeu_pd(t_near.as_mut_ptr(), near);
+            (_mm256_movemask_pd(_mm256_cmp_pd(far, near, _CMP_GT_OQ)) as u8) & bounds.valid
+        }
+
+        #[target_feature(enable = "avx")]
+        pub unsafe fn hit_triangle4(
+            triangles: &Triangle4,
+            ray: &Ray,
+            t_min: N,
+            t_max: N,
+            t: &mut [N; LANES],
+            u: &mut [N; LANES],
+            v: &mut [N; LANES],
+        ) -> u8 {
+            let ox = _mm256_set1_pd(*ray.origin().x());
+            let oy = _mm256_set1_pd(*ray.origin().y());
+            let oz = _mm256_set1_pd(*ray.origin().z());
+            let dx = _mm256_set1_pd(*ray.direction().x());
+            let dy = _mm256_set1_pd(*ray.direction().y());
+            let dz = _mm256_set1_pd(*ray.direction().z());
+
+            let e1x = _mm256_loadu_pd(triangles.e1_x.as_ptr());
+            let e1y = _mm256_loadu_pd(triangles.e1_y.as_ptr());
+            let e1z = _mm256_loadu_pd(triangles.e1_z.as_ptr());
+            let e2x = _mm256_loadu_pd(triangles.e2_x.as_ptr());
+            let e2y = _mm256_loadu_pd(triangles.e2_y.as_ptr());
+            let e2z = _mm256_loadu_pd(triangles.e2_z.as_ptr());
+
+            // pvec = d x e2
+            let px = _mm256_sub_pd(_mm256_mul_pd(dy, e2z), _mm256_mul_pd(dz, e2y));
+            let py = _mm256_sub_pd(_mm256_mul_pd(dz, e2x), _mm256_mul_pd(dx, e2z));
+            let pz = _mm256_sub_pd(_mm256_mul_pd(dx, e2y), _mm256_mul_pd(dy, e2x));
+            let det = _mm256_add_pd(
+                _mm256_add_pd(_mm256_mul_pd(e1x, px), _mm256_mul_pd(e1y, py)),
+                _mm256_mul_pd(e1z, pz),
+            );
+            let inv_det = _mm256_div_pd(_mm256_set1_pd(1.0), det);
+
+            let tx = _mm256_sub_pd(ox, _mm256_loadu_pd(triangles.v0_x.as_ptr()));
+            let ty = _mm256_sub_pd(oy, _mm256_loadu_pd(triangles.v0_y.as_ptr()));
+            let tz = _mm256_sub_pd(oz, _mm256_loadu_pd(triangles.v0_z.as_ptr()));
+            let u_lanes = _mm256_mul_pd(
+                _mm256_add_pd(
+                    _mm256_add_pd(_mm256_mul_pd(tx, px), _mm256_mul_pd(ty, py)),
+                    _mm256_mul_pd(tz, pz),
+                ),
+                inv_det,
+            );
+
+            // qvec = tvec x e1
+            let qx = _mm256_sub_pd(_mm256_mul_pd(ty, e1z), _mm256_mul_pd(tz, e1y));
+            let qy = _mm256_sub_pd(_mm256_mul_pd(tz, e1x), _mm256_mul_pd(tx, e1z));
+            let qz = _mm256_sub_pd(_mm256_mul_pd(tx, e1y), _mm256_mul_pd(ty, e1x));
+            let v_lanes = _mm256_mul_pd(
+                _mm256_add_pd(
+                    _mm256_add_pd(_mm256_mul_pd(dx, qx), _mm256_mul_pd(dy, qy)),
+                    _mm256_mul_pd(dz, qz),
+                ),
+                inv_det,
+            );
+            let t_lanes = _mm256_mul_pd(
+                _mm256_add_pd(
+                    _mm256_add_pd(_mm256_mul_pd(e2x, qx), _mm256_mul_pd(e2y, qy)),
+                    _mm256_mul_pd(e2z, qz),
+                ),
+                inv_det,
+            );
+
+            let zero = _mm256_setzero_pd();
+            // |det| via clearing the sign bit
+            let abs_det = _mm256_andnot_pd(_mm256_set1_pd(-0.0), det);
+            let mut hit = _mm256_cmp_pd(abs_det, _mm256_set1_pd(TRIANGLE_EPSILON), _CMP_GE_OQ);
+            hit = _mm256_and_pd(hit, _mm256_cmp_pd(u_lanes, zero, _CMP_GE_OQ));
+            hit = _mm256_and_pd(hit, _mm256_cmp_pd(v_lanes, zero, _CMP_GE_OQ));
+            hit = _mm256_and_pd(
+                hit,
+                _mm256_cmp_pd(
+                    _mm256_add_pd(u_lanes, v_lanes),
+                    _mm256_set1_pd(1.0),
+                    _CMP_LE_OQ,
+                ),
+            );
+            hit = _mm256_and_pd(
+                hit,
+                _mm256_cmp_pd(t_lanes, _mm256_set1_pd(t_min), _CMP_GT_OQ),
+            );
+            hit = _mm256_and_pd(
+                hit,
+                _mm256_cmp_pd(t_lanes, _mm256_set1_pd(t_max), _CMP_LE_OQ),
+            );
+
+            _mm256_storeu_pd(t.as_mut_ptr(), t_lanes);
+            _mm256_storeu_pd(u.as_mut_ptr(), u_lanes);
+            _mm256_storeu_pd(v.as_mut_ptr(), v_lanes);
+            (_mm256_movemask_pd(hit) as u8) & triangles.valid
+        }
+    }
+
+    // Four f32 lanes per SSE register
+    #[cfg(feature = "f32")]
+    mod sse {
+        use std::arch::x86_64::*;
+
+        use super::super::{Bounds4, Triangle4, LANES, TRIANGLE_EPSILON};
+        use crate::ray::Ray;
+        use crate::vector::N;
+
+        #[target_feature(enable = "sse")]
+        pub unsafe fn hit_aabb4(
+            bounds: &Bounds4,
+            ray: &Ray,
+            t_min: N,
+            t_max: N,
+            t_near: &mut [N; LANES],
+        ) -> u8 {
+            let mut near = _mm_set1_ps(t_min);
+            let mut far = _mm_set1_ps(t_max);
+
+            macro_rules! slab {
+                ($min:expr, $max:expr, $origin:expr, $inv_direction:expr) => {
+                    let origin = _mm_set1_ps(*$origin);
+                    let inv_direction = _mm_set1_ps(*$inv_direction);
+                    let t0 = _mm_mul_ps(
+                        _mm_sub_ps(_mm_loadu_ps($min.as_ptr()), origin),
+                        inv_direction,
+                    );
+                    let t1 = _mm_mul_ps(
+                        _mm_sub_ps(_mm_loadu_ps($max.as_ptr()), origin),
+                        inv_direction,
+                    );
+                    near = _mm_max_ps(near, _mm_min_ps(t0, t1));
+                    far = _mm_min_ps(far, _mm_max_ps(t0, t1));
+                };
+            }
+
+            let origin = ray.origin();
+            let inv_direction = ray.inv_direction();
+            slab!(bounds.min_x, bounds.max_x, origin.x(), inv_direction.x());
+            slab!(bounds.min_y, bounds.max_y, origin.y(), inv_direction.y());
+            slab!(bounds.min_z, bounds.max_z, origin.z(), inv_direction.z());
+
+            _mm_storeu_ps(t_near.as_mut_ptr(), near);
+            (_mm_movemask_ps(_mm_cmpgt_ps(far, near)) as u8) & bounds.valid
+        }
+
+        #[target_feature(enable = "sse")]
+        pub unsafe fn hit_triangle4(
+            triangles: &Triangle4,
+            ray: &Ray,
+            t_min: N,
+            t_max: N,
+            t: &mut [N; LANES],
+            u: &mut [N; LANES],
+            v: &mut [N; LANES],
+        ) -> u8 {
+            let ox = _mm_set1_ps(*ray.origin().x());
+            let oy = _mm_set1_ps(*ray.origin().y());
+            let oz = _mm_set1_ps(*ray.origin().z());
+            let dx = _mm_set1_ps(*ray.direction().x());
+            let dy = _mm_set1_ps(*ray.direction().y());
+            let dz = _mm_set1_ps(*ray.direction().z());
+
+            let e1x = _mm_loadu_ps(triangles.e1_x.as_ptr());
+            let e1y = _mm_loadu_ps(triangles.e1_y.as_ptr());
+            let e1z = _mm_loadu_ps(triangles.e1_z.as_ptr());
+            let e2x = _mm_loadu_ps(triangles.e2_x.as_ptr());
+            let e2y = _mm_loadu_ps(triangles.e2_y.as_ptr());
+            let e2z = _mm_loadu_ps(triangles.e2_z.as_ptr());
+
+            // pvec = d x e2
+            let px = _mm_sub_ps(_mm_mul_ps(dy, e2z), _mm_mul_ps(dz, e2y));
+            let py = _mm_sub_ps(_mm_mul_ps(dz, e2x), _mm_mul_ps(dx, e2z));
+            let pz = _mm_sub_ps(_mm_mul_ps(dx, e2y), _mm_mul_ps(dy, e2x));
+            let det = _mm_add_ps(
+                _mm_add_ps(_mm_mul_ps(e1x, px), _mm_mul_ps(e1y, py)),
+                _mm_mul_ps(e1z, pz),
+            );
+            let inv_det = _mm_div_ps(_mm_set1_ps(1.0), det);
+
+            let tx = _mm_sub_ps(ox, _mm_loadu_ps(triangles.v0_x.as_ptr()));
+            let ty = _mm_sub_ps(oy, _mm_loadu_ps(triangles.v0_y.as_ptr()));
+            let tz = _mm_sub_ps(oz, _mm_loadu_ps(triangles.v0_z.as_ptr()));
+            let u_lanes = _mm_mul_ps(
+                _mm_add_ps(
+                    _mm_add_ps(_mm_mul_ps(tx, px), _mm_mul_ps(ty, py)),
+                    _mm_mul_ps(tz, pz),
+                ),
+                inv_det,
+            );
+
+            // qvec = tvec x e1
+            let qx = _mm_sub_ps(_mm_mul_ps(ty, e1z), _mm_mul_ps(tz, e1y));
+            let qy = _mm_sub_ps(_mm_mul_ps(tz, e1x), _mm_mul_ps(tx, e1z));
+            let qz = _mm_sub_ps(_mm_mul_ps(tx, e1y), _mm_mul_ps(ty, e1x));
+            let v_lanes = _mm_mul_ps(
+                _mm_add_ps(
+                    _mm_add_ps(_mm_mul_ps(dx, qx), _mm_mul_ps(dy, qy)),
+                    _mm_mul_ps(dz, qz),
+                ),
+                inv_det,
+            );
+            let t_lanes = _mm_mul_ps(
+                _mm_add_ps(
+                    _mm_add_ps(_mm_mul_ps(e2x, qx), _mm_mul_ps(e2y, qy)),
+                    _mm_mul_ps(e2z, qz),
+                ),
+                inv_det,
+            );
+
+            let zero = _mm_setzero_ps();
+            // |det| via clearing the sign bit
+            let abs_det = _mm_andnot_ps(_mm_set1_ps(-0.0), det);
+            let mut hit = _mm_cmpge_ps(abs_det, _mm_set1_ps(TRIANGLE_EPSILON));
+            hit = _mm_and_ps(hit, _mm_cmpge_ps(u_lanes, zero));
+            hit = _mm_and_ps(hit, _mm_cmpge_ps(v_lanes, zero));
+            hit = _mm_and_ps(
+                hit,
+                _mm_cmple_ps(_mm_add_ps(u_lanes, v_lanes), _mm_set1_ps(1.0)),
+            );
+            hit = _mm_and_ps(hit, _mm_cmpgt_ps(t_lanes, _mm_set1_ps(t_min)));
+            hit = _mm_and_ps(hit, _mm_cmple_ps(t_lanes, _mm_set1_ps(t_max)));
+
+            _mm_storeu_ps(t.as_mut_ptr(), t_lanes);
+            _mm_storeu_ps(u.as_mut_ptr(), u_lanes);
+            _mm_storeu_ps(v.as_mut_ptr(), v_lanes);
+            (_mm_movemask_ps(hit) as u8) & triangles.valid
+        }
+    }
+}
+
+#[test]
+fn aabb4_matches_scalar_aabb() {
+    use super::{HitRecord, AABB};
+    use crate::utils::random_range;
+    use crate::vector::{Point3D, Vector3D};
+
+    let boxes: Vec<AABB> = (0..LANES)
+        .map(|_| {
+            let min = Point3D::random_range(-2.0, 1.0);
+            AABB::new(min, min + Vector3D::random_range(0.1, 1.0))
+        })
+        .collect();
+    let mut bounds = Bounds4::default();
+    for (lane, aabb) in boxes.iter().enumerate() {
+        bounds.min_x[lane] = *aabb.min().x();
+        bounds.min_y[lane] = *aabb.min().y();
+        bounds.min_z[lane] = *aabb.min().z();
+        bounds.max_x[lane] = *aabb.max().x();
+        bounds.max_y[lane] = *aabb.max().y();
+        bounds.max_z[lane] = *aabb.max().z();
+        bounds.valid |= 1 << lane;
+    }
+
+    let mut rec = HitRecord::default();
+    for _ in 0..1000 {
+        let ray = Ray::new(
+            Point3D::random_range(-5.0, 5.0),
+            Vector3D::random_range(-1.0, 1.0),
+            0.0,
+        );
+        let t_max = random_range(0.5, 10.0);
+        let mut t_near = [0.0; LANES];
+        let mask = hit_aabb4(&bounds, &ray, 0.001, t_max, &mut t_near);
+        for (lane, aabb) in boxes.iter().enumerate() {
+            let expected = aabb.hit(&ray, 0.001, t_max, &mut rec);
+            assert_eq!(expected, mask & (1 << lane) != 0);
+        }
+    }
+}
+
+#[test]
+fn triangle4_matches_scalar_triangle() {
+    use super::{HitRecord, Hittable};
+    use crate::materials::Lambert;
+    use crate::vector::{Color, Point3D, Vector3D};
+    use std::sync::Arc;
+
+    let material = Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5)));
+    let triangles: Vec<Triangle> = (0..3)
+        .map(|_| {
+            Triangle::new(
+                Point3D::random_range(-1.0, 1.0),
+                Point3D::random_range(-1.0, 1.0),
+                Point3D::random_range(-1.0, 1.0),
+                material.clone(),
+            )
+        })
+        .collect();
+    // Only three lanes are filled, so the last one must never report a hit
+    let packed = Triangle4::new(&triangles);
+
+    let mut rec = HitRecord::default();
+    for _ in 0..1000 {
+        let ray = Ray::new(
+            Point3D::random_range(-3.0, 3.0),
+            Vector3D::random_range(-1.0, 1.0),
+            0.0,
+        );
+        let (mut t, mut u, mut v) = ([0.0; LANES], [0.0; LANES], [0.0; LANES]);
+        let mask = hit_triangle4(&packed, &ray, 0.001, N::MAX, &mut t, &mut u, &mut v);
+        assert_eq!(mask & (1 << 3), 0);
+        for (lane, triangle) in triangles.iter().enumerate() {
+            let expected = triangle.hit(&ray, 0.001, N::MAX, &mut rec);
+            assert_eq!(expected, mask & (1 << lane) != 0);
+            if expected {
+                assert!((rec.t - t[lane]).abs() < 1e-6);
+            }
+        }
+    }
+}
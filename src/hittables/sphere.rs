@@ -1,6 +1,7 @@
-use super::{HitRecord, Hittable, AABB};
+use super::{object_id, HitRecord, Hittable, AABB};
 use crate::materials::SharedMaterial;
 use crate::ray::Ray;
+use crate::utils::{gamma, random_in_cone, random_unit_vector, PI};
 use crate::vector::{Point3D, Vector3D, N};
 
 #[derive(Clone)]
@@ -22,31 +23,39 @@ impl Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_min: N, t_max: N, rec: &mut HitRecord) -> bool {
-        let oc = ray.origin() - &self.center;
-        let a = ray.direction().length_sq();
-        let half_b = oc.dot(ray.direction());
-        let c = oc.length_sq() - self.radius * self.radius;
-        let discrim = half_b * half_b - a * c;
-
-        if discrim < 0.0 {
+        if !hit_sphere(&self.center, self.radius, ray, t_min, t_max, rec) {
             return false;
         }
+        rec.material = self.material.clone();
+        rec.object_id = object_id(self);
+        true
+    }
 
-        let sqrt_d = N::sqrt(discrim);
-        let mut root = (-half_b - sqrt_d) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrt_d) / a;
-            if root < t_min || t_max < root {
-                return false;
-            }
-        }
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
 
-        rec.t = root;
-        rec.p = ray.at(rec.t);
-        let outward_normal = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(ray, outward_normal);
-        rec.material = self.material.clone();
+    fn sample(&self, origin: &Point3D, _: N) -> Vector3D {
+        sample_sphere(&self.center, self.radius, origin)
+    }
 
+    fn pdf(&self, origin: &Point3D, direction: &Vector3D, time: N) -> N {
+        pdf_sphere(&self.center, self.radius, origin, direction, time)
+    }
+
+    fn area(&self) -> N {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, _: N, rec: &mut HitRecord) -> bool {
+        let outward_normal = random_unit_vector();
+        let offset = outward_normal * self.radius.abs();
+        rec.p = self.center + offset;
+        rec.p_error = offset.abs() * gamma(5) + rec.p.abs() * gamma(1);
+        rec.normal = outward_normal;
+        rec.front_face = true;
+        rec.material = self.material.clone();
+        rec.object_id = object_id(self);
         true
     }
 
@@ -58,3 +67,131 @@ impl Hittable for Sphere {
         true
     }
 }
+
+/// Intersect a ray with a sphere and fill in everything but the material of the
+/// hit record. Shared by `Sphere` and `MovingSphere`.
+pub(super) fn hit_sphere(
+    center: &Point3D,
+    radius: N,
+    ray: &Ray,
+    t_min: N,
+    t_max: N,
+    rec: &mut HitRecord,
+) -> bool {
+    let oc = ray.origin() - center;
+    let a = ray.direction().length_sq();
+    let half_b = oc.dot(ray.direction());
+    let c = oc.length_sq() - radius * radius;
+
+    // Taking the discriminant from the distance between the center and the ray
+    // avoids the cancellation in `half_b * half_b - a * c`
+    let perpendicular = oc - ray.direction() * (half_b / a);
+    let discrim = a * (radius * radius - perpendicular.length_sq());
+    if discrim < 0.0 {
+        return false;
+    }
+
+    // Use the form of each root that doesn't subtract nearly equal values
+    let sqrt_d = N::sqrt(discrim);
+    let q = if half_b > 0.0 {
+        -half_b - sqrt_d
+    } else {
+        -half_b + sqrt_d
+    };
+    if q == 0.0 {
+        return false;
+    }
+    let (near, far) = {
+        let (r0, r1) = (q / a, c / q);
+        if r0 < r1 {
+            (r0, r1)
+        } else {
+            (r1, r0)
+        }
+    };
+
+    // `c` is only accurate to within this much, which bounds how close to zero a
+    // root can be and still be trusted. Anything closer is the surface a spawned
+    // ray is leaving.
+    let t_error = gamma(3) * (oc.length_sq() + radius * radius) / q.abs();
+    let outside = |root: N| root < t_min || root <= t_error || t_max < root;
+
+    let mut root = near;
+    if outside(root) {
+        root = far;
+        if outside(root) {
+            return false;
+        }
+    }
+
+    rec.t = root;
+    rec.distance = root * ray.direction().length();
+    let offset = ray.at(rec.t) - *center;
+    // Reproject onto the surface, which removes most of the error of `ray.at`
+    let offset = offset * (radius.abs() / offset.length());
+    rec.p = *center + offset;
+    // Error of the reprojected offset, plus rounding when adding the center
+    rec.p_error = offset.abs() * gamma(5) + rec.p.abs() * gamma(1);
+    let outward_normal = (rec.p - *center) / radius;
+    rec.set_face_normal(ray, outward_normal);
+
+    // Longitude around the y axis starting at -x, and latitude from -y to +y
+    let theta = (-outward_normal.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-outward_normal.z()).atan2(*outward_normal.x()) + PI;
+    rec.u = phi / (2.0 * PI);
+    rec.v = theta / PI;
+    // Along the line of latitude, undefined at the poles
+    let tangent = Vector3D::new(*outward_normal.z(), 0.0, -outward_normal.x());
+    rec.tangent = if tangent.near_zero() {
+        Vector3D::default()
+    } else {
+        tangent.unit()
+    };
+
+    true
+}
+
+// 1 - cos of the half angle of the cone a sphere subtends from a point outside
+// of it, written so it stays accurate for small or distant spheres
+#[inline]
+fn one_minus_cos_theta_max(radius: N, distance_sq: N) -> N {
+    let sin_sq = radius * radius / distance_sq;
+    sin_sq / (1.0 + (1.0 - sin_sq).sqrt())
+}
+
+/// Direction from `origin` uniformly distributed over the cone of directions
+/// that hit the sphere. Shared by `Sphere` and `MovingSphere`.
+pub(super) fn sample_sphere(center: &Point3D, radius: N, origin: &Point3D) -> Vector3D {
+    let direction = center - origin;
+    let distance_sq = direction.length_sq();
+    if distance_sq <= radius * radius {
+        // Every direction hits the sphere from inside of it
+        return random_unit_vector();
+    }
+
+    random_in_cone(
+        &direction.unit(),
+        one_minus_cos_theta_max(radius, distance_sq),
+    )
+}
+
+/// Density of `sample_sphere` picking `direction`
+pub(super) fn pdf_sphere(
+    center: &Point3D,
+    radius: N,
+    origin: &Point3D,
+    direction: &Vector3D,
+    time: N,
+) -> N {
+    let distance_sq = (center - origin).length_sq();
+    if distance_sq <= radius * radius {
+        return 1.0 / (4.0 * PI);
+    }
+
+    let mut rec = HitRecord::default();
+    let ray = Ray::new(*origin, *direction, time);
+    if !hit_sphere(center, radius, &ray, 0.0, N::MAX, &mut rec) {
+        return 0.0;
+    }
+    1.0 / (2.0 * PI * one_minus_cos_theta_max(radius, distance_sq))
+}
@@ -0,0 +1,241 @@
+use super::{area_to_solid_angle, object_id, HitRecord, Hittable, AABB};
+use crate::materials::SharedMaterial;
+use crate::ray::Ray;
+use crate::utils::{gamma, random_n};
+use crate::vector::{Point3D, Vector3D, N};
+
+// Determinants smaller than this are treated as rays parallel to the triangle
+pub const TRIANGLE_EPSILON: N = 1e-8;
+
+#[derive(Clone)]
+pub struct Triangle {
+    v0: Point3D,
+    edge1: Vector3D,
+    edge2: Vector3D,
+    normal: Vector3D,
+    area: N,
+    // Texture coordinates of the first vertex, and their change along each edge
+    uv0: (N, N),
+    duv1: (N, N),
+    duv2: (N, N),
+    tangent: Vector3D,
+    material: SharedMaterial,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3D, v1: Point3D, v2: Point3D, material: SharedMaterial) -> Self {
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let cross = edge1.cross(&edge2);
+        Self {
+            v0,
+            edge1,
+            edge2,
+            normal: cross.unit(),
+            area: 0.5 * cross.length(),
+            // Barycentrics of the second and third vertices, until given UVs
+            uv0: (0.0, 0.0),
+            duv1: (1.0, 0.0),
+            duv2: (0.0, 1.0),
+            tangent: edge1.unit(),
+            material,
+        }
+    }
+
+    /// Give each vertex texture coordinates, interpolated across the triangle
+    /// for `u` and `v` of the hit record, in place of barycentrics
+    pub fn with_uvs(mut self, uv0: (N, N), uv1: (N, N), uv2: (N, N)) -> Self {
+        self.uv0 = uv0;
+        self.duv1 = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+        self.duv2 = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+
+        // Solve for the direction of increasing `u` in the plane of the edges
+        let det = self.duv1.0 * self.duv2.1 - self.duv1.1 * self.duv2.0;
+        let dpdu = (self.edge1 * self.duv2.1 - self.edge2 * self.duv1.1) / det;
+        self.tangent = if det.abs() < TRIANGLE_EPSILON || dpdu.near_zero() {
+            Vector3D::default()
+        } else {
+            dpdu.unit()
+        };
+        self
+    }
+
+    // Texture coordinates at the barycentrics `b1` and `b2`
+    #[inline]
+    fn uv(&self, b1: N, b2: N) -> (N, N) {
+        (
+            self.uv0.0 + self.duv1.0 * b1 + self.duv2.0 * b2,
+            self.uv0.1 + self.duv1.1 * b1 + self.duv2.1 * b2,
+        )
+    }
+
+    #[cfg(feature = "simd")]
+    #[inline]
+    pub fn v0(&self) -> &Point3D {
+        &self.v0
+    }
+
+    #[cfg(feature = "simd")]
+    #[inline]
+    pub fn edge1(&self) -> &Vector3D {
+        &self.edge1
+    }
+
+    #[cfg(feature = "simd")]
+    #[inline]
+    pub fn edge2(&self) -> &Vector3D {
+        &self.edge2
+    }
+
+    /// Fill in a hit record from the ray parameter and barycentrics of an intersection
+    pub fn set_hit_record(&self, ray: &Ray, t: N, u: N, v: N, rec: &mut HitRecord) {
+        rec.t = t;
+        rec.distance = t * ray.direction().length();
+        // Interpolating the vertices is more accurate than `ray.at(t)`
+        let e1 = self.edge1 * u;
+        let e2 = self.edge2 * v;
+        rec.p = self.v0 + e1 + e2;
+        rec.p_error = (self.v0.abs() + e1.abs() + e2.abs()) * gamma(7);
+        rec.set_face_normal(ray, self.normal);
+        rec.material = self.material.clone();
+        (rec.u, rec.v) = self.uv(u, v);
+        rec.tangent = self.tangent;
+        rec.object_id = object_id(self);
+    }
+}
+
+impl Hittable for Triangle {
+    // Möller–Trumbore
+    fn hit(&self, ray: &Ray, t_min: N, t_max: N, rec: &mut HitRecord) -> bool {
+        let pvec = ray.direction().cross(&self.edge2);
+        let det = self.edge1.dot(&pvec);
+        if det.abs() < TRIANGLE_EPSILON {
+            return false;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = ray.origin() - &self.v0;
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return false;
+        }
+
+        let qvec = tvec.cross(&self.edge1);
+        let v = ray.direction().dot(&qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return false;
+        }
+
+        // Hits exactly at `t_min` are rejected too, since a ray spawned on an
+        // axis-aligned triangle can have no error to offset it by
+        let t = self.edge2.dot(&qvec) * inv_det;
+        if t <= t_min || t_max < t {
+            return false;
+        }
+
+        self.set_hit_record(ray, t, u, v, rec);
+        true
+    }
+
+    fn bounding_box(&self, _: N, _: N, output_box: &mut AABB) -> bool {
+        let v1 = self.v0 + self.edge1;
+        let v2 = self.v0 + self.edge2;
+        // Pad the box so axis-aligned triangles don't end up with zero thickness
+        let padding = Vector3D::new(1e-4, 1e-4, 1e-4);
+        *output_box = AABB::new(
+            Point3D::new(
+                self.v0.x().min(*v1.x()).min(*v2.x()),
+                self.v0.y().min(*v1.y()).min(*v2.y()),
+                self.v0.z().min(*v1.z()).min(*v2.z()),
+            ) - padding,
+            Point3D::new(
+                self.v0.x().max(*v1.x()).max(*v2.x()),
+                self.v0.y().max(*v1.y()).max(*v2.y()),
+                self.v0.z().max(*v1.z()).max(*v2.z()),
+            ) + padding,
+        );
+        true
+    }
+
+    #[cfg(feature = "simd")]
+    fn as_triangle(&self) -> Option<&Triangle> {
+        Some(self)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    // Uniform over the area of the triangle
+    fn sample(&self, origin: &Point3D, _: N) -> Vector3D {
+        let (b1, b2) = random_barycentrics();
+        self.v0 + self.edge1 * b1 + self.edge2 * b2 - *origin
+    }
+
+    fn pdf(&self, origin: &Point3D, direction: &Vector3D, time: N) -> N {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(*origin, *direction, time), 0.0, N::MAX, &mut rec) {
+            return 0.0;
+        }
+        area_to_solid_angle(self.area, direction, &rec)
+    }
+
+    fn area(&self) -> N {
+        self.area
+    }
+
+    fn flat_normal(&self) -> Option<Vector3D> {
+        Some(self.normal)
+    }
+
+    fn sample_surface(&self, _: N, rec: &mut HitRecord) -> bool {
+        let (u, v) = random_barycentrics();
+        let e1 = self.edge1 * u;
+        let e2 = self.edge2 * v;
+        rec.p = self.v0 + e1 + e2;
+        rec.p_error = (self.v0.abs() + e1.abs() + e2.abs()) * gamma(7);
+        rec.normal = self.normal;
+        rec.front_face = true;
+        rec.material = self.material.clone();
+        (rec.u, rec.v) = self.uv(u, v);
+        rec.tangent = self.tangent;
+        rec.object_id = object_id(self);
+        true
+    }
+}
+
+// Weights of the second and third vertices of a point uniformly distributed
+// over a triangle
+fn random_barycentrics() -> (N, N) {
+    let su = random_n().sqrt();
+    let b2 = random_n() * su;
+    (su - b2, b2)
+}
+
+#[test]
+fn uvs_interpolate_and_set_the_tangent() {
+    use std::sync::Arc;
+
+    use crate::materials::Lambert;
+    use crate::vector::Color;
+
+    // `u` runs along y and `v` along x, the other way round from barycentrics
+    let triangle = Triangle::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(2.0, 0.0, 0.0),
+        Point3D::new(0.0, 2.0, 0.0),
+        Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5))),
+    )
+    .with_uvs((0.0, 0.0), (0.0, 1.0), (1.0, 0.0));
+
+    let ray = Ray::new(
+        Point3D::new(0.5, 1.0, 1.0),
+        Vector3D::new(0.0, 0.0, -1.0),
+        0.0,
+    );
+    let mut rec = HitRecord::default();
+    assert!(triangle.hit(&ray, 0.001, N::MAX, &mut rec));
+    assert!((rec.u - 0.5).abs() < 1e-6);
+    assert!((rec.v - 0.25).abs() < 1e-6);
+    assert!((rec.tangent - Vector3D::new(0.0, 1.0, 0.0)).length() < 1e-6);
+}
@@ -0,0 +1,51 @@
+use super::Integrator;
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::utils::random_unit_vector;
+use crate::vector::{Color, N};
+
+/// White where the surface sees the open sky, darkening where nearby
+/// geometry blocks it. Ignores materials and lights.
+pub struct AmbientOcclusion {
+    /// How far away geometry still blocks the sky
+    distance: N,
+    samples: usize,
+}
+
+impl AmbientOcclusion {
+    pub fn new(distance: N, samples: usize) -> Self {
+        Self {
+            distance,
+            samples: samples.max(1),
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        // Directions picked proportional to the cosine with the normal, which
+        // leaves just the fraction of them that get out
+        let mut unoccluded = 0;
+        for _ in 0..self.samples {
+            let mut direction = hit_record.normal + random_unit_vector();
+            if direction.near_zero() {
+                direction = hit_record.normal;
+            }
+            let occlusion_ray = hit_record.spawn_ray(direction.unit(), *ray.time());
+            let mut blocker = HitRecord::default();
+            if !scene
+                .world
+                .hit(&occlusion_ray, T_MIN, self.distance, &mut blocker)
+            {
+                unoccluded += 1;
+            }
+        }
+        let visibility = unoccluded as N / self.samples as N;
+        Color::new(visibility, visibility, visibility)
+    }
+
+    fn miss(&self, _: &Ray, _: &Scene) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+}
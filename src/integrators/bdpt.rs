@@ -0,0 +1,558 @@
+use super::integrator::survives_roulette;
+use super::{lighting, Integrator};
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{offset_ray_origin, Ray, T_MIN};
+use crate::scene::Scene;
+use crate::utils::PI;
+use crate::vector::{Color, Point3D, Vector3D, N};
+
+// Shadow rays between two vertices stop this fraction of the way short
+const SHADOW_EPSILON: N = 1e-4;
+
+/// Bidirectional path tracer. Each camera sample also traces a path from a
+/// random point on an emissive object, and every vertex of one is connected
+/// to every vertex of the other, weighting the ways of building the same path
+/// with multiple importance sampling. Connections from light paths straight
+/// to the camera land on other pixels, so they are splatted onto a film.
+///
+/// Lights without geometry and the background can't start paths, so they are
+/// only found from the camera side, as by `PathTracer`.
+pub struct Bdpt {
+    camera: Camera,
+    film: Film,
+    // Area of the plane in focus that camera rays are spread over
+    film_area: N,
+}
+
+impl Bdpt {
+    /// Renders what `camera` sees to images of `width` by `height` pixels
+    pub fn new(camera: Camera, width: usize, height: usize) -> Self {
+        // Pixel `i` covers [i, i + 1] / (width - 1) of what `get_ray` takes
+        let film_area = camera.viewport_area()
+            * (width as N / (width - 1) as N)
+            * (height as N / (height - 1) as N);
+        Self {
+            camera,
+            film: Film::new(width, height),
+            film_area,
+        }
+    }
+
+    // Pixel the camera ray from `lens_point` along `direction` falls in, and
+    // the cosine of its angle with the view direction
+    fn raster(&self, lens_point: &Point3D, direction: &Vector3D) -> Option<(usize, N)> {
+        let (s, t, cos_theta) = self.camera.film_coordinates(lens_point, direction)?;
+        let (width, height) = (self.film.width(), self.film.height());
+        let x = s * (width - 1) as N;
+        let y = t * (height - 1) as N;
+        if !(0.0..width as N).contains(&x) || !(0.0..height as N).contains(&y) {
+            return None;
+        }
+        Some(((height - 1 - y as usize) * width + x as usize, cos_theta))
+    }
+
+    // Density per unit solid angle of camera rays from `lens_point` leaving
+    // along `direction`. Also the camera's importance times that cosine.
+    fn camera_pdf(&self, lens_point: &Point3D, direction: &Vector3D) -> N {
+        match self.raster(lens_point, direction) {
+            Some((_, cos_theta)) => {
+                self.camera.focus_distance().powi(2) / (cos_theta.powi(3) * self.film_area)
+            }
+            None => 0.0,
+        }
+    }
+
+    // Density per unit area of `vertex` picking `next`, having been reached
+    // from `prev`
+    fn pdf(&self, vertex: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> N {
+        let to_next = next.rec.p - vertex.rec.p;
+        let pdf = match vertex.kind {
+            Kind::Camera => self.camera_pdf(&vertex.rec.p, &to_next),
+            Kind::Light => return pdf_light(vertex, next),
+            Kind::Surface => match prev {
+                Some(prev) => {
+                    let wo = (prev.rec.p - vertex.rec.p).unit();
+                    vertex.rec.material.pdf(&vertex.rec, &to_next.unit(), &wo)
+                }
+                None => return 0.0,
+            },
+        };
+        to_area(pdf, vertex, next)
+    }
+
+    // Follow `ray`, which the last vertex of `path` picked with density `pdf`
+    // per unit solid angle, adding a vertex for every surface it scatters off.
+    // Camera paths also gather the light from lights without geometry and
+    // the background on the way, which is returned.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        scene: &Scene,
+        mut ray: Ray,
+        mut first_hit: Option<HitRecord>,
+        mut beta: Color,
+        mut pdf: N,
+        max_vertices: usize,
+        from_camera: bool,
+        path: &mut Vec<Vertex>,
+    ) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut bsdf_pdf = None;
+
+        while path.len() < max_vertices {
+            // Connections pass through interfaces, so paths don't stop at them
+            let mut rec = HitRecord::default();
+            let hit = match first_hit.take() {
+                Some(first_hit) => {
+                    rec = first_hit;
+                    true
+                }
+                None => scene.world.hit(&ray, T_MIN, N::MAX, &mut rec),
+            };
+            if !(hit && lighting::pass_interfaces(scene, &mut ray, &mut rec)) {
+                if from_camera {
+                    color += beta * lighting::escaped(&ray, scene, bsdf_pdf);
+                }
+                break;
+            }
+
+            let prev = path.len() - 1;
+            let mut vertex = Vertex {
+                kind: Kind::Surface,
+                rec,
+                beta,
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            vertex.pdf_fwd = to_area(pdf, &path[prev], &vertex);
+
+            let material = vertex.rec.material.clone();
+            let mut scattered = Ray::default();
+            let mut attenuation = Color::default();
+            if !material.scatter(&ray, &mut vertex.rec, &mut attenuation, &mut scattered) {
+                path.push(vertex);
+                break;
+            }
+
+            let wo = -ray.direction().unit();
+            let wi = scattered.direction().unit();
+            let pdf_rev = if material.is_delta() {
+                vertex.delta = true;
+                pdf = 0.0;
+                bsdf_pdf = None;
+                0.0
+            } else {
+                if from_camera {
+                    color += beta * lighting::sample_lights(&ray, scene, &vertex.rec, &wo);
+                }
+                pdf = material.pdf(&vertex.rec, &wi, &wo);
+                bsdf_pdf = Some(pdf);
+                material.pdf(&vertex.rec, &wo, &wi)
+            };
+            path[prev].pdf_rev = to_area(pdf_rev, &vertex, &path[prev]);
+            path.push(vertex);
+
+            beta *= attenuation;
+            ray = scattered;
+            if !survives_roulette(scene, prev, &mut beta) {
+                break;
+            }
+        }
+        color
+    }
+
+    // Light reaching the camera along the path of the first `s` vertices of
+    // the light path and first `t` of the camera path, at least two
+    fn connect(
+        &self,
+        scene: &Scene,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+        time: N,
+    ) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let pt = &camera_path[t - 1];
+        if s == 0 {
+            // The camera path found a light on its own
+            if !pt.rec.material.is_emissive() {
+                return black;
+            }
+            let emitted = pt.beta * pt.rec.material.emitted(&pt.rec);
+            return emitted * self.mis_weight(scene, light_path, camera_path, None, 0, t);
+        }
+        if pt.delta {
+            return black;
+        }
+
+        // A single light vertex is picked anew for every connection
+        let sampled;
+        let qs = if s == 1 {
+            match lighting::sample_light_origin(scene, time) {
+                Some((rec, pdf)) => {
+                    sampled = Vertex::light(rec, pdf);
+                    &sampled
+                }
+                None => return black,
+            }
+        } else {
+            &light_path[s - 1]
+        };
+        if qs.delta {
+            return black;
+        }
+
+        let qs_minus = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+        let distance_sq = (qs.rec.p - pt.rec.p).length_sq();
+        let contribution =
+            qs.beta * qs.f(qs_minus, pt) * pt.f(Some(&camera_path[t - 2]), qs) * pt.beta
+                / distance_sq;
+        if contribution.near_zero() || !unoccluded(scene, pt, qs, time) {
+            return black;
+        }
+        let sampled = if s == 1 { Some(qs) } else { None };
+        contribution * self.mis_weight(scene, light_path, camera_path, sampled, s, t)
+    }
+
+    // Add the light the first `s` vertices of the light path send straight to
+    // a random point on the lens to the pixel it lands in
+    fn splat(
+        &self,
+        scene: &Scene,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        time: N,
+    ) {
+        let qs = &light_path[s - 1];
+        if qs.delta {
+            return;
+        }
+        let camera = Vertex::camera(self.camera.sample_lens());
+        let to_camera = camera.rec.p - qs.rec.p;
+        let index = match self.raster(&camera.rec.p, &-to_camera) {
+            Some((index, _)) => index,
+            None => return,
+        };
+
+        // The camera's importance times the cosines at the lens, over the
+        // density of the lens point, is the density of camera rays
+        let qs_minus = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+        let contribution = qs.beta
+            * qs.f(qs_minus, &camera)
+            * (self.camera_pdf(&camera.rec.p, &-to_camera) / to_camera.length_sq());
+        if contribution.near_zero() || !unoccluded(scene, qs, &camera, time) {
+            return;
+        }
+        let weight = self.mis_weight(scene, light_path, camera_path, Some(&camera), s, 1);
+        self.film.add(index, contribution * weight);
+    }
+
+    // Power heuristic weight of the strategy using `s` light vertices and `t`
+    // camera vertices, against every other way of making the same path.
+    // `sampled` stands in for the first vertex of a subpath of length 1.
+    fn mis_weight(
+        &self,
+        scene: &Scene,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+    ) -> N {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let light_vertex = |i: usize| match sampled {
+            Some(vertex) if s == 1 && i == 0 => vertex,
+            _ => &light_path[i],
+        };
+        let camera_vertex = |i: usize| match sampled {
+            Some(vertex) if t == 1 && i == 0 => vertex,
+            _ => &camera_path[i],
+        };
+        let densities = |vertex: &Vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.delta);
+        let mut light: Vec<_> = (0..s).map(|i| densities(light_vertex(i))).collect();
+        let mut camera: Vec<_> = (0..t).map(|i| densities(camera_vertex(i))).collect();
+
+        // The densities of sampling the vertices around the connection the
+        // other way, which the subpaths didn't know about
+        let pt = camera_vertex(t - 1);
+        let pt_minus = if t > 1 {
+            Some(camera_vertex(t - 2))
+        } else {
+            None
+        };
+        let qs = if s > 0 {
+            Some(light_vertex(s - 1))
+        } else {
+            None
+        };
+        let qs_minus = if s > 1 {
+            Some(light_vertex(s - 2))
+        } else {
+            None
+        };
+        camera[t - 1].1 = match qs {
+            Some(qs) => self.pdf(qs, qs_minus, pt),
+            None => lighting::pdf_light_origin(scene, &pt.rec),
+        };
+        camera[t - 1].2 = false;
+        if let Some(pt_minus) = pt_minus {
+            camera[t - 2].1 = match qs {
+                Some(qs) => self.pdf(pt, Some(qs), pt_minus),
+                None => pdf_light(pt, pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light[s - 1].1 = self.pdf(pt, pt_minus, qs);
+            light[s - 1].2 = false;
+            if let Some(qs_minus) = qs_minus {
+                light[s - 2].1 = self.pdf(qs, Some(pt), qs_minus);
+            }
+        }
+
+        // Walk the connection towards either end, getting the ratio of each
+        // strategy's density to this one's. Delta vertices can't be connected.
+        let remap = |pdf: N| if pdf == 0.0 { 1.0 } else { pdf };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].1) / remap(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 {
+                sum += ratio * ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            let after_delta = i > 0 && light[i - 1].2;
+            if !light[i].2 && !after_delta {
+                sum += ratio * ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+
+    fn trace(&self, ray: &Ray, scene: &Scene, first_hit: Option<&HitRecord>) -> Color {
+        let time = *ray.time();
+        let max_depth = scene.max_depth;
+
+        let mut camera_path = vec![Vertex::camera(*ray.origin())];
+        let mut color = match first_hit {
+            Some(rec) => self.random_walk(
+                scene,
+                *ray,
+                Some(rec.clone()),
+                Color::new(1.0, 1.0, 1.0),
+                self.camera_pdf(ray.origin(), ray.direction()),
+                max_depth + 1,
+                true,
+                &mut camera_path,
+            ),
+            None => lighting::escaped(ray, scene, None),
+        };
+
+        let mut light_path = Vec::new();
+        if let Some((mut rec, pdf_pos)) = lighting::sample_light_origin(scene, time) {
+            let (direction, pdf_dir) = lighting::sample_emission(&mut rec);
+            let normal = rec.normal;
+            let light = Vertex::light(rec, pdf_pos);
+            let ray = light.rec.spawn_ray(direction, time);
+            let beta = light.beta * (normal.dot(&direction) / pdf_dir);
+            light_path.push(light);
+            self.random_walk(
+                scene,
+                ray,
+                None,
+                beta,
+                pdf_dir,
+                max_depth,
+                false,
+                &mut light_path,
+            );
+        }
+
+        // Paths hit at most `max_depth` surfaces, counting the light's. Lights
+        // seen directly are left to the camera path alone.
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 1 > max_depth {
+                    continue;
+                }
+                if t == 1 {
+                    self.splat(scene, &light_path, &camera_path, s, time);
+                } else {
+                    color += self.connect(scene, &light_path, &camera_path, s, t, time);
+                }
+            }
+        }
+        color
+    }
+}
+
+impl Integrator for Bdpt {
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        self.trace(ray, scene, Some(hit_record))
+    }
+
+    // Light paths are traced for every camera sample, even ones that miss
+    fn miss(&self, ray: &Ray, scene: &Scene) -> Color {
+        self.trace(ray, scene, None)
+    }
+
+    fn film(&self) -> Option<&Film> {
+        Some(&self.film)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+struct Vertex {
+    kind: Kind,
+    rec: HitRecord,
+    // Light or importance carried to here, over the density of the subpath
+    beta: Color,
+    // Whether the material only scatters in a finite set of directions
+    delta: bool,
+    // Density per unit area of sampling this vertex from the one before it on
+    // its subpath, and from the one after it
+    pdf_fwd: N,
+    pdf_rev: N,
+}
+
+impl Vertex {
+    fn camera(lens_point: Point3D) -> Self {
+        Self {
+            kind: Kind::Camera,
+            rec: HitRecord {
+                p: lens_point,
+                ..HitRecord::default()
+            },
+            beta: Color::new(1.0, 1.0, 1.0),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    // A point on an emissive object, picked with density `pdf` per unit area
+    fn light(rec: HitRecord, pdf: N) -> Self {
+        Self {
+            kind: Kind::Light,
+            beta: rec.material.emitted(&rec) / pdf,
+            rec,
+            delta: false,
+            pdf_fwd: pdf,
+            pdf_rev: 0.0,
+        }
+    }
+
+    // Fraction of what arrives from `prev` that leaves towards `next`, times
+    // the cosine on the side of `next`. Lights emit the same in every
+    // direction, which their `beta` already holds.
+    fn f(&self, prev: Option<&Vertex>, next: &Vertex) -> Color {
+        let wi = (next.rec.p - self.rec.p).unit();
+        match (self.kind, prev) {
+            (Kind::Light, _) => {
+                let cosine = self.rec.normal.dot(&wi).abs();
+                Color::new(cosine, cosine, cosine)
+            }
+            (Kind::Surface, Some(prev)) => {
+                let wo = (prev.rec.p - self.rec.p).unit();
+                self.rec.material.eval(&self.rec, &wi, &wo)
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Convert a density per unit solid angle at `from` to one per unit area at `to`
+fn to_area(pdf: N, from: &Vertex, to: &Vertex) -> N {
+    let w = to.rec.p - from.rec.p;
+    let distance_sq = w.length_sq();
+    if distance_sq == 0.0 {
+        return 0.0;
+    }
+    let pdf = pdf / distance_sq;
+    // The lens isn't a surface facing any particular way
+    if to.kind == Kind::Camera {
+        pdf
+    } else {
+        pdf * to.rec.normal.dot(&w).abs() / distance_sq.sqrt()
+    }
+}
+
+// Density per unit area of light leaving `light`, a vertex on an emissive
+// object, towards `next`
+fn pdf_light(light: &Vertex, next: &Vertex) -> N {
+    let w = (next.rec.p - light.rec.p).unit();
+    to_area(light.rec.normal.dot(&w).abs() / (2.0 * PI), light, next)
+}
+
+// Whether nothing but interfaces lies between two vertices
+fn unoccluded(scene: &Scene, a: &Vertex, b: &Vertex, time: N) -> bool {
+    let (a, b) = (&a.rec, &b.rec);
+    let origin = offset_ray_origin(&a.p, &a.p_error, &a.normal, &(b.p - a.p));
+    let target = offset_ray_origin(&b.p, &b.p_error, &b.normal, &(origin - b.p));
+    let mut ray = Ray::new(origin, target - origin, time);
+    let mut t_max = 1.0 - SHADOW_EPSILON;
+    loop {
+        let mut rec = HitRecord::default();
+        if !scene.world.hit(&ray, T_MIN, t_max, &mut rec) {
+            return true;
+        }
+        if !rec.material.is_interface() {
+            return false;
+        }
+        t_max -= rec.t;
+        ray = rec.spawn_ray(*ray.direction(), time);
+    }
+}
+
+#[test]
+fn agrees_with_path_tracer() {
+    use crate::utils::random_n;
+
+    // Seen through a lens
+    let (width, height) = (16, 12);
+    let (scene, camera) = super::test_scene(width, height, 0.1);
+    let bdpt = Bdpt::new(camera.clone(), width, height);
+
+    // Total light over the image
+    let samples = 64;
+    let mut path_traced = 0.0;
+    let mut bidirectional = 0.0;
+    for j in 0..height {
+        for i in 0..width {
+            for _ in 0..samples {
+                let u = (i as N + random_n()) / (width - 1) as N;
+                let v = (j as N + random_n()) / (height - 1) as N;
+                let ray = camera.get_ray(u, v);
+                path_traced += super::PathTracer.color(&ray, &scene).luminance();
+                bidirectional += bdpt.color(&ray, &scene).luminance();
+            }
+        }
+    }
+    for index in 0..width * height {
+        bidirectional += bdpt.film.get(index).luminance();
+    }
+    assert!((bidirectional / path_traced - 1.0).abs() < 0.03);
+}
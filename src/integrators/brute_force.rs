@@ -0,0 +1,42 @@
+use super::integrator::survives_roulette;
+use super::Integrator;
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::vector::{Color, N};
+
+/// Path tracer which only follows the directions materials pick, so light is
+/// only found by paths happening to hit it. Slow to converge, but simple enough
+/// to check the others against.
+pub struct BruteForce;
+
+impl Integrator for BruteForce {
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for depth in 0..scene.max_depth {
+            if depth > 0 && !scene.world.hit(&ray, T_MIN, N::MAX, hit_record) {
+                color += throughput * ray.background(scene);
+                break;
+            }
+
+            let material = hit_record.material.clone();
+            color += throughput * material.emitted(hit_record);
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Color::default();
+            if !material.scatter(&ray, hit_record, &mut attenuation, &mut scattered) {
+                break;
+            }
+            throughput *= attenuation;
+            ray = scattered;
+
+            if !survives_roulette(scene, depth, &mut throughput) {
+                break;
+            }
+        }
+        color
+    }
+}
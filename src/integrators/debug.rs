@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use super::Integrator;
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::vector::{Color, N};
+
+/// Something about the first hit of camera rays to show instead of lighting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugView {
+    /// Shading normals, mapped from [-1, 1] to [0, 1]
+    Normals,
+    /// Distance to the hit, from white up close to black at the given distance
+    Depth(N),
+    /// Base color of the material
+    Albedo,
+    /// Surface coordinates as red and green
+    Uv,
+    /// A different color for every primitive
+    ObjectId,
+    /// A different color for every material
+    MaterialId,
+    /// Heatmap of ray-box tests done to find the hit, red at the given count
+    BoxTests(u64),
+}
+
+pub struct DebugIntegrator {
+    view: DebugView,
+}
+
+impl DebugIntegrator {
+    pub fn new(view: DebugView) -> Self {
+        Self { view }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        match self.view {
+            DebugView::Normals => (hit_record.normal + Color::new(1.0, 1.0, 1.0)) * 0.5,
+            DebugView::Depth(max_distance) => {
+                let distance = hit_record.t * ray.direction().length();
+                let shade = 1.0 - (distance / max_distance).min(1.0);
+                Color::new(shade, shade, shade)
+            }
+            DebugView::Albedo => hit_record.material.albedo(hit_record),
+            DebugView::Uv => Color::new(hit_record.u, hit_record.v, 0.0),
+            DebugView::ObjectId => false_color(hit_record.object_id),
+            DebugView::MaterialId => {
+                false_color(Arc::as_ptr(&hit_record.material) as *const () as usize)
+            }
+            DebugView::BoxTests(max_tests) => box_test_heat(ray, scene, max_tests),
+        }
+    }
+
+    fn miss(&self, ray: &Ray, scene: &Scene) -> Color {
+        match self.view {
+            DebugView::BoxTests(max_tests) => box_test_heat(ray, scene, max_tests),
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Traces the ray again on its own, counting the boxes it tests
+fn box_test_heat(ray: &Ray, scene: &Scene, max_tests: u64) -> Color {
+    let mut box_tests = 0;
+    scene.world.hit_counted(
+        ray,
+        T_MIN,
+        N::MAX,
+        &mut HitRecord::default(),
+        &mut box_tests,
+    );
+    heat(box_tests as N / max_tests.max(1) as N)
+}
+
+// Blue through green and yellow to red as `x` goes from 0 to 1
+fn heat(x: N) -> Color {
+    let x = x.clamp(0.0, 1.0);
+    let channel = |center: N| (1.5 - (4.0 * x - center).abs()).clamp(0.0, 1.0);
+    Color::new(channel(3.0), channel(2.0), channel(1.0))
+}
+
+// Scrambles an ID into a bright color, so that nearby IDs look different
+fn false_color(id: usize) -> Color {
+    let hash = (id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let channel = |shift: u32| 0.2 + 0.8 * ((hash >> shift) & 0xFF) as N / 255.0;
+    Color::new(channel(40), channel(48), channel(56))
+}
+
+#[test]
+fn box_tests_count_traversal() {
+    use crate::hittables::{Hittables, Sphere};
+    use crate::materials::Lambert;
+    use crate::vector::{Point3D, Vector3D};
+
+    let material = Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5)));
+    let mut objects = Hittables::new();
+    for i in 0..64 {
+        objects.add(Arc::new(Sphere::new(
+            Point3D::new(i as N * 3.0, 0.0, 0.0),
+            1.0,
+            material.clone(),
+        )));
+    }
+    let scene = Scene::new(objects);
+
+    // Along the row of spheres through the corners of their boxes, missing
+    // every sphere, visits every box. Across it, most subtrees are skipped.
+    let along = Ray::new(
+        Point3D::new(-5.0, 0.9, 0.9),
+        Vector3D::new(1.0, 0.0, 0.0),
+        0.0,
+    );
+    let across = Ray::new(
+        Point3D::new(90.0, 5.0, 0.0),
+        Vector3D::new(0.0, -1.0, 0.0),
+        0.0,
+    );
+    let box_tests = |ray: &Ray| {
+        let mut box_tests = 0;
+        scene.world.hit_counted(
+            ray,
+            T_MIN,
+            N::MAX,
+            &mut HitRecord::default(),
+            &mut box_tests,
+        );
+        box_tests
+    };
+    let (along_tests, across_tests) = (box_tests(&along), box_tests(&across));
+    assert!(across_tests > 0);
+    assert!(along_tests >= 2 * across_tests);
+    // One box for each of the 63 nodes of the binary tree over 64 spheres
+    #[cfg(not(feature = "simd"))]
+    assert_eq!(along_tests, 63);
+}
@@ -0,0 +1,95 @@
+use super::{lighting, Integrator};
+use crate::aov::Aovs;
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::vector::{Color, N};
+
+/// Only light which reaches the camera after one bounce off a non-delta
+/// surface. Mirrors and glass in between are followed.
+pub struct DirectLighting;
+
+impl DirectLighting {
+    // The light sampled at the first hit is `direct` if it was sampled already
+    fn trace(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        direct: Option<Color>,
+    ) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for depth in 0..scene.max_depth {
+            if depth > 0 && !scene.world.hit(&ray, T_MIN, N::MAX, hit_record) {
+                color += throughput * ray.background(scene);
+                break;
+            }
+
+            let material = hit_record.material.clone();
+            color += throughput * material.emitted(hit_record);
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Color::default();
+            if !material.scatter(&ray, hit_record, &mut attenuation, &mut scattered) {
+                break;
+            }
+            if material.is_delta() {
+                throughput *= attenuation;
+                ray = scattered;
+                continue;
+            }
+
+            // Sample the lights, and add what the material's own sample finds
+            // of them
+            let wo = -ray.direction().unit();
+            let wi = scattered.direction().unit();
+            let bsdf_pdf = Some(material.pdf(hit_record, &wi, &wo));
+            color += throughput
+                * direct
+                    .filter(|_| depth == 0)
+                    .unwrap_or_else(|| lighting::sample_direct(&ray, scene, hit_record, &wo));
+
+            // Shadow rays pass through interfaces, so this does too
+            throughput *= attenuation;
+            let mut light_ray = scattered;
+            let mut light_record = HitRecord::default();
+            color += throughput
+                * if scene
+                    .world
+                    .hit(&light_ray, T_MIN, N::MAX, &mut light_record)
+                    && lighting::pass_interfaces(scene, &mut light_ray, &mut light_record)
+                {
+                    lighting::emitted(&scattered, scene, &light_record, bsdf_pdf)
+                } else {
+                    lighting::escaped(&scattered, scene, bsdf_pdf)
+                };
+            break;
+        }
+        color
+    }
+}
+
+impl Integrator for DirectLighting {
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        self.trace(ray, scene, hit_record, None)
+    }
+
+    fn takes_direct(&self) -> bool {
+        true
+    }
+
+    fn shade_direct_aovs(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        direct: Color,
+        aovs: &mut Aovs,
+    ) -> Color {
+        aovs.record_hit(ray, hit_record);
+        self.trace(ray, scene, hit_record, Some(direct))
+    }
+}
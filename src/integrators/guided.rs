@@ -0,0 +1,280 @@
+use std::cell::RefCell;
+
+use super::integrator::survives_roulette;
+use super::sd_tree::SdTree;
+use super::{lighting, Integrator};
+use crate::hittables::{HitRecord, Hittable, AABB};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::utils::random_n;
+use crate::vector::{Color, Point3D, Vector3D, N};
+
+// Chance of following the learnt distribution rather than the material's at
+// a surface that has one
+const GUIDE_FRACTION: N = 0.5;
+
+thread_local! {
+    // What this thread has learnt of where light comes from
+    static GUIDE: RefCell<Option<Guide>> = const { RefCell::new(None) };
+}
+
+/// `PathTracer` which learns where light arrives from as it renders, and
+/// guides paths there. Every pass over the image records the light each
+/// bounce found into an `SdTree`, which the next pass samples directions
+/// from, mixed with the materials' own sampling. Paths lit through a few
+/// bounces off bright spots, which the materials rarely pick, converge much
+/// faster once the tree has found them.
+///
+/// Every rendering thread learns on its own, starting out as a plain path
+/// tracer.
+pub struct GuidedPathTracer;
+
+struct Guide {
+    owner: usize,
+    tree: SdTree,
+}
+
+// A bounce that picked its next direction from a mix with the guide
+struct Vertex {
+    p: Point3D,
+    wi: Vector3D,
+    // Throughput of the path after the bounce, and the density of the
+    // direction picked
+    throughput: Color,
+    pdf: N,
+    // Light found arriving along `wi`, weighted by `throughput`
+    radiance: Color,
+}
+
+impl GuidedPathTracer {
+    // Key for what this integrator learns, as opposed to another on the same
+    // thread
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn trace(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        tree: &mut SdTree,
+    ) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut bsdf_pdf = None;
+        // Where the path's direction was last picked, which passing straight
+        // through interfaces doesn't change
+        let mut origin = *ray.origin();
+        let mut vertices: Vec<Vertex> = Vec::new();
+        // Light reaching the camera also arrives at every bounce before
+        let add = |light: Color, color: &mut Color, vertices: &mut Vec<Vertex>| {
+            *color += light;
+            for vertex in vertices.iter_mut() {
+                vertex.radiance += light;
+            }
+        };
+
+        for depth in 0..scene.max_depth {
+            // Shadow rays pass through interfaces, so light found beyond them
+            // is weighted as seen from where its direction was picked
+            let hit = (depth == 0 || scene.world.hit(&ray, T_MIN, N::MAX, hit_record))
+                && lighting::pass_interfaces(scene, &mut ray, hit_record);
+            let from_origin = Ray::new(origin, *ray.direction(), *ray.time());
+            if !hit {
+                let light = throughput * lighting::escaped(&from_origin, scene, bsdf_pdf);
+                add(light, &mut color, &mut vertices);
+                break;
+            }
+            let light = throughput * lighting::emitted(&from_origin, scene, hit_record, bsdf_pdf);
+            add(light, &mut color, &mut vertices);
+
+            let material = hit_record.material.clone();
+            let mut scattered = Ray::default();
+            let mut attenuation = Color::default();
+            if material.is_delta() {
+                if !material.scatter(&ray, hit_record, &mut attenuation, &mut scattered) {
+                    break;
+                }
+                bsdf_pdf = None;
+                throughput *= attenuation;
+                ray = scattered;
+            } else {
+                // Untrained regions sample the material alone, as `PathTracer` does
+                let wo = -ray.direction().unit();
+                let leaf = tree.leaf(&hit_record.p);
+                let trained = tree.is_trained(leaf);
+                let mixed_pdf = |hit_record: &HitRecord, wi: &Vector3D| {
+                    let material_pdf = material.pdf(hit_record, wi, &wo);
+                    if trained {
+                        GUIDE_FRACTION * tree.pdf(leaf, wi) + (1.0 - GUIDE_FRACTION) * material_pdf
+                    } else {
+                        material_pdf
+                    }
+                };
+                let light = throughput
+                    * lighting::sample_direct_against(&ray, scene, hit_record, &wo, &|wi| {
+                        mixed_pdf(hit_record, wi)
+                    });
+                add(light, &mut color, &mut vertices);
+
+                let wi = if trained && random_n() < GUIDE_FRACTION {
+                    tree.sample(leaf)
+                } else {
+                    if !material.scatter(&ray, hit_record, &mut attenuation, &mut scattered) {
+                        break;
+                    }
+                    scattered.direction().unit()
+                };
+                let pdf = mixed_pdf(hit_record, &wi);
+                if pdf <= 0.0 {
+                    break;
+                }
+                if trained {
+                    attenuation = material.eval(hit_record, &wi, &wo) / pdf;
+                    scattered = hit_record.spawn_ray(wi, *ray.time());
+                }
+
+                bsdf_pdf = Some(pdf);
+                throughput *= attenuation;
+                ray = scattered;
+                vertices.push(Vertex {
+                    p: hit_record.p,
+                    wi: ray.direction().unit(),
+                    throughput,
+                    pdf,
+                    radiance: Color::new(0.0, 0.0, 0.0),
+                });
+            }
+            origin = hit_record.p;
+
+            if !survives_roulette(scene, depth, &mut throughput) {
+                break;
+            }
+        }
+
+        for vertex in vertices {
+            let radiance = unweighted(vertex.radiance, vertex.throughput);
+            tree.record(&vertex.p, &vertex.wi, radiance.luminance() / vertex.pdf);
+        }
+        color
+    }
+
+    // Tree covering the scene, padded so flat scenes have some depth
+    fn new_tree(scene: &Scene) -> SdTree {
+        let mut bounds = AABB::default();
+        scene.world.bounding_box(0.0, 0.0, &mut bounds);
+        let padding = Vector3D::new(1e-3, 1e-3, 1e-3);
+        SdTree::new(AABB::new(bounds.min() - padding, bounds.max() + padding))
+    }
+}
+
+impl Integrator for GuidedPathTracer {
+    // Sample what the last pass learnt, and learn anew
+    fn begin_pass(&self, scene: &Scene) {
+        GUIDE.with(|guide| {
+            let mut guide = guide.borrow_mut();
+            match guide.as_mut() {
+                Some(guide) if guide.owner == self.id() => guide.tree.refine(),
+                _ => {
+                    *guide = Some(Guide {
+                        owner: self.id(),
+                        tree: Self::new_tree(scene),
+                    })
+                }
+            }
+        });
+    }
+
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        GUIDE.with(|guide| {
+            let mut guide = guide.borrow_mut();
+            // For callers that don't make passes
+            if !matches!(guide.as_ref(), Some(guide) if guide.owner == self.id()) {
+                *guide = Some(Guide {
+                    owner: self.id(),
+                    tree: Self::new_tree(scene),
+                });
+            }
+            let tree = &mut guide.as_mut().unwrap().tree;
+            self.trace(ray, scene, hit_record, tree)
+        })
+    }
+}
+
+// Divide `light` by `throughput`, component by component, leaving out the
+// components no light passes through
+fn unweighted(light: Color, throughput: Color) -> Color {
+    let divide = |light: N, throughput: N| {
+        if throughput > 0.0 {
+            light / throughput
+        } else {
+            0.0
+        }
+    };
+    Color::new(
+        divide(*light.x(), *throughput.x()),
+        divide(*light.y(), *throughput.y()),
+        divide(*light.z(), *throughput.z()),
+    )
+}
+
+#[test]
+fn guiding_reduces_variance() {
+    use super::PathTracer;
+    use crate::backgrounds::Black;
+    use crate::hittables::{Hittables, Quad};
+    use crate::lights::PointLight;
+    use crate::materials::Lambert;
+    use std::sync::Arc;
+
+    // A floor lit from a small patch on the ceiling, which a point light just
+    // below makes bright. The floor's own sampling rarely finds the patch.
+    let gray = Arc::new(Lambert::new(Color::new(0.8, 0.8, 0.8)));
+    let mut objects = Hittables::new();
+    objects.add(Arc::new(Quad::new(
+        Point3D::new(-2.0, 0.0, -2.0),
+        Vector3D::new(4.0, 0.0, 0.0),
+        Vector3D::new(0.0, 0.0, 4.0),
+        gray.clone(),
+    )));
+    objects.add(Arc::new(Quad::new(
+        Point3D::new(0.9, 2.0, -0.1),
+        Vector3D::new(0.2, 0.0, 0.0),
+        Vector3D::new(0.0, 0.0, 0.2),
+        gray,
+    )));
+    let mut scene = Scene::new(objects);
+    scene.add_light(Arc::new(PointLight::new(
+        Point3D::new(1.0, 1.9, 0.0),
+        Color::new(1.0, 1.0, 1.0),
+    )));
+    scene.background = Arc::new(Black);
+
+    let ray = Ray::new(
+        Point3D::new(0.0, 1.0, 0.0),
+        Vector3D::new(0.0, -1.0, 0.0),
+        0.0,
+    );
+    // Mean and variance of single samples
+    let estimate = |integrator: &dyn Integrator| {
+        let samples = 20_000;
+        let values: Vec<N> = (0..samples)
+            .map(|_| integrator.color(&ray, &scene).luminance())
+            .collect();
+        let mean = values.iter().sum::<N>() / samples as N;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<N>() / samples as N;
+        (mean, variance)
+    };
+
+    let guided = GuidedPathTracer;
+    let mut learnt = (0.0, 0.0);
+    for _ in 0..4 {
+        guided.begin_pass(&scene);
+        learnt = estimate(&guided);
+    }
+    let unguided = estimate(&PathTracer);
+    assert!((learnt.0 / unguided.0 - 1.0).abs() < 0.2);
+    assert!(learnt.1 < 0.25 * unguided.1);
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use crate::aov::Aovs;
+use crate::film::Film;
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::utils::random_n;
+use crate::vector::{Color, N};
+
+pub type SharedIntegrator = Arc<dyn Integrator + Sync + Send>;
+
+/// Computes the light arriving at the camera along camera rays
+pub trait Integrator {
+    fn color(&self, ray: &Ray, scene: &Scene) -> Color {
+        let mut hit_record = HitRecord::default();
+        if scene.world.hit(ray, T_MIN, N::MAX, &mut hit_record) {
+            self.shade(ray, scene, &mut hit_record)
+        } else {
+            self.miss(ray, scene)
+        }
+    }
+
+    /// Color of a camera ray whose closest hit has already been found
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color;
+
+    /// Color of a camera ray that hits nothing
+    fn miss(&self, ray: &Ray, scene: &Scene) -> Color {
+        ray.background(scene)
+    }
+
+    /// Called on each rendering thread before every pass it makes over the
+    /// image, in which each pixel gets one sample
+    fn begin_pass(&self, _scene: &Scene) {}
+
+    /// Light the integrator adds to the image itself, rather than returning
+    /// it for the pixel of the camera ray it traced. Scaled like the colors it
+    /// returns, so divided by the samples per pixel in the end.
+    fn film(&self) -> Option<&Film> {
+        None
+    }
+
+    /// `color`, also filling in `aovs` for the sample
+    fn color_aovs(&self, ray: &Ray, scene: &Scene, aovs: &mut Aovs) -> Color {
+        let mut hit_record = HitRecord::default();
+        if scene.world.hit(ray, T_MIN, N::MAX, &mut hit_record) {
+            self.shade_aovs(ray, scene, &mut hit_record, aovs)
+        } else {
+            self.miss_aovs(ray, scene, aovs)
+        }
+    }
+
+    /// `shade`, also filling in `aovs`. Only the surface ones unless
+    /// overridden, since that needs to know where the light comes from.
+    fn shade_aovs(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        aovs: &mut Aovs,
+    ) -> Color {
+        aovs.record_hit(ray, hit_record);
+        self.shade(ray, scene, hit_record)
+    }
+
+    /// Whether `shade_direct_aovs` takes the light sampled straight from the
+    /// lights at the hit, letting packets of camera rays trace their shadow
+    /// rays together
+    fn takes_direct(&self) -> bool {
+        false
+    }
+
+    /// `shade_aovs` for a hit at which `direct` is what `sample_direct_packet`
+    /// sampled of the light arriving straight from the lights
+    fn shade_direct_aovs(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        _direct: Color,
+        aovs: &mut Aovs,
+    ) -> Color {
+        self.shade_aovs(ray, scene, hit_record, aovs)
+    }
+
+    /// `miss`, also filling in `aovs`
+    fn miss_aovs(&self, ray: &Ray, scene: &Scene, _aovs: &mut Aovs) -> Color {
+        self.miss(ray, scene)
+    }
+}
+
+/// Past the scene's minimum depth, end dim paths at random, and make up for
+/// it in the ones that survive by scaling up their throughput
+pub(super) fn survives_roulette(scene: &Scene, depth: usize, throughput: &mut Color) -> bool {
+    if depth + 1 < scene.min_depth {
+        return true;
+    }
+    let survival = throughput.max_element().min(1.0);
+    if random_n() >= survival {
+        return false;
+    }
+    *throughput /= survival;
+    true
+}
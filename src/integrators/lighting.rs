@@ -0,0 +1,485 @@
+use crate::hittables::{HitRecord, Hittable};
+use crate::lights::Light;
+use crate::packet::{lanes, RayPacket, PACKET_SIZE};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::utils;
+use crate::vector::{Color, Point3D, Vector3D, N};
+
+// Shared by the integrators that sample lights directly. `bsdf_pdf` is the
+// density with which the previous bounce's material picked a ray, if its
+// lights were also sampled directly. Light found by the ray is then weighted
+// against the chance of the light sampling having found it.
+
+/// Light given off by the surface a ray hit
+pub(super) fn emitted(
+    ray: &Ray,
+    scene: &Scene,
+    hit_record: &HitRecord,
+    bsdf_pdf: Option<N>,
+) -> Color {
+    let material = &hit_record.material;
+    let emitted = material.emitted(hit_record);
+    match bsdf_pdf {
+        Some(bsdf_pdf) if material.is_emissive() => {
+            let light_pdf = scene
+                .light_tree
+                .pdf(ray.origin(), ray.direction(), *ray.time());
+            emitted * utils::power_heuristic(bsdf_pdf, light_pdf)
+        }
+        _ => emitted,
+    }
+}
+
+/// Carries `ray` on in the same direction past the interfaces bounding media,
+/// which shadow rays pass through too, until `hit_record` holds some other
+/// surface it hits. Returns whether there is one.
+pub(super) fn pass_interfaces(scene: &Scene, ray: &mut Ray, hit_record: &mut HitRecord) -> bool {
+    while hit_record.material.is_interface() {
+        *ray = hit_record.spawn_ray(*ray.direction(), *ray.time());
+        if !scene.world.hit(ray, T_MIN, N::MAX, hit_record) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Light arriving along a ray that hit nothing
+pub(super) fn escaped(ray: &Ray, scene: &Scene, bsdf_pdf: Option<N>) -> Color {
+    let background = ray.background(scene);
+    match bsdf_pdf {
+        Some(bsdf_pdf) => {
+            let light_pdf = scene.background.pdf_li(ray.direction());
+            background * utils::power_heuristic(bsdf_pdf, light_pdf)
+        }
+        None => background,
+    }
+}
+
+/// Light arriving straight from the scene's lights and background at the hit
+/// point of `ray`, leaving towards `wo`
+pub(super) fn sample_direct(
+    ray: &Ray,
+    scene: &Scene,
+    hit_record: &HitRecord,
+    wo: &Vector3D,
+) -> Color {
+    let pdf = |wi: &Vector3D| hit_record.material.pdf(hit_record, wi, wo);
+    sample_direct_against(ray, scene, hit_record, wo, &pdf)
+}
+
+/// `sample_direct` for integrators that pick the next direction otherwise
+/// than the material does, weighting against the density `pdf` of that
+pub(super) fn sample_direct_against(
+    ray: &Ray,
+    scene: &Scene,
+    hit_record: &HitRecord,
+    wo: &Vector3D,
+    pdf: &dyn Fn(&Vector3D) -> N,
+) -> Color {
+    let time = *ray.time();
+    let f = |wi: &Vector3D| hit_record.material.eval(hit_record, wi, wo);
+    let shadow = |wi: &Vector3D, distance: N| surface_shadow(scene, hit_record, wi, distance, time);
+    let receiver = Receiver {
+        p: hit_record.p,
+        f: &f,
+        pdf,
+        shadow: &shadow,
+    };
+    sample_direct_at(scene, &receiver, time)
+}
+
+/// `sample_direct` without the emissive objects, for integrators that find
+/// those some other way
+pub(super) fn sample_lights(
+    ray: &Ray,
+    scene: &Scene,
+    hit_record: &HitRecord,
+    wo: &Vector3D,
+) -> Color {
+    let time = *ray.time();
+    let f = |wi: &Vector3D| hit_record.material.eval(hit_record, wi, wo);
+    let pdf = |wi: &Vector3D| hit_record.material.pdf(hit_record, wi, wo);
+    let shadow = |wi: &Vector3D, distance: N| surface_shadow(scene, hit_record, wi, distance, time);
+    let receiver = Receiver {
+        p: hit_record.p,
+        f: &f,
+        pdf: &pdf,
+        shadow: &shadow,
+    };
+    let mut color = Color::new(0.0, 0.0, 0.0);
+    for source in 1..sources(scene) {
+        color += sample_source(scene, &receiver, time, source);
+    }
+    color
+}
+
+/// Fraction of light getting through along a shadow ray, and the surface that
+/// stops it if any, whose `t` is its distance from the start of the shadow ray
+pub(super) type Shadow = (Color, Option<HitRecord>);
+
+/// A point gathering light straight from the scene's lights, for integrators
+/// whose paths don't only scatter off surfaces
+pub(super) struct Receiver<'a> {
+    pub p: Point3D,
+    /// Light scattered on towards the camera for each unit arriving from `wi`
+    pub f: &'a dyn Fn(&Vector3D) -> Color,
+    /// Density of the path's own sampling picking `wi`, to weight against
+    pub pdf: &'a dyn Fn(&Vector3D) -> N,
+    /// What gets to the point along a shadow ray leaving in `wi`, up to
+    /// `distance`
+    pub shadow: &'a dyn Fn(&Vector3D, N) -> Shadow,
+}
+
+/// Light arriving straight from the scene's lights and background at the
+/// receiver
+pub(super) fn sample_direct_at(scene: &Scene, receiver: &Receiver, time: N) -> Color {
+    let mut color = Color::new(0.0, 0.0, 0.0);
+    for source in 0..sources(scene) {
+        color += sample_source(scene, receiver, time, source);
+    }
+    color
+}
+
+/// `sample_direct` at the first hits `recs` of the lanes `active` of a packet
+/// of camera rays, added to `direct`. The lanes' shadow rays towards each
+/// source of light are traced together.
+pub(crate) fn sample_direct_packet(
+    scene: &Scene,
+    packet: &RayPacket,
+    active: u64,
+    recs: &[HitRecord],
+    direct: &mut [Color; PACKET_SIZE],
+) {
+    let white = Color::new(1.0, 1.0, 1.0);
+    let mut tests = [None; PACKET_SIZE];
+    let mut shadows = RayPacket::new();
+    let mut blockers = vec![HitRecord::default(); PACKET_SIZE];
+
+    for source in 0..sources(scene) {
+        shadows.active = 0;
+        let mut t_max = [N::MAX; PACKET_SIZE];
+        for lane in lanes(active) {
+            let (ray, rec) = (&packet.rays[lane], &recs[lane]);
+            let time = *ray.time();
+            let wo = -ray.direction().unit();
+            let f = |wi: &Vector3D| rec.material.eval(rec, wi, &wo);
+            let pdf = |wi: &Vector3D| rec.material.pdf(rec, wi, &wo);
+            let shadow =
+                |wi: &Vector3D, distance: N| surface_shadow(scene, rec, wi, distance, time);
+            let receiver = Receiver {
+                p: rec.p,
+                f: &f,
+                pdf: &pdf,
+                shadow: &shadow,
+            };
+            tests[lane] = shadow_test(scene, &receiver, time, source);
+            if let Some(test) = &tests[lane] {
+                shadows.set(lane, rec.spawn_ray(test.wi, time));
+                t_max[lane] = test.distance;
+            }
+        }
+        if shadows.active == 0 {
+            continue;
+        }
+
+        // Only the emissive objects need to know what stops a shadow ray
+        let stopped = if source == 0 {
+            scene
+                .world
+                .hit_packet(&shadows, shadows.active, T_MIN, &mut t_max, &mut blockers)
+        } else {
+            scene
+                .world
+                .occluded_packet(&shadows, shadows.active, T_MIN, &t_max)
+        };
+        for lane in lanes(shadows.active) {
+            let test = match &tests[lane] {
+                Some(test) => test,
+                None => continue,
+            };
+            let shadow = if stopped & (1 << lane) == 0 {
+                (white, None)
+            } else if source == 0 && !blockers[lane].material.is_interface() {
+                (white, Some(blockers[lane].clone()))
+            } else {
+                // Only a ray of its own follows on through interfaces
+                let time = *packet.rays[lane].time();
+                surface_shadow(scene, &recs[lane], &test.wi, test.distance, time)
+            };
+            direct[lane] += test.resolve(shadow);
+        }
+    }
+}
+
+/// Random point on a random emissive object, and its density per unit area
+pub(super) fn sample_light_origin(scene: &Scene, time: N) -> Option<(HitRecord, N)> {
+    if scene.emitters.is_empty() {
+        return None;
+    }
+    let count = scene.emitters.len();
+    let emitter = scene
+        .emitters
+        .iter()
+        .nth(utils::random_int_range(0, count))?;
+    let mut rec = HitRecord::default();
+    if !emitter.sample_surface(time, &mut rec) {
+        return None;
+    }
+    Some((rec, 1.0 / (count as N * emitter.area())))
+}
+
+/// Density of `sample_light_origin` picking the point of an emissive object
+/// in `rec`
+pub(super) fn pdf_light_origin(scene: &Scene, rec: &HitRecord) -> N {
+    match scene.emitter_areas.get(&rec.object_id) {
+        Some(&area) if area > 0.0 => 1.0 / (scene.emitters.len() as N * area),
+        _ => 0.0,
+    }
+}
+
+/// Random direction for light to leave the emissive surface in `rec`, and
+/// its density per unit solid angle. Emissive objects give off light evenly
+/// from both sides, so `rec.normal` is flipped to face the side picked.
+pub(super) fn sample_emission(rec: &mut HitRecord) -> (Vector3D, N) {
+    if utils::random_n() < 0.5 {
+        rec.normal = -rec.normal;
+    }
+    let mut direction = rec.normal + utils::random_unit_vector();
+    if direction.near_zero() {
+        direction = rec.normal;
+    }
+    let direction = direction.unit();
+    (direction, rec.normal.dot(&direction) / (2.0 * utils::PI))
+}
+
+// Shadow ray leaving a surface, stopped by the first thing it hits. Media
+// are ignored here, so the invisible surfaces bounding them let it through.
+// `wi` has to be a unit vector for the lengths of the segments between them
+// to add up to `distance`.
+fn surface_shadow(
+    scene: &Scene,
+    hit_record: &HitRecord,
+    wi: &Vector3D,
+    mut distance: N,
+    time: N,
+) -> Shadow {
+    let mut shadow = hit_record.spawn_ray(*wi, time);
+    let white = Color::new(1.0, 1.0, 1.0);
+    let mut travelled = 0.0;
+    loop {
+        let mut blocker = HitRecord::default();
+        if !scene.world.hit(&shadow, T_MIN, distance, &mut blocker) {
+            return (white, None);
+        }
+        if !blocker.material.is_interface() {
+            blocker.t += travelled;
+            return (white, Some(blocker));
+        }
+        travelled += blocker.t;
+        distance -= blocker.t;
+        shadow = blocker.spawn_ray(*wi, time);
+    }
+}
+
+// Light is sampled from a number of sources in turn: the emissive objects
+// first, then each light without geometry, then the background
+fn sources(scene: &Scene) -> usize {
+    scene.lights.len() + 2
+}
+
+// Light arriving straight from one source
+fn sample_source(scene: &Scene, receiver: &Receiver, time: N, source: usize) -> Color {
+    match shadow_test(scene, receiver, time, source) {
+        Some(test) => test.resolve((receiver.shadow)(&test.wi, test.distance)),
+        None => Color::new(0.0, 0.0, 0.0),
+    }
+}
+
+// The shadow ray a sample of a source needs traced, if the source has any
+// light to give the receiver
+fn shadow_test(scene: &Scene, receiver: &Receiver, time: N, source: usize) -> Option<ShadowTest> {
+    match source {
+        0 if scene.emitters.is_empty() => None,
+        0 => emitter_test(scene, receiver, time),
+        source if source <= scene.lights.len() => {
+            light_test(scene.lights[source - 1].as_ref(), receiver)
+        }
+        _ => background_test(scene, receiver),
+    }
+}
+
+// A shadow ray leaving the receiver in `wi`, up to `distance`, and what it
+// brings. Emissive objects give the light of whichever one the ray reaches
+// first, scaled by `light`, and other sources `light` if nothing stops it.
+#[derive(Clone, Copy)]
+struct ShadowTest {
+    wi: Vector3D,
+    distance: N,
+    light: Color,
+    emitter: bool,
+}
+
+impl ShadowTest {
+    fn resolve(&self, (transmittance, blocker): Shadow) -> Color {
+        match blocker {
+            Some(blocker) if self.emitter => {
+                blocker.material.emitted(&blocker) * transmittance * self.light
+            }
+            None if !self.emitter => self.light * transmittance,
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Shadow ray towards a random point on one of the emissive objects, weighted
+// against the chance of the path's own sampling having picked the same
+// direction
+fn emitter_test(scene: &Scene, receiver: &Receiver, time: N) -> Option<ShadowTest> {
+    let wi = scene.light_tree.sample(&receiver.p, time).unit();
+    let light_pdf = scene.light_tree.pdf(&receiver.p, &wi, time);
+    if light_pdf <= 0.0 {
+        return None;
+    }
+    let f = (receiver.f)(&wi);
+    if f.near_zero() {
+        return None;
+    }
+
+    // Whichever emitter the shadow ray reaches first is what arrives from
+    // `wi`, and `light_pdf` already accounts for every emitter along it
+    let weight = utils::power_heuristic(light_pdf, (receiver.pdf)(&wi));
+    Some(ShadowTest {
+        wi,
+        distance: N::MAX,
+        light: f * (weight / light_pdf),
+        emitter: true,
+    })
+}
+
+// Shadow ray towards a light without geometry. Rays can't hit these, so
+// there's nothing to weight against.
+fn light_test(light: &dyn Light, receiver: &Receiver) -> Option<ShadowTest> {
+    let sample = light.sample_li(&receiver.p)?;
+    let f = (receiver.f)(&sample.wi);
+    if f.near_zero() {
+        return None;
+    }
+    Some(ShadowTest {
+        wi: sample.wi,
+        distance: sample.distance,
+        light: sample.radiance * f / sample.pdf,
+        emitter: false,
+    })
+}
+
+// Shadow ray towards the background, weighted against the chance of the
+// path's own sampling having picked the same direction and escaped
+fn background_test(scene: &Scene, receiver: &Receiver) -> Option<ShadowTest> {
+    let sample = scene.background.sample_li()?;
+    let f = (receiver.f)(&sample.wi);
+    if f.near_zero() {
+        return None;
+    }
+    let weight = utils::power_heuristic(sample.pdf, (receiver.pdf)(&sample.wi));
+    Some(ShadowTest {
+        wi: sample.wi,
+        distance: N::MAX,
+        light: sample.radiance * f * (weight / sample.pdf),
+        emitter: false,
+    })
+}
+
+#[test]
+fn shadow_rays_pass_through_medium_bounds() {
+    use std::sync::Arc;
+
+    use crate::hittables::{Hittables, Sphere};
+    use crate::materials::{Interface, Lambert};
+    use crate::media::Homogeneous;
+
+    // A smoke ball between the receiver and a wall behind it
+    let black = Color::new(0.0, 0.0, 0.0);
+    let gray = Color::new(0.5, 0.5, 0.5);
+    let smoke = Arc::new(Homogeneous::new(gray, black, 0.0));
+    let mut objects = Hittables::new();
+    objects.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 0.0, 3.0),
+        1.0,
+        Arc::new(Interface::new(smoke)),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 0.0, 106.0),
+        100.0,
+        Arc::new(Lambert::new(gray)),
+    )));
+    let scene = Scene::new(objects);
+
+    let hit_record = HitRecord {
+        normal: Vector3D::new(0.0, 0.0, 1.0),
+        front_face: true,
+        ..Default::default()
+    };
+    let wi = Vector3D::new(0.0, 0.0, 1.0);
+    let (_, blocker) = surface_shadow(&scene, &hit_record, &wi, 5.0, 0.0);
+    assert!(blocker.is_none());
+    let (_, blocker) = surface_shadow(&scene, &hit_record, &wi, 10.0, 0.0);
+    assert!((blocker.unwrap().t - 6.0).abs() < 1e-3);
+}
+
+#[test]
+fn packet_shadows_match_single_rays() {
+    use std::sync::Arc;
+
+    use crate::backgrounds::Constant;
+    use crate::hittables::Sphere;
+    use crate::lights::PointLight;
+    use crate::materials::Interface;
+    use crate::media::Homogeneous;
+
+    // Every kind of source, with some shadow rays passing through an
+    // interface and others blocked
+    let (width, height) = (8, 8);
+    let (mut scene, camera) = super::test_scene(width, height, 0.0);
+    scene.background = Arc::new(Constant::new(Color::new(0.2, 0.3, 0.4)));
+    scene.add_light(Arc::new(PointLight::new(
+        Point3D::new(1.0, 2.0, 1.0),
+        Color::new(2.0, 2.0, 2.0),
+    )));
+    scene.world.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 2.5, 0.0),
+        1.0,
+        Arc::new(Interface::new(Arc::new(Homogeneous::vacuum()))),
+    )));
+
+    let mut packet = RayPacket::new();
+    for lane in 0..PACKET_SIZE {
+        let u = (lane % width) as N / (width - 1) as N;
+        let v = (lane / width) as N / (height - 1) as N;
+        packet.set(lane, camera.get_ray(u, v));
+    }
+    let mut t_max = [N::MAX; PACKET_SIZE];
+    let mut recs = vec![HitRecord::default(); PACKET_SIZE];
+    let hits = scene
+        .world
+        .hit_packet(&packet, packet.active, T_MIN, &mut t_max, &mut recs);
+    let lit = lanes(hits)
+        .filter(|&lane| !recs[lane].material.is_delta())
+        .fold(0, |lit, lane| lit | 1 << lane);
+    assert_ne!(lit, 0);
+
+    let samples = 4_000;
+    let mut together = [Color::new(0.0, 0.0, 0.0); PACKET_SIZE];
+    let mut alone = Color::new(0.0, 0.0, 0.0);
+    for _ in 0..samples {
+        sample_direct_packet(&scene, &packet, lit, &recs, &mut together);
+        for lane in lanes(lit) {
+            let ray = &packet.rays[lane];
+            alone += sample_direct(ray, &scene, &recs[lane], &-ray.direction().unit());
+        }
+    }
+    let together = together
+        .iter()
+        .fold(Color::new(0.0, 0.0, 0.0), |sum, &color| sum + color);
+    assert!((together.luminance() / alone.luminance() - 1.0).abs() < 0.02);
+}
@@ -0,0 +1,336 @@
+use rand::Rng;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{Integrator, PathTracer};
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittables::HitRecord;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::utils::{self, Sampler, PI};
+use crate::vector::{Color, N};
+
+thread_local! {
+    // The Markov chain this thread is running
+    static CHAIN: RefCell<Option<Chain>> = const { RefCell::new(None) };
+}
+
+/// Primary sample space Metropolis light transport. Rather than tracing
+/// independent paths, every camera sample mutates the random numbers that
+/// drove the last path a path tracer took, either slightly or by drawing
+/// new ones, and keeps the new path with a chance that makes paths come up
+/// as often as they are bright. Light that only a few paths find, through
+/// small gaps or off caustics, is explored once one is found. Paths land on
+/// any pixel, so they are splatted onto a film, and the image is scaled by
+/// the average brightness found by independent paths beforehand.
+///
+/// Every rendering thread runs a chain of its own, one mutation per sample.
+pub struct Mlt {
+    camera: Camera,
+    film: Film,
+    // Independent paths traced to find the image's brightness and a path to
+    // start from
+    bootstrap_samples: usize,
+    // Chance of a mutation drawing all new random numbers
+    large_step_probability: N,
+    // Standard deviation of the small steps
+    sigma: N,
+}
+
+impl Mlt {
+    /// Renders what `camera` sees to images of `width` by `height` pixels
+    pub fn new(camera: Camera, width: usize, height: usize, bootstrap_samples: usize) -> Self {
+        Self {
+            camera,
+            film: Film::new(width, height),
+            bootstrap_samples,
+            large_step_probability: 0.3,
+            sigma: 0.01,
+        }
+    }
+
+    // Key for the chain this integrator runs, as opposed to another on the
+    // same thread
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // Trace a path with the random numbers `sampler` gives, returning the
+    // pixel it lands on and the light it carries
+    fn path(&self, scene: &Scene, sampler: &Rc<RefCell<MltSampler>>) -> (usize, Color) {
+        utils::with_sampler(Box::new(SharedSampler(Rc::clone(sampler))), || {
+            // Pixel `i` covers [i, i + 1] / (width - 1) of what `get_ray` takes
+            let (width, height) = (self.film.width(), self.film.height());
+            let s = utils::random_n() * width as N / (width - 1) as N;
+            let t = utils::random_n() * height as N / (height - 1) as N;
+            let i = ((s * (width - 1) as N) as usize).min(width - 1);
+            let j = ((t * (height - 1) as N) as usize).min(height - 1);
+            let color = PathTracer.color(&self.camera.get_ray(s, t), scene);
+            ((height - 1 - j) * width + i, color)
+        })
+    }
+
+    // Trace independent paths, and start a chain from one of them picked in
+    // proportion to its brightness
+    fn bootstrap(&self, scene: &Scene) -> Chain {
+        let mut chosen = None;
+        let mut total = 0.0;
+        for _ in 0..self.bootstrap_samples {
+            let sampler = Rc::new(RefCell::new(MltSampler::new(
+                self.large_step_probability,
+                self.sigma,
+            )));
+            let (pixel, color) = self.path(scene, &sampler);
+            let luminance = color.luminance();
+            if luminance <= 0.0 || !luminance.is_finite() {
+                continue;
+            }
+            // Keeping each with the chance of its share of the brightness so
+            // far leaves every one with its share of the total in the end
+            total += luminance;
+            if rand::thread_rng().gen::<N>() * total < luminance {
+                chosen = Some((sampler, pixel, color));
+            }
+        }
+
+        let (sampler, pixel, color) = chosen.unwrap_or_else(|| {
+            let sampler = MltSampler::new(self.large_step_probability, self.sigma);
+            (Rc::new(RefCell::new(sampler)), 0, Color::new(0.0, 0.0, 0.0))
+        });
+        sampler.borrow_mut().accept();
+        Chain {
+            owner: self.id(),
+            brightness: total / self.bootstrap_samples.max(1) as N,
+            sampler,
+            pixel,
+            color,
+        }
+    }
+
+    // Advance this thread's chain by one mutation, splatting the expected
+    // contributions of both the current and the proposed path
+    fn mutate(&self, scene: &Scene) {
+        CHAIN.with(|chain| {
+            let mut chain = chain.borrow_mut();
+            if !matches!(chain.as_ref(), Some(chain) if chain.owner == self.id()) {
+                *chain = Some(self.bootstrap(scene));
+            }
+            let chain = chain.as_mut().unwrap();
+            if chain.brightness == 0.0 {
+                return;
+            }
+
+            chain.sampler.borrow_mut().start_iteration();
+            let (pixel, color) = self.path(scene, &chain.sampler);
+            let luminance = color.luminance();
+            let current = chain.color.luminance();
+            let accept = if !luminance.is_finite() {
+                0.0
+            } else if current > 0.0 {
+                (luminance / current).min(1.0)
+            } else {
+                1.0
+            };
+
+            // Each splat carries the image's brightness, spread over paths
+            // in proportion to how bright they are
+            if accept > 0.0 {
+                self.film
+                    .add(pixel, color * (accept * chain.brightness / luminance));
+            }
+            if accept < 1.0 {
+                self.film.add(
+                    chain.pixel,
+                    chain.color * ((1.0 - accept) * chain.brightness / current),
+                );
+            }
+
+            if rand::thread_rng().gen::<N>() < accept {
+                chain.pixel = pixel;
+                chain.color = color;
+                chain.sampler.borrow_mut().accept();
+            } else {
+                chain.sampler.borrow_mut().reject();
+            }
+        })
+    }
+}
+
+impl Integrator for Mlt {
+    // The chain picks its own camera rays, so the ones given are only a count
+    // of how many mutations to make
+    fn shade(&self, _ray: &Ray, scene: &Scene, _hit_record: &mut HitRecord) -> Color {
+        self.mutate(scene);
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    fn miss(&self, _ray: &Ray, scene: &Scene) -> Color {
+        self.mutate(scene);
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    fn film(&self) -> Option<&Film> {
+        Some(&self.film)
+    }
+}
+
+struct Chain {
+    owner: usize,
+    // Average luminance of the image
+    brightness: N,
+    sampler: Rc<RefCell<MltSampler>>,
+    // Where the current path lands and what it carries
+    pixel: usize,
+    color: Color,
+}
+
+// One of the random numbers a path is traced with, and what it was before
+// the mutation being tried
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: N,
+    // Iteration it was last mutated in
+    modified: usize,
+    backup_value: N,
+    backup_modified: usize,
+}
+
+// The random numbers of the current path, mutated lazily as a path asks for
+// them. Its own randomness comes straight from the thread's generator, since
+// it is the one drawing numbers for everything else.
+struct MltSampler {
+    samples: Vec<PrimarySample>,
+    // Next one to hand out
+    index: usize,
+    iteration: usize,
+    large_step: bool,
+    last_large_step: usize,
+    large_step_probability: N,
+    sigma: N,
+}
+
+impl MltSampler {
+    // Starts out drawing all new numbers
+    fn new(large_step_probability: N, sigma: N) -> Self {
+        Self {
+            samples: Vec::new(),
+            index: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            large_step_probability,
+            sigma,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = rand::thread_rng().gen::<N>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    // Keep the proposed numbers
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    // Go back to the numbers from before the iteration
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup_value;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn next_n(&mut self) -> N {
+        let mut rng = rand::thread_rng();
+        // Numbers a path hasn't asked for before start out uniform, as if set
+        // by the last large step. Starting them at 0 would leave them near 0
+        // after small steps, and rejection sampling drawing on them might
+        // never finish.
+        if self.index == self.samples.len() {
+            self.samples.push(PrimarySample {
+                value: rng.gen(),
+                modified: self.last_large_step,
+                ..Default::default()
+            });
+        }
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        // Numbers not used since the last large step that was kept were
+        // replaced by it
+        if sample.modified < self.last_large_step {
+            sample.value = rng.gen();
+            sample.modified = self.last_large_step;
+        }
+
+        sample.backup_value = sample.value;
+        sample.backup_modified = sample.modified;
+        if self.large_step {
+            sample.value = rng.gen();
+        } else {
+            // All the small steps missed since it was last used, at once
+            let steps = (self.iteration - sample.modified) as N;
+            let u1 = 1.0 - rng.gen::<N>();
+            let u2 = rng.gen::<N>();
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+            sample.value += normal * self.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+            // Wrapping can round up to 1
+            if sample.value >= 1.0 {
+                sample.value = 0.0;
+            }
+        }
+        sample.modified = self.iteration;
+        sample.value
+    }
+}
+
+// Lets the chain keep hold of its sampler while paths draw from it
+struct SharedSampler(Rc<RefCell<MltSampler>>);
+
+impl Sampler for SharedSampler {
+    fn next_n(&mut self) -> N {
+        self.0.borrow_mut().next_n()
+    }
+}
+
+#[test]
+fn agrees_with_path_tracer() {
+    let (width, height) = (16, 12);
+    let (scene, camera) = super::test_scene(width, height, 0.1);
+    let mlt = Mlt::new(camera.clone(), width, height, 10_000);
+
+    // Light in the left and right halves of the image
+    let samples = 512;
+    let mut path_traced = [0.0; 2];
+    let mut metropolis = [0.0; 2];
+    for j in 0..height {
+        for i in 0..width {
+            for _ in 0..samples {
+                let u = (i as N + utils::random_n()) / (width - 1) as N;
+                let v = (j as N + utils::random_n()) / (height - 1) as N;
+                let ray = camera.get_ray(u, v);
+                path_traced[2 * i / width] += PathTracer.color(&ray, &scene).luminance();
+                mlt.color(&ray, &scene);
+            }
+        }
+    }
+    for j in 0..height {
+        for i in 0..width {
+            metropolis[2 * i / width] += mlt.film.get(j * width + i).luminance();
+        }
+    }
+    for half in 0..2 {
+        assert!((metropolis[half] / path_traced[half] - 1.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,78 @@
+mod ambient_occlusion;
+mod bdpt;
+mod brute_force;
+mod debug;
+mod direct;
+mod guided;
+mod integrator;
+mod lighting;
+mod mlt;
+mod path;
+mod photon_mapping;
+mod sd_tree;
+mod volumetric;
+
+pub use ambient_occlusion::*;
+pub use bdpt::*;
+pub use brute_force::*;
+pub use debug::*;
+pub use direct::*;
+pub use guided::*;
+pub use integrator::*;
+pub(crate) use lighting::sample_direct_packet;
+pub use mlt::*;
+pub use path::*;
+pub use photon_mapping::*;
+pub use volumetric::*;
+
+/// A sphere on a floor lit by a small light out of view, and a camera on it
+/// for a `width` by `height` image, for comparing integrators
+#[cfg(test)]
+fn test_scene(
+    width: usize,
+    height: usize,
+    aperture: crate::vector::N,
+) -> (crate::scene::Scene, crate::camera::Camera) {
+    use crate::backgrounds::Black;
+    use crate::camera::Camera;
+    use crate::hittables::{Hittables, Quad, Sphere};
+    use crate::materials::{DiffuseLight, Lambert};
+    use crate::scene::Scene;
+    use crate::vector::{Color, Point3D, Vector3D, N};
+    use std::sync::Arc;
+
+    let gray = Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5)));
+    let mut objects = Hittables::new();
+    objects.add(Arc::new(Quad::new(
+        Point3D::new(-2.0, 0.0, -2.0),
+        Vector3D::new(4.0, 0.0, 0.0),
+        Vector3D::new(0.0, 0.0, 4.0),
+        gray.clone(),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 0.5, 0.0),
+        0.5,
+        gray,
+    )));
+    objects.add(Arc::new(Quad::new(
+        Point3D::new(-0.5, 2.5, -0.5),
+        Vector3D::new(1.0, 0.0, 0.0),
+        Vector3D::new(0.0, 0.0, 1.0),
+        Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+    )));
+    let mut scene = Scene::new(objects);
+    scene.background = Arc::new(Black);
+
+    let camera = Camera::new(
+        Point3D::new(0.0, 1.0, 4.0),
+        Point3D::new(0.0, 0.3, 0.0),
+        Vector3D::new(0.0, 1.0, 0.0),
+        40.0,
+        width as N / height as N,
+        aperture,
+        4.0,
+        None,
+        None,
+    );
+    (scene, camera)
+}
@@ -0,0 +1,251 @@
+use super::integrator::survives_roulette;
+use super::{lighting, Integrator};
+use crate::aov::Aovs;
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::vector::{Color, N};
+
+/// Path tracer which samples lights directly at every bounce, combining that
+/// with the materials' own sampling through multiple importance sampling
+pub struct PathTracer;
+
+impl PathTracer {
+    /// Follows the path the ray starts until it escapes, is absorbed, reaches
+    /// the scene's max depth or is ended by Russian roulette. The light
+    /// sampled at the first hit is `direct` if it was sampled already.
+    fn trace(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        direct: Option<Color>,
+        aovs: &mut Aovs,
+    ) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        // Fraction of light arriving along `ray` that reaches the camera
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut bsdf_pdf = None;
+        // Where the path's direction was last picked, which passing straight
+        // through interfaces doesn't change
+        let mut origin = *ray.origin();
+        // Whether the first bounce is off a specular surface
+        let mut specular = false;
+        let mut add = |bounces: usize, specular: bool, light: Color| {
+            color += light;
+            aovs.add_light(bounces, specular, light);
+        };
+
+        for depth in 0..scene.max_depth {
+            // Shadow rays pass through interfaces, so light found beyond them
+            // is weighted as seen from where its direction was picked
+            let hit = (depth == 0 || scene.world.hit(&ray, T_MIN, N::MAX, hit_record))
+                && lighting::pass_interfaces(scene, &mut ray, hit_record);
+            let from_origin = Ray::new(origin, *ray.direction(), *ray.time());
+            if !hit {
+                add(
+                    depth,
+                    specular,
+                    throughput * lighting::escaped(&from_origin, scene, bsdf_pdf),
+                );
+                break;
+            }
+            add(
+                depth,
+                specular,
+                throughput * lighting::emitted(&from_origin, scene, hit_record, bsdf_pdf),
+            );
+
+            let material = hit_record.material.clone();
+            let mut scattered = Ray::default();
+            let mut attenuation = Color::default();
+            if !material.scatter(&ray, hit_record, &mut attenuation, &mut scattered) {
+                break;
+            }
+            if depth == 0 {
+                specular = material.is_specular();
+            }
+
+            bsdf_pdf = if material.is_delta() {
+                None
+            } else {
+                let wo = -ray.direction().unit();
+                let wi = scattered.direction().unit();
+                let light = direct
+                    .filter(|_| depth == 0)
+                    .unwrap_or_else(|| lighting::sample_direct(&ray, scene, hit_record, &wo));
+                add(depth + 1, specular, throughput * light);
+                Some(material.pdf(hit_record, &wi, &wo))
+            };
+            origin = hit_record.p;
+            throughput *= attenuation;
+            ray = scattered;
+
+            if !survives_roulette(scene, depth, &mut throughput) {
+                break;
+            }
+        }
+        color
+    }
+}
+
+impl Integrator for PathTracer {
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        self.trace(ray, scene, hit_record, None, &mut Aovs::default())
+    }
+
+    fn shade_aovs(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        aovs: &mut Aovs,
+    ) -> Color {
+        aovs.record_hit(ray, hit_record);
+        self.trace(ray, scene, hit_record, None, aovs)
+    }
+
+    fn takes_direct(&self) -> bool {
+        true
+    }
+
+    fn shade_direct_aovs(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        hit_record: &mut HitRecord,
+        direct: Color,
+        aovs: &mut Aovs,
+    ) -> Color {
+        aovs.record_hit(ray, hit_record);
+        self.trace(ray, scene, hit_record, Some(direct), aovs)
+    }
+
+    fn miss_aovs(&self, ray: &Ray, scene: &Scene, aovs: &mut Aovs) -> Color {
+        let color = self.miss(ray, scene);
+        aovs.add_light(0, false, color);
+        color
+    }
+}
+
+#[test]
+fn integrators_agree_with_russian_roulette() {
+    use std::sync::Arc;
+
+    use crate::backgrounds::Constant;
+    use crate::hittables::{Hittables, Sphere};
+    use crate::materials::Lambert;
+    use crate::vector::{Point3D, Vector3D};
+
+    // Every bounce off a convex object escapes, so the sphere reflects exactly
+    // its albedo of a white background
+    let mut objects = Hittables::new();
+    objects.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+    let mut scene = Scene::new(objects);
+    scene.background = Arc::new(Constant::new(Color::new(1.0, 1.0, 1.0)));
+    scene.min_depth = 0;
+
+    let samples = 40_000;
+    let ray = Ray::new(
+        Point3D::new(0.0, 0.0, 5.0),
+        Vector3D::new(0.0, 0.0, -1.0),
+        0.0,
+    );
+    let integrators = [
+        &PathTracer as &dyn Integrator,
+        &super::BruteForce,
+        &super::DirectLighting,
+    ];
+    for integrator in integrators.iter() {
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            sum += integrator.color(&ray, &scene);
+        }
+        assert!((sum / samples as N - Color::new(0.5, 0.5, 0.5)).length() < 0.02);
+    }
+}
+
+#[test]
+fn light_aovs_add_up_to_the_color() {
+    use std::sync::Arc;
+
+    use crate::backgrounds::Constant;
+    use crate::hittables::{Hittables, Sphere};
+    use crate::materials::Lambert;
+    use crate::vector::{Point3D, Vector3D};
+
+    // Light bouncing off a convex object only ever does so once
+    let mut objects = Hittables::new();
+    objects.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+    let mut scene = Scene::new(objects);
+    scene.background = Arc::new(Constant::new(Color::new(1.0, 1.0, 1.0)));
+
+    let ray = Ray::new(
+        Point3D::new(0.0, 0.0, 5.0),
+        Vector3D::new(0.0, 0.0, -1.0),
+        0.0,
+    );
+    for _ in 0..100 {
+        let mut aovs = Aovs::default();
+        let color = PathTracer.color_aovs(&ray, &scene, &mut aovs);
+        assert!((aovs.direct_diffuse - color).length() < 1e-4);
+        assert_eq!(aovs.indirect_diffuse, Color::new(0.0, 0.0, 0.0));
+        assert!((aovs.depth - 4.0).abs() < 1e-4);
+        assert!((aovs.normal - Vector3D::new(0.0, 0.0, 1.0)).length() < 1e-4);
+    }
+}
+
+#[test]
+fn empty_interfaces_change_nothing() {
+    use std::sync::Arc;
+
+    use crate::hittables::Sphere;
+    use crate::materials::Interface;
+    use crate::media::Homogeneous;
+    use crate::utils::random_n;
+    use crate::vector::Point3D;
+
+    // Shadow rays pass through the bounds of a medium around the light, so
+    // paths have to as well for light found both ways to be weighted right
+    let (width, height) = (16, 12);
+    let (scene, camera) = super::test_scene(width, height, 0.0);
+    let (mut bounded, _) = super::test_scene(width, height, 0.0);
+    bounded.world.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 2.5, 0.0),
+        1.0,
+        Arc::new(Interface::new(Arc::new(Homogeneous::vacuum()))),
+    )));
+
+    // Total light over the image
+    let total = |integrator: &dyn Integrator, scene: &Scene| {
+        let mut total = 0.0;
+        for j in 0..height {
+            for i in 0..width {
+                for _ in 0..16 {
+                    let u = (i as N + random_n()) / (width - 1) as N;
+                    let v = (j as N + random_n()) / (height - 1) as N;
+                    total += integrator.color(&camera.get_ray(u, v), scene).luminance();
+                }
+            }
+        }
+        total
+    };
+    let integrators = [
+        &PathTracer as &dyn Integrator,
+        &super::GuidedPathTracer,
+        &super::DirectLighting,
+    ];
+    for integrator in integrators.iter() {
+        let ratio = total(*integrator, &bounded) / total(*integrator, &scene);
+        assert!((ratio - 1.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,339 @@
+use std::cell::RefCell;
+
+use super::integrator::survives_roulette;
+use super::{lighting, Integrator};
+use crate::hittables::{HitRecord, Hittable};
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::utils::PI;
+use crate::vector::{Color, Point3D, Vector3D, N};
+
+// Photons only count towards surfaces facing about the same way as the one
+// they landed on, so light doesn't leak through thin walls
+const MIN_NORMAL_COSINE: N = 0.9;
+
+thread_local! {
+    // Photons shot for the pass this thread is making over the image
+    static PASS: RefCell<Option<Pass>> = const { RefCell::new(None) };
+}
+
+/// Stochastic progressive photon mapping. Every pass over the image shoots
+/// photons from the emissive objects, and camera paths follow mirrors and
+/// glass to the first other surface, where the light arriving from the
+/// emissive objects is estimated from the photons around it. The radius they
+/// are gathered in shrinks from pass to pass, so the blur fades while the
+/// noise averages out. Caustics seen through or cast by glass come out sharp,
+/// which sampling paths from the camera can hardly find.
+///
+/// Lights without geometry and the background can't shoot photons, so they
+/// are sampled directly at the camera path's surface instead. Every rendering
+/// thread makes passes of its own.
+pub struct PhotonMapping {
+    photons_per_pass: usize,
+    initial_radius: N,
+    // Fraction of the photons kept from one pass to the next, as a share of
+    // the radius squared
+    alpha: N,
+}
+
+impl PhotonMapping {
+    /// Shoots `photons_per_pass` photons each pass, gathered within
+    /// `initial_radius` in the first one
+    pub fn new(photons_per_pass: usize, initial_radius: N) -> Self {
+        Self {
+            photons_per_pass,
+            initial_radius,
+            alpha: 2.0 / 3.0,
+        }
+    }
+
+    // Key for the passes this integrator makes, as opposed to another on the
+    // same thread
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // Follow photons from random points on the emissive objects, leaving one
+    // at every surface that isn't delta. Their power adds up to the light the
+    // emissive objects give off.
+    fn shoot_photons(&self, scene: &Scene) -> Vec<Photon> {
+        let mut photons = Vec::new();
+        // There is no shutter here, so the scene is lit as it is at the start
+        let time = 0.0;
+        for _ in 0..self.photons_per_pass {
+            let (mut rec, pdf_pos) = match lighting::sample_light_origin(scene, time) {
+                Some(origin) => origin,
+                None => break,
+            };
+            let (direction, pdf_dir) = lighting::sample_emission(&mut rec);
+            let mut power = rec.material.emitted(&rec)
+                * (rec.normal.dot(&direction) / (pdf_pos * pdf_dir * self.photons_per_pass as N));
+            let mut ray = rec.spawn_ray(direction, time);
+
+            for depth in 0..scene.max_depth {
+                let mut rec = HitRecord::default();
+                if !scene.world.hit(&ray, T_MIN, N::MAX, &mut rec) {
+                    break;
+                }
+                let material = rec.material.clone();
+                // Lights absorb what lands on them
+                if !material.is_delta() && !material.is_emissive() {
+                    photons.push(Photon {
+                        p: rec.p,
+                        normal: rec.normal,
+                        wi: -ray.direction().unit(),
+                        power,
+                    });
+                }
+
+                let mut scattered = Ray::default();
+                let mut attenuation = Color::default();
+                if !material.scatter(&ray, &mut rec, &mut attenuation, &mut scattered) {
+                    break;
+                }
+                power *= attenuation;
+                ray = scattered;
+                if !survives_roulette(scene, depth, &mut power) {
+                    break;
+                }
+            }
+        }
+        photons
+    }
+
+    // Light from the emissive objects leaving the surface in `hit_record`
+    // towards `wo`, estimated from the photons within the pass's radius
+    fn gather(&self, hit_record: &HitRecord, wo: &Vector3D) -> Color {
+        PASS.with(|pass| {
+            let pass = pass.borrow();
+            let pass = match pass.as_ref() {
+                Some(pass) => pass,
+                None => return Color::new(0.0, 0.0, 0.0),
+            };
+            let radius_sq = pass.radius * pass.radius;
+            let mut color = Color::new(0.0, 0.0, 0.0);
+            pass.photons
+                .for_each_near(&hit_record.p, pass.radius, |photon| {
+                    if (photon.p - hit_record.p).length_sq() > radius_sq
+                        || photon.normal.dot(&hit_record.normal) < MIN_NORMAL_COSINE
+                    {
+                        return;
+                    }
+                    // The photon's power is spread over the surface already, so
+                    // the material's cosine is taken back out
+                    let cosine = hit_record.normal.dot(&photon.wi).abs();
+                    if cosine > 0.0 {
+                        let f = hit_record.material.eval(hit_record, &photon.wi, wo);
+                        color += photon.power * f / cosine;
+                    }
+                });
+            color / (PI * radius_sq)
+        })
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn begin_pass(&self, scene: &Scene) {
+        // Each pass keeps `alpha` of the last one's photons, as if they had
+        // been gathered together, by shrinking the area they are gathered over
+        let (index, radius) = PASS.with(|pass| match pass.borrow().as_ref() {
+            Some(pass) if pass.owner == self.id() => {
+                let shrink = (pass.index as N + self.alpha) / (pass.index as N + 1.0);
+                (pass.index + 1, pass.radius * shrink.sqrt())
+            }
+            _ => (1, self.initial_radius),
+        });
+        let photons = PhotonGrid::new(self.shoot_photons(scene), radius);
+        PASS.with(|pass| {
+            *pass.borrow_mut() = Some(Pass {
+                owner: self.id(),
+                index,
+                radius,
+                photons,
+            })
+        });
+    }
+
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        // For callers that don't make passes
+        let started = PASS
+            .with(|pass| matches!(pass.borrow().as_ref(), Some(pass) if pass.owner == self.id()));
+        if !started {
+            self.begin_pass(scene);
+        }
+
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for depth in 0..scene.max_depth {
+            if depth > 0 && !scene.world.hit(&ray, T_MIN, N::MAX, hit_record) {
+                color += throughput * ray.background(scene);
+                break;
+            }
+
+            let material = hit_record.material.clone();
+            color += throughput * material.emitted(hit_record);
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Color::default();
+            if !material.scatter(&ray, hit_record, &mut attenuation, &mut scattered) {
+                break;
+            }
+            if material.is_delta() {
+                throughput *= attenuation;
+                ray = scattered;
+                continue;
+            }
+
+            // Photons for the emissive objects, and sampling for the rest,
+            // including what the material's own sample finds of the background
+            let wo = -ray.direction().unit();
+            let wi = scattered.direction().unit();
+            let bsdf_pdf = Some(material.pdf(hit_record, &wi, &wo));
+            color += throughput
+                * (self.gather(hit_record, &wo)
+                    + lighting::sample_lights(&ray, scene, hit_record, &wo));
+            // Shadow rays pass through interfaces, so this does too
+            let mut background_ray = scattered;
+            let mut rec = HitRecord::default();
+            if !(scene.world.hit(&background_ray, T_MIN, N::MAX, &mut rec)
+                && lighting::pass_interfaces(scene, &mut background_ray, &mut rec))
+            {
+                color += throughput * attenuation * lighting::escaped(&scattered, scene, bsdf_pdf);
+            }
+            break;
+        }
+        color
+    }
+}
+
+struct Pass {
+    // Address of the integrator making it
+    owner: usize,
+    // Counting from 1
+    index: usize,
+    radius: N,
+    photons: PhotonGrid,
+}
+
+struct Photon {
+    p: Point3D,
+    // Facing the side the photon arrived from
+    normal: Vector3D,
+    // Towards where the photon came from
+    wi: Vector3D,
+    power: Color,
+}
+
+// Photons bucketed by the cell of a uniform grid they fall in, with cells
+// twice the gathering radius wide so a gather only has to look in the 8
+// around it. Cells are hashed into as many buckets as there are photons.
+struct PhotonGrid {
+    cell_size: N,
+    photons: Vec<Photon>,
+    // Photons of bucket `i` are `photons[starts[i]..starts[i + 1]]`
+    starts: Vec<usize>,
+}
+
+impl PhotonGrid {
+    fn new(photons: Vec<Photon>, radius: N) -> Self {
+        let cell_size = 2.0 * radius;
+        let buckets = photons.len().max(1);
+        let bucket = |p: &Point3D| {
+            let cell = Self::cell(p, cell_size);
+            Self::bucket(cell, buckets)
+        };
+
+        // Counting sort by bucket
+        let mut starts = vec![0; buckets + 1];
+        for photon in &photons {
+            starts[bucket(&photon.p) + 1] += 1;
+        }
+        for i in 0..buckets {
+            starts[i + 1] += starts[i];
+        }
+        let mut next = starts.clone();
+        let mut slots: Vec<Option<Photon>> = (0..photons.len()).map(|_| None).collect();
+        for photon in photons {
+            let b = bucket(&photon.p);
+            slots[next[b]] = Some(photon);
+            next[b] += 1;
+        }
+
+        Self {
+            cell_size,
+            photons: slots.into_iter().flatten().collect(),
+            starts,
+        }
+    }
+
+    fn cell(p: &Point3D, cell_size: N) -> [i64; 3] {
+        [
+            (p.x() / cell_size).floor() as i64,
+            (p.y() / cell_size).floor() as i64,
+            (p.z() / cell_size).floor() as i64,
+        ]
+    }
+
+    fn bucket([x, y, z]: [i64; 3], buckets: usize) -> usize {
+        let hash = (x.wrapping_mul(73_856_093)
+            ^ y.wrapping_mul(19_349_663)
+            ^ z.wrapping_mul(83_492_791)) as u64;
+        (hash % buckets as u64) as usize
+    }
+
+    // Call `f` with every photon in a cell overlapping the cube of half width
+    // `radius` around `p`, and some others that share their buckets
+    fn for_each_near<F: FnMut(&Photon)>(&self, p: &Point3D, radius: N, mut f: F) {
+        let offset = Vector3D::new(radius, radius, radius);
+        let lo = Self::cell(&(*p - offset), self.cell_size);
+        let hi = Self::cell(&(*p + offset), self.cell_size);
+        let buckets = self.starts.len() - 1;
+        // Neighbouring cells can share a bucket, which mustn't be visited twice
+        let mut visited = [usize::MAX; 8];
+        let mut count = 0;
+        for x in lo[0]..=hi[0] {
+            for y in lo[1]..=hi[1] {
+                for z in lo[2]..=hi[2] {
+                    let b = Self::bucket([x, y, z], buckets);
+                    if visited[..count].contains(&b) {
+                        continue;
+                    }
+                    visited[count] = b;
+                    count += 1;
+                    self.photons[self.starts[b]..self.starts[b + 1]]
+                        .iter()
+                        .for_each(&mut f);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn agrees_with_path_tracer() {
+    use crate::utils::random_n;
+
+    // Gathering blurs the light, but hardly changes how much there is
+    let (width, height) = (16, 12);
+    let (scene, camera) = super::test_scene(width, height, 0.0);
+    let photon_mapping = PhotonMapping::new(20_000, 0.1);
+
+    // Total light over the image
+    let mut path_traced = 0.0;
+    let mut gathered = 0.0;
+    for _ in 0..32 {
+        photon_mapping.begin_pass(&scene);
+        for j in 0..height {
+            for i in 0..width {
+                let u = (i as N + random_n()) / (width - 1) as N;
+                let v = (j as N + random_n()) / (height - 1) as N;
+                let ray = camera.get_ray(u, v);
+                path_traced += super::PathTracer.color(&ray, &scene).luminance();
+                gathered += photon_mapping.color(&ray, &scene).luminance();
+            }
+        }
+    }
+    assert!((gathered / path_traced - 1.0).abs() < 0.05);
+}
@@ -0,0 +1,301 @@
+use crate::hittables::AABB;
+use crate::utils::{random_n, PI};
+use crate::vector::{Point3D, Vector3D, N};
+
+// Records a region of space takes in a pass before it is split in two
+const SPATIAL_THRESHOLD: usize = 12_000;
+const MAX_SPATIAL_DEPTH: usize = 24;
+// Share of the energy a quadrant of directions holds before it is split in four
+const DIRECTIONAL_THRESHOLD: N = 0.01;
+const MAX_DIRECTIONAL_DEPTH: usize = 20;
+
+/// Spatial-directional tree, after "Practical Path Guiding for Efficient
+/// Light-Transport Simulation" (Müller et al. 2017). A binary tree splits the
+/// scene into regions, each with a quadtree over the sphere of directions
+/// holding how much light arrives from each. The quadtrees learnt in one pass
+/// are sampled in the next while new ones are recorded, refined where the
+/// last ones found the light to be.
+pub(super) struct SdTree {
+    bounds: AABB,
+    nodes: Vec<SpatialNode>,
+}
+
+struct SpatialNode {
+    // Index of the first of the two children, 0 for leaves
+    children: usize,
+    // Learnt in the last pass
+    sampling: DTree,
+    // Being recorded in this one
+    building: DTree,
+    records: usize,
+}
+
+impl SdTree {
+    /// A single region covering `bounds`, sampling nothing until a pass has
+    /// been recorded
+    pub(super) fn new(bounds: AABB) -> Self {
+        Self {
+            bounds,
+            nodes: vec![SpatialNode {
+                children: 0,
+                sampling: DTree::default(),
+                building: DTree::default(),
+                records: 0,
+            }],
+        }
+    }
+
+    /// Index of the region `p` is in. Each level halves the region along the
+    /// next axis.
+    pub(super) fn leaf(&self, p: &Point3D) -> usize {
+        let (min, max) = (self.bounds.min(), self.bounds.max());
+        let size = max - min;
+        let mut local = [
+            (*p.x() - *min.x()) / *size.x(),
+            (*p.y() - *min.y()) / *size.y(),
+            (*p.z() - *min.z()) / *size.z(),
+        ];
+        let mut node = 0;
+        let mut depth = 0;
+        while self.nodes[node].children != 0 {
+            let axis = depth % 3;
+            let x = local[axis].clamp(0.0, 1.0);
+            if x < 0.5 {
+                local[axis] = 2.0 * x;
+                node = self.nodes[node].children;
+            } else {
+                local[axis] = 2.0 * x - 1.0;
+                node = self.nodes[node].children + 1;
+            }
+            depth += 1;
+        }
+        node
+    }
+
+    /// Whether the region has learnt where light comes from
+    pub(super) fn is_trained(&self, leaf: usize) -> bool {
+        self.nodes[leaf].sampling.total() > 0.0
+    }
+
+    pub(super) fn sample(&self, leaf: usize) -> Vector3D {
+        let (u, v) = self.nodes[leaf].sampling.sample();
+        to_direction(u, v)
+    }
+
+    /// Density per unit solid angle of `sample` picking `direction`
+    pub(super) fn pdf(&self, leaf: usize, direction: &Vector3D) -> N {
+        let (u, v) = from_direction(direction);
+        self.nodes[leaf].sampling.pdf(u, v) / (4.0 * PI)
+    }
+
+    /// Add light arriving at `p` from `direction`, divided by the density
+    /// with which `direction` was picked
+    pub(super) fn record(&mut self, p: &Point3D, direction: &Vector3D, value: N) {
+        if !value.is_finite() || value < 0.0 {
+            return;
+        }
+        let leaf = self.leaf(p);
+        let (u, v) = from_direction(direction);
+        let node = &mut self.nodes[leaf];
+        node.building.record(u, v, value);
+        node.records += 1;
+    }
+
+    /// Start sampling what was recorded, splitting regions that got many
+    /// records and refining each quadtree where it found light
+    pub(super) fn refine(&mut self) {
+        let mut stack = vec![(0, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            if self.nodes[node].children != 0 {
+                let first = self.nodes[node].children;
+                stack.push((first, depth + 1));
+                stack.push((first + 1, depth + 1));
+                continue;
+            }
+            if self.nodes[node].records <= SPATIAL_THRESHOLD || depth >= MAX_SPATIAL_DEPTH {
+                continue;
+            }
+
+            // Both halves start out with what the whole recorded
+            let first = self.nodes.len();
+            let parent = std::mem::replace(
+                &mut self.nodes[node],
+                SpatialNode {
+                    children: first,
+                    sampling: DTree::default(),
+                    building: DTree::default(),
+                    records: 0,
+                },
+            );
+            for _ in 0..2 {
+                self.nodes.push(SpatialNode {
+                    children: 0,
+                    sampling: DTree::default(),
+                    building: parent.building.clone(),
+                    records: parent.records / 2,
+                });
+            }
+            stack.push((first, depth + 1));
+            stack.push((first + 1, depth + 1));
+        }
+
+        for node in self.nodes.iter_mut().filter(|node| node.children == 0) {
+            let refined = node.building.refined();
+            node.sampling = std::mem::replace(&mut node.building, refined);
+            node.records = 0;
+        }
+    }
+}
+
+// Quadtree over the unit square, which maps to the sphere of directions
+// keeping areas. Each node holds the energy in each of its quadrants.
+#[derive(Clone)]
+struct DTree {
+    nodes: Vec<DirectionalNode>,
+}
+
+#[derive(Clone, Default)]
+struct DirectionalNode {
+    sums: [N; 4],
+    // Index of the node splitting each quadrant, 0 if it isn't
+    children: [usize; 4],
+}
+
+impl Default for DTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![DirectionalNode::default()],
+        }
+    }
+}
+
+impl DTree {
+    fn total(&self) -> N {
+        self.nodes[0].sums.iter().sum()
+    }
+
+    // Walk down picking quadrants by their energy, then uniformly within
+    fn sample(&self) -> (N, N) {
+        let mut node = 0;
+        let (mut u, mut v, mut size) = (0.0, 0.0, 1.0);
+        loop {
+            let sums = &self.nodes[node].sums;
+            let total: N = sums.iter().sum();
+            let mut quadrant = 3;
+            if total > 0.0 {
+                let mut pick = random_n() * total;
+                for (i, &sum) in sums.iter().enumerate() {
+                    if pick < sum {
+                        quadrant = i;
+                        break;
+                    }
+                    pick -= sum;
+                }
+            } else {
+                quadrant = (random_n() * 4.0) as usize % 4;
+            }
+            size /= 2.0;
+            u += size * (quadrant % 2) as N;
+            v += size * (quadrant / 2) as N;
+            match self.nodes[node].children[quadrant] {
+                0 => return (u + size * random_n(), v + size * random_n()),
+                child => node = child,
+            }
+        }
+    }
+
+    // Density over the unit square of `sample` picking (u, v)
+    fn pdf(&self, mut u: N, mut v: N) -> N {
+        let mut node = 0;
+        let mut pdf = 1.0;
+        loop {
+            let sums = &self.nodes[node].sums;
+            let total: N = sums.iter().sum();
+            if total <= 0.0 {
+                return pdf;
+            }
+            let quadrant = quadrant(&mut u, &mut v);
+            pdf *= 4.0 * sums[quadrant] / total;
+            match self.nodes[node].children[quadrant] {
+                0 => return pdf,
+                child => node = child,
+            }
+        }
+    }
+
+    fn record(&mut self, mut u: N, mut v: N, value: N) {
+        let mut node = 0;
+        loop {
+            let quadrant = quadrant(&mut u, &mut v);
+            self.nodes[node].sums[quadrant] += value;
+            match self.nodes[node].children[quadrant] {
+                0 => return,
+                child => node = child,
+            }
+        }
+    }
+
+    // Empty tree split wherever a quadrant held enough of the energy. Where
+    // this one wasn't split, the energy is taken to be spread evenly.
+    fn refined(&self) -> Self {
+        let total = self.total();
+        let mut refined = Self::default();
+        let mut stack = vec![(Some(0), 0, 1, self.nodes[0].sums)];
+        while let Some((old, new, depth, sums)) = stack.pop() {
+            for (quadrant, &energy) in sums.iter().enumerate() {
+                if depth >= MAX_DIRECTIONAL_DEPTH
+                    || total <= 0.0
+                    || energy <= DIRECTIONAL_THRESHOLD * total
+                {
+                    continue;
+                }
+                let child = refined.nodes.len();
+                refined.nodes.push(DirectionalNode::default());
+                refined.nodes[new].children[quadrant] = child;
+                let old_child = old
+                    .map(|old| self.nodes[old].children[quadrant])
+                    .filter(|&child| child != 0);
+                let child_sums = match old_child {
+                    Some(old_child) => self.nodes[old_child].sums,
+                    None => [energy / 4.0; 4],
+                };
+                stack.push((old_child, child, depth + 1, child_sums));
+            }
+        }
+        refined
+    }
+}
+
+// Which quadrant of the current node (u, v) is in, rescaling them to it
+fn quadrant(u: &mut N, v: &mut N) -> usize {
+    let mut quadrant = 0;
+    if *u >= 0.5 {
+        quadrant += 1;
+        *u -= 0.5;
+    }
+    if *v >= 0.5 {
+        quadrant += 2;
+        *v -= 0.5;
+    }
+    *u = (2.0 * *u).min(1.0);
+    *v = (2.0 * *v).min(1.0);
+    quadrant
+}
+
+// Cylindrical mapping, which keeps areas: u picks the height, v the angle
+fn to_direction(u: N, v: N) -> Vector3D {
+    let cos_theta = 2.0 * u - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vector3D::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn from_direction(direction: &Vector3D) -> (N, N) {
+    let d = direction.unit();
+    let u = ((d.z() + 1.0) / 2.0).clamp(0.0, 1.0);
+    let mut phi = d.y().atan2(*d.x());
+    if phi < 0.0 {
+        phi += 2.0 * PI;
+    }
+    (u, (phi / (2.0 * PI)).clamp(0.0, 1.0))
+}
@@ -0,0 +1,268 @@
+use super::integrator::survives_roulette;
+use super::lighting::{self, Receiver, Shadow};
+use super::Integrator;
+use crate::hittables::{HitRecord, Hittable};
+use crate::media::MediumStack;
+use crate::ray::{Ray, T_MIN};
+use crate::scene::Scene;
+use crate::vector::{Color, Vector3D, N};
+
+/// `PathTracer` which also follows paths through participating media: the
+/// scene's own, and those filling objects whose materials bound one. Along
+/// every segment through a medium a free-flight distance is sampled, and the
+/// path either scatters there by the medium's phase function or carries on,
+/// weighted by the transmittance. Lights are sampled at scattering points in
+/// media as well as on surfaces, with shadow rays picking up the
+/// transmittance of the media they pass through.
+pub struct VolumetricPathTracer;
+
+impl VolumetricPathTracer {
+    /// Follows the path the ray starts, whose closest hit is in `hit_record`
+    /// if it has one
+    fn trace(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord, mut hit: bool) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut media = MediumStack::new(scene.medium.clone());
+        let mut bsdf_pdf = None;
+        // Where the path's direction was last picked, which passing straight
+        // through interfaces doesn't change
+        let mut origin = *ray.origin();
+        let mut depth = 0;
+
+        while depth < scene.max_depth {
+            let t_max = if hit { hit_record.t } else { N::MAX };
+            if let Some(medium) = media.current().cloned() {
+                let sample = medium.sample(&ray, t_max);
+                throughput *= sample.weight;
+                if let Some(t) = sample.t {
+                    // Scatter in the medium
+                    let p = ray.at(t);
+                    let wo = -ray.direction().unit();
+                    let time = *ray.time();
+                    let f = |wi: &Vector3D| {
+                        let phase = medium.phase(wi, &wo);
+                        Color::new(phase, phase, phase)
+                    };
+                    let pdf = |wi: &Vector3D| medium.phase(wi, &wo);
+                    let shadow = |wi: &Vector3D, distance: N| {
+                        trace_shadow(scene, Ray::new(p, *wi, time), media.clone(), distance)
+                    };
+                    let receiver = Receiver {
+                        p,
+                        f: &f,
+                        pdf: &pdf,
+                        shadow: &shadow,
+                    };
+                    color += throughput * lighting::sample_direct_at(scene, &receiver, time);
+
+                    // The phase function is sampled exactly, so the throughput
+                    // stays as it is
+                    let wi = medium.sample_phase(&wo);
+                    bsdf_pdf = Some(medium.phase(&wi, &wo));
+                    origin = p;
+                    ray = Ray::new(p, wi, time);
+                    if !survives_roulette(scene, depth, &mut throughput) {
+                        break;
+                    }
+                    depth += 1;
+                    hit = scene.world.hit(&ray, T_MIN, N::MAX, hit_record);
+                    continue;
+                }
+            }
+
+            // Light found is weighted as seen from where the direction it
+            // arrives along was picked
+            let from_origin = Ray::new(origin, *ray.direction(), *ray.time());
+            if !hit {
+                color += throughput * lighting::escaped(&from_origin, scene, bsdf_pdf);
+                break;
+            }
+
+            let material = hit_record.material.clone();
+            if material.is_interface() {
+                media.cross(hit_record, ray.direction());
+                ray = hit_record.spawn_ray(*ray.direction(), *ray.time());
+                hit = scene.world.hit(&ray, T_MIN, N::MAX, hit_record);
+                continue;
+            }
+            color += throughput * lighting::emitted(&from_origin, scene, hit_record, bsdf_pdf);
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Color::default();
+            if !material.scatter(&ray, hit_record, &mut attenuation, &mut scattered) {
+                break;
+            }
+
+            bsdf_pdf = if material.is_delta() {
+                None
+            } else {
+                let wo = -ray.direction().unit();
+                let time = *ray.time();
+                let f = |wi: &Vector3D| material.eval(hit_record, wi, &wo);
+                let pdf = |wi: &Vector3D| material.pdf(hit_record, wi, &wo);
+                let shadow = |wi: &Vector3D, distance: N| {
+                    let mut media = media.clone();
+                    media.cross(hit_record, wi);
+                    let ray = hit_record.spawn_ray(*wi, time);
+                    trace_shadow(scene, ray, media, distance)
+                };
+                let receiver = Receiver {
+                    p: hit_record.p,
+                    f: &f,
+                    pdf: &pdf,
+                    shadow: &shadow,
+                };
+                color += throughput * lighting::sample_direct_at(scene, &receiver, time);
+                Some(material.pdf(hit_record, &scattered.direction().unit(), &wo))
+            };
+            media.cross(hit_record, scattered.direction());
+            origin = hit_record.p;
+            throughput *= attenuation;
+            ray = scattered;
+
+            if !survives_roulette(scene, depth, &mut throughput) {
+                break;
+            }
+            depth += 1;
+            hit = scene.world.hit(&ray, T_MIN, N::MAX, hit_record);
+        }
+        color
+    }
+}
+
+// Follow a shadow ray with a unit direction through the media it passes,
+// stopped by the first surface other than an interface it hits within
+// `distance`
+fn trace_shadow(scene: &Scene, mut ray: Ray, mut media: MediumStack, mut distance: N) -> Shadow {
+    let mut transmittance = Color::new(1.0, 1.0, 1.0);
+    let mut travelled = 0.0;
+    loop {
+        let mut rec = HitRecord::default();
+        let hit = scene.world.hit(&ray, T_MIN, distance, &mut rec);
+        let t = if hit { rec.t } else { distance };
+        if let Some(medium) = media.current() {
+            transmittance *= medium.transmittance(&ray, t);
+        }
+        if !hit || !rec.material.is_interface() {
+            rec.t += travelled;
+            return (transmittance, hit.then_some(rec));
+        }
+        if transmittance.near_zero() {
+            return (transmittance, None);
+        }
+        media.cross(&rec, ray.direction());
+        travelled += t;
+        distance -= t;
+        ray = rec.spawn_ray(*ray.direction(), *ray.time());
+    }
+}
+
+impl Integrator for VolumetricPathTracer {
+    fn shade(&self, ray: &Ray, scene: &Scene, hit_record: &mut HitRecord) -> Color {
+        self.trace(ray, scene, hit_record, true)
+    }
+
+    // Camera rays through the scene's medium can still scatter in it
+    fn miss(&self, ray: &Ray, scene: &Scene) -> Color {
+        match scene.medium {
+            Some(_) => self.trace(ray, scene, &mut HitRecord::default(), false),
+            None => ray.background(scene),
+        }
+    }
+}
+
+#[test]
+fn nested_media_attenuate_by_path_length() {
+    use std::sync::Arc;
+
+    use crate::backgrounds::Constant;
+    use crate::hittables::{Hittables, Sphere};
+    use crate::materials::Interface;
+    use crate::media::Homogeneous;
+    use crate::vector::Point3D;
+
+    // A ball of one absorbing medium inside another filling the scene, seen
+    // straight through its center against a white background
+    let black = Color::new(0.0, 0.0, 0.0);
+    let inner = Color::new(0.5, 0.2, 0.1);
+    let outer = Color::new(0.05, 0.1, 0.0);
+    let mut objects = Hittables::new();
+    objects.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        Arc::new(Interface::new(Arc::new(Homogeneous::new(
+            inner, black, 0.0,
+        )))),
+    )));
+    let mut scene = Scene::new(objects);
+    scene.background = Arc::new(Constant::new(Color::new(1.0, 1.0, 1.0)));
+    scene.medium = Some(Arc::new(Homogeneous::new(outer, black, 0.0)));
+
+    // 3 units of the outer medium before the ball, 2 inside and the rest
+    // behind it
+    let ray = Ray::new(
+        Point3D::new(0.0, 0.0, 4.0),
+        Vector3D::new(0.0, 0.0, -1.0),
+        0.0,
+    );
+    let average = |scene: &Scene| {
+        let samples = 50_000;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            sum += VolumetricPathTracer.color(&ray, scene);
+        }
+        sum / samples as N
+    };
+    let through_inner = Color::new(
+        (-2.0 * inner.x()).exp(),
+        (-2.0 * inner.y()).exp(),
+        (-2.0 * inner.z()).exp(),
+    );
+    // Escaping into an endless medium, only what doesn't absorb gets out
+    let expected = through_inner * Color::new(0.0, 0.0, 1.0);
+    assert!((average(&scene) - expected).length() < 0.05);
+
+    // Without the outer medium, the ball alone
+    scene.medium = None;
+    assert!((average(&scene) - through_inner).length() < 0.05);
+}
+
+#[test]
+fn scattering_media_conserve_energy() {
+    use std::sync::Arc;
+
+    use crate::backgrounds::Constant;
+    use crate::hittables::{Hittables, Sphere};
+    use crate::materials::Interface;
+    use crate::media::Homogeneous;
+    use crate::vector::Point3D;
+
+    // A cloud which scatters without absorbing, in a white surrounding. Every
+    // path eventually escapes, so it looks the same as the surrounding.
+    let mut objects = Hittables::new();
+    objects.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        Arc::new(Interface::new(Arc::new(Homogeneous::new(
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(1.0, 2.0, 3.0),
+            0.4,
+        )))),
+    )));
+    let mut scene = Scene::new(objects);
+    scene.background = Arc::new(Constant::new(Color::new(1.0, 1.0, 1.0)));
+    scene.max_depth = 1000;
+
+    let ray = Ray::new(
+        Point3D::new(0.3, 0.2, 5.0),
+        Vector3D::new(0.0, 0.0, -1.0),
+        0.0,
+    );
+    let samples = 50_000;
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    for _ in 0..samples {
+        sum += VolumetricPathTracer.color(&ray, &scene);
+    }
+    assert!((sum / samples as N - Color::new(1.0, 1.0, 1.0)).length() < 0.05);
+}
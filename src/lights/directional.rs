@@ -0,0 +1,64 @@
+use super::{Light, LightSample};
+use crate::utils::{random_in_cone, PI};
+use crate::vector::{Color, Point3D, Vector3D, N};
+
+/// Light from infinitely far away, like the sun. With a non-zero angular
+/// diameter it arrives from a small disk in the sky and casts soft shadows.
+pub struct DirectionalLight {
+    // Unit vector pointing towards the light
+    to_light: Vector3D,
+    irradiance: Color,
+    one_minus_cos_max: N,
+}
+
+impl DirectionalLight {
+    /// `direction` is the way the light travels, and `irradiance` is what
+    /// arrives on a surface facing it
+    pub fn new(direction: Vector3D, irradiance: Color, angular_diameter: N) -> Self {
+        let half_angle = angular_diameter.to_radians() / 2.0;
+        // 1 - cos written with the sine of half the angle, to stay accurate for
+        // disks as small as the sun's
+        let sin_half = (half_angle / 2.0).sin();
+        Self {
+            to_light: -direction.unit(),
+            irradiance,
+            one_minus_cos_max: 2.0 * sin_half * sin_half,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _: &Point3D) -> Option<LightSample> {
+        if self.one_minus_cos_max == 0.0 {
+            return Some(LightSample {
+                wi: self.to_light,
+                radiance: self.irradiance,
+                pdf: 1.0,
+                distance: N::MAX,
+            });
+        }
+
+        // Uniform over the disk, whose radiance spreads the irradiance over
+        // the solid angle it covers
+        let solid_angle = 2.0 * PI * self.one_minus_cos_max;
+        Some(LightSample {
+            wi: random_in_cone(&self.to_light, self.one_minus_cos_max),
+            radiance: self.irradiance / solid_angle,
+            pdf: 1.0 / solid_angle,
+            distance: N::MAX,
+        })
+    }
+}
+
+#[test]
+fn sun_disk_delivers_its_irradiance() {
+    let irradiance = Color::new(3.0, 2.0, 1.0);
+    let direction = Vector3D::new(0.2, -1.0, 0.4);
+    let sun = DirectionalLight::new(direction, irradiance, 0.53);
+    let cos_max = ((0.53 as N).to_radians() / 2.0).cos();
+    for _ in 0..1000 {
+        let sample = sun.sample_li(&Point3D::new(0.0, 0.0, 0.0)).unwrap();
+        assert!((sample.radiance / sample.pdf - irradiance).length() < 1e-3);
+        assert!(sample.wi.dot(&-direction.unit()) >= cos_max - 1e-6);
+    }
+}
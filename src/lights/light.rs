@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use crate::vector::{Color, Point3D, Vector3D, N};
+
+pub type SharedLight = Arc<dyn Light + Sync + Send>;
+
+/// Light arriving at a point from a sampled direction
+pub struct LightSample {
+    /// Unit direction from the point towards the light
+    pub wi: Vector3D,
+    /// Radiance arriving along `wi` if nothing blocks it
+    pub radiance: Color,
+    /// Density over solid angle of picking `wi`, 1 for lights that only arrive
+    /// from a single direction
+    pub pdf: N,
+    /// Distance to the light along `wi`, past which objects don't block it
+    pub distance: N,
+}
+
+/// A light source without geometry. Rays can't hit these, so they only
+/// contribute through shadow rays.
+pub trait Light {
+    /// Pick a direction from `p` towards the light. `None` if no light from it
+    /// reaches `p`.
+    fn sample_li(&self, p: &Point3D) -> Option<LightSample>;
+}
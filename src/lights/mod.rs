@@ -0,0 +1,9 @@
+mod directional;
+mod light;
+mod point;
+mod spot;
+
+pub use directional::*;
+pub use light::*;
+pub use point::*;
+pub use spot::*;
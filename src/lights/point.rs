@@ -0,0 +1,31 @@
+use super::{Light, LightSample};
+use crate::vector::{Color, Point3D};
+
+/// Shines equally in all directions from a single point
+pub struct PointLight {
+    position: Point3D,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3D, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Point3D) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_sq = to_light.length_sq();
+        let distance = distance_sq.sqrt();
+        Some(LightSample {
+            wi: to_light / distance,
+            radiance: self.intensity / distance_sq,
+            pdf: 1.0,
+            distance,
+        })
+    }
+}
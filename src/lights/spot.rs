@@ -0,0 +1,63 @@
+use super::{Light, LightSample};
+use crate::vector::{Color, Point3D, Vector3D, N};
+
+/// A point light limited to a cone, fading out between `falloff_start` and
+/// `total_width` degrees from its axis
+pub struct SpotLight {
+    position: Point3D,
+    axis: Vector3D,
+    intensity: Color,
+    cos_total_width: N,
+    cos_falloff_start: N,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point3D,
+        target: Point3D,
+        intensity: Color,
+        total_width: N,
+        falloff_start: N,
+    ) -> Self {
+        Self {
+            position,
+            axis: (target - position).unit(),
+            intensity,
+            cos_total_width: total_width.to_radians().cos(),
+            cos_falloff_start: falloff_start.min(total_width).to_radians().cos(),
+        }
+    }
+
+    // Smoothly goes from 1 inside the falloff start to 0 at the total width
+    fn falloff(&self, cos_theta: N) -> N {
+        if cos_theta <= self.cos_total_width {
+            return 0.0;
+        }
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        let t =
+            (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Point3D) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_sq = to_light.length_sq();
+        let distance = distance_sq.sqrt();
+        let wi = to_light / distance;
+
+        let falloff = self.falloff(-wi.dot(&self.axis));
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.intensity * (falloff / distance_sq),
+            pdf: 1.0,
+            distance,
+        })
+    }
+}
@@ -1,11 +1,22 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod aov;
+mod backgrounds;
 mod camera;
+mod distribution;
+mod film;
+mod hdr;
 mod hittables;
+mod integrators;
+mod lights;
 mod materials;
+mod media;
+mod packet;
 mod ray;
 mod render;
+mod scene;
+mod textures;
 mod utils;
 mod vector;
 
@@ -16,9 +27,23 @@ use std::sync::Arc;
 
 use indicatif::ProgressBar;
 
+use aov::Aov;
+use backgrounds::{Black, Constant, EnvironmentLight, Sky};
 use camera::Camera;
-use hittables::{BvhNode, Hittables, Sphere};
-use materials::{Glass, Lambert, Metal, SharedMaterial};
+use hdr::HdrImage;
+use hittables::{Hittable, Hittables, Quad, Sphere, AABB};
+use integrators::{
+    AmbientOcclusion, Bdpt, BruteForce, DebugIntegrator, DebugView, DirectLighting,
+    GuidedPathTracer, Mlt, PathTracer, PhotonMapping, SharedIntegrator, VolumetricPathTracer,
+};
+use lights::{DirectionalLight, PointLight, SpotLight};
+use materials::{
+    DiffuseLight, Glass, Interface, Lambert, Metal, OrenNayar, Principled, SharedMaterial,
+};
+use media::Homogeneous;
+use render::RenderMode;
+use scene::Scene;
+use textures::{Checker, Constant as ConstantTexture, ImageTexture, SharedTexture};
 use utils::{random_n, random_range};
 use vector::{Color, Point3D, Vector3D, N};
 
@@ -82,42 +107,457 @@ fn random_scene() -> Hittables {
         material3,
     )));
 
-    let mut hittables = Hittables::new();
-    let mut world = world.into_vec();
-    let len = world.len();
-    hittables.add(Arc::new(BvhNode::new(&mut world, 0, len, 0.0, 0.0)));
-    hittables
+    world
+}
+
+// Walls and light of the Cornell box, open at the front
+fn cornell_walls(world: &mut Hittables) {
+    let red = Arc::new(Lambert::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambert::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambert::new(Color::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new(Color::new(15.0, 15.0, 15.0)));
+
+    let x = Vector3D::new(555.0, 0.0, 0.0);
+    let y = Vector3D::new(0.0, 555.0, 0.0);
+    let z = Vector3D::new(0.0, 0.0, 555.0);
+    let origin = Point3D::new(0.0, 0.0, 0.0);
+    world.add(Arc::new(Quad::new(origin + x, y, z, green)));
+    world.add(Arc::new(Quad::new(origin, y, z, red)));
+    world.add(Arc::new(Quad::new(origin, x, z, white.clone())));
+    world.add(Arc::new(Quad::new(origin + y, x, z, white.clone())));
+    world.add(Arc::new(Quad::new(origin + z, x, y, white)));
+    world.add(Arc::new(Quad::new(
+        Point3D::new(343.0, 554.0, 332.0),
+        Vector3D::new(-130.0, 0.0, 0.0),
+        Vector3D::new(0.0, 0.0, -105.0),
+        light,
+    )));
+}
+
+fn cornell_box() -> Scene {
+    let mut world = Hittables::new();
+    cornell_walls(&mut world);
+
+    world.add(Arc::new(Sphere::new(
+        Point3D::new(190.0, 90.0, 190.0),
+        90.0,
+        Arc::new(Glass::new(1.5)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3D::new(370.0, 120.0, 370.0),
+        120.0,
+        Arc::new(Lambert::new(Color::new(0.73, 0.73, 0.73))),
+    )));
+
+    // Only the light in the ceiling, with nothing shining in through the open side
+    let mut scene = Scene::new(world);
+    scene.background = Arc::new(Black);
+    scene
+}
+
+// Cornell box filled with thin fog, holding a glass ball of murky red liquid
+// and a cloud of smoke, for the volumetric integrator
+fn cornell_volumes() -> Scene {
+    let mut world = Hittables::new();
+    cornell_walls(&mut world);
+
+    let liquid = Arc::new(Homogeneous::new(
+        Color::new(0.002, 0.02, 0.02),
+        Color::new(0.01, 0.002, 0.002),
+        0.3,
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point3D::new(190.0, 90.0, 190.0),
+        90.0,
+        Arc::new(Glass::new(1.33).filled(liquid)),
+    )));
+    let smoke = Arc::new(Homogeneous::new(
+        Color::new(0.002, 0.002, 0.002),
+        Color::new(0.02, 0.02, 0.02),
+        0.0,
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point3D::new(370.0, 160.0, 370.0),
+        120.0,
+        Arc::new(Interface::new(smoke)),
+    )));
+
+    let mut scene = Scene::new(world);
+    scene.background = Arc::new(Black);
+    scene.medium = Some(Arc::new(Homogeneous::new(
+        Color::new(0.0, 0.0, 0.0),
+        Color::new(0.0005, 0.0005, 0.0005),
+        0.5,
+    )));
+    scene
+}
+
+// Subject lit by a classic three-point rig of lights without geometry
+fn studio() -> Scene {
+    let mut world = Hittables::new();
+
+    let floor = Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5)));
+    studio_floor(&mut world, floor);
+    world.add(Arc::new(Sphere::new(
+        Point3D::new(0.0, 1.0, 0.0),
+        1.0,
+        Arc::new(Lambert::new(Color::new(0.8, 0.3, 0.2))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3D::new(-2.2, 0.7, -0.5),
+        0.7,
+        Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 0.2)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3D::new(2.0, 0.6, 0.5),
+        0.6,
+        Arc::new(Glass::new(1.5)),
+    )));
+    studio_rig(world)
+}
+
+fn studio_floor(world: &mut Hittables, material: SharedMaterial) {
+    world.add(Arc::new(Quad::new(
+        Point3D::new(-50.0, 0.0, 50.0),
+        Vector3D::new(100.0, 0.0, 0.0),
+        Vector3D::new(0.0, 0.0, -100.0),
+        material,
+    )));
+}
+
+// Scene of `world` under the studio's lights
+fn studio_rig(world: Hittables) -> Scene {
+    let mut scene = Scene::new(world);
+    scene.background = Arc::new(Constant::new(Color::new(0.02, 0.02, 0.02)));
+    // Nothing here needs many bounces to look right
+    scene.max_depth = 16;
+    // Key
+    scene.add_light(Arc::new(SpotLight::new(
+        Point3D::new(4.0, 6.0, 4.0),
+        Point3D::new(0.0, 1.0, 0.0),
+        Color::new(120.0, 110.0, 100.0),
+        25.0,
+        15.0,
+    )));
+    // Fill
+    scene.add_light(Arc::new(PointLight::new(
+        Point3D::new(-5.0, 3.0, 4.0),
+        Color::new(10.0, 12.0, 15.0),
+    )));
+    // Rim, a sun behind the subject
+    scene.add_light(Arc::new(DirectionalLight::new(
+        Vector3D::new(0.0, -0.6, 1.0),
+        Color::new(2.0, 2.0, 2.0),
+        0.53,
+    )));
+    scene
+}
+
+// A row of principled materials on a checkered floor in the studio, the first
+// textured by the image given with `--texture`
+fn principled() -> Scene {
+    let mut world = Hittables::new();
+
+    let checker = Checker::new(
+        ConstantTexture::shared(Color::new(0.8, 0.8, 0.8)),
+        ConstantTexture::shared(Color::new(0.2, 0.2, 0.2)),
+        100.0,
+    );
+    let floor = Principled {
+        roughness: ConstantTexture::shared(0.2),
+        ..Principled::new(Arc::new(checker))
+    };
+    studio_floor(&mut world, Arc::new(floor));
+
+    let base_color: SharedTexture<Color> = match arg_value("--texture") {
+        Some(path) => Arc::new(ImageTexture::new(HdrImage::load(&path).unwrap())),
+        None => ConstantTexture::shared(Color::new(0.8, 0.3, 0.2)),
+    };
+    let spheres = vec![
+        // Varnished plastic
+        Principled {
+            clearcoat: ConstantTexture::shared(1.0),
+            ..Principled::new(base_color)
+        },
+        // Velvet
+        Principled {
+            roughness: ConstantTexture::shared(1.0),
+            sheen: ConstantTexture::shared(1.0),
+            ..Principled::new(ConstantTexture::shared(Color::new(0.3, 0.1, 0.4)))
+        },
+        // Brushed gold, its highlights stretched along the lines of latitude
+        Principled {
+            anisotropic: ConstantTexture::shared(0.8),
+            ..Principled::metallic_roughness(
+                ConstantTexture::shared(Color::new(1.0, 0.78, 0.34)),
+                ConstantTexture::shared(1.0),
+                ConstantTexture::shared(0.3),
+            )
+        },
+        // Frosted green glass
+        Principled {
+            roughness: ConstantTexture::shared(0.2),
+            transmission: ConstantTexture::shared(1.0),
+            ..Principled::new(ConstantTexture::shared(Color::new(0.7, 0.95, 0.8)))
+        },
+    ];
+    for (i, material) in spheres.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Point3D::new(-2.4 + 1.6 * i as N, 0.7, 0.0),
+            0.7,
+            Arc::new(material),
+        )));
+    }
+    studio_rig(world)
+}
+
+// Clay spheres from smooth to rough in the studio, next to a Lambertian one
+// for comparison
+fn clay() -> Scene {
+    let mut world = Hittables::new();
+    let clay = Color::new(0.75, 0.45, 0.3);
+
+    studio_floor(
+        &mut world,
+        Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    let materials: Vec<SharedMaterial> = vec![
+        Arc::new(Lambert::new(clay)),
+        Arc::new(OrenNayar::new(clay, 10.0)),
+        Arc::new(OrenNayar::new(clay, 20.0)),
+        Arc::new(OrenNayar::new(clay, 40.0)),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Point3D::new(-2.4 + 1.6 * i as N, 0.7, 0.0),
+            0.7,
+            material,
+        )));
+    }
+    studio_rig(world)
+}
+
+// Aluminium brushed along the lines of latitude of spheres and along the
+// edge of a standing panel, from isotropic to strongly stretched
+fn brushed() -> Scene {
+    let mut world = Hittables::new();
+
+    studio_floor(
+        &mut world,
+        Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    let roughnesses = [(0.3, 0.3), (0.15, 0.4), (0.05, 0.6)];
+    for (i, (roughness_u, roughness_v)) in roughnesses.iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Point3D::new(-2.4 + 1.6 * i as N, 0.7, 0.0),
+            0.7,
+            Arc::new(Metal::aluminium(0.0).anisotropic(*roughness_u, *roughness_v)),
+        )));
+    }
+    world.add(Arc::new(Quad::new(
+        Point3D::new(1.7, 0.0, -0.7),
+        Vector3D::new(1.4, 0.0, 0.0),
+        Vector3D::new(0.0, 1.8, 0.0),
+        Arc::new(Metal::aluminium(0.0).anisotropic(0.05, 0.5)),
+    )));
+    studio_rig(world)
+}
+
+// Colored glass in the studio: a hollow amber bottle-like shell, solid green
+// glass darker where it's thicker, a frosted blue ball and a soap bubble, in
+// front of a thin tinted pane
+fn glass() -> Scene {
+    let mut world = Hittables::new();
+
+    studio_floor(
+        &mut world,
+        Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    // The negative radius turns the inner sphere inside out, so light only
+    // travels through glass between the two
+    let amber: SharedMaterial = Arc::new(Glass::new(1.5).absorbing(Color::new(0.5, 2.0, 6.0)));
+    let center = Point3D::new(-2.4, 0.7, 0.0);
+    world.add(Arc::new(Sphere::new(center, 0.7, amber.clone())));
+    world.add(Arc::new(Sphere::new(center, -0.6, amber)));
+    let materials: Vec<SharedMaterial> = vec![
+        Arc::new(Glass::new(1.5).absorbing(Color::new(1.5, 0.2, 1.2))),
+        Arc::new(
+            Glass::new(1.5)
+                .rough(0.3)
+                .absorbing(Color::new(1.2, 0.6, 0.1)),
+        ),
+        Arc::new(Glass::new(1.33).thin(Color::new(0.95, 0.95, 1.0))),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Point3D::new(-0.8 + 1.6 * i as N, 0.7, 0.0),
+            0.7,
+            material,
+        )));
+    }
+    world.add(Arc::new(Quad::new(
+        Point3D::new(-3.5, 0.0, -2.0),
+        Vector3D::new(7.0, 0.0, 0.0),
+        Vector3D::new(0.0, 2.5, 0.0),
+        Arc::new(Glass::new(1.5).thin(Color::new(0.7, 0.9, 0.8))),
+    )));
+    studio_rig(world)
+}
+
+// Value following `name` on the command line
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next();
+    args.next()
+}
+
+fn scene_name() -> String {
+    arg_value("--scene").unwrap_or_else(|| "random".to_string())
 }
 
 const ASPECT_RATIO: N = 3.0 / 2.0;
 const SAMPLES_PER_PIXEL: usize = 4;
-const MAX_DEPTH: usize = 50;
 
 lazy_static! {
-    static ref WORLD: Hittables = random_scene();
-    static ref CAMERA: Camera = {
-        // Camera setup
-        let lookat = Point3D::new(0.0, 0.0, 0.0);
-        let lookfrom = Point3D::new(13.0, 2.0, 3.0);
-        let vup = Vector3D::new(0.0, 1.0, 0.0);
-        let focus_distance = 10.0;
-        let aperature = 0.1;
-
-        Camera::new(
-            lookfrom,
-            lookat,
-            vup,
-            20.0,
+    static ref SCENE: Scene = {
+        let mut scene = match scene_name().as_str() {
+            "cornell" => cornell_box(),
+            "volumes" => cornell_volumes(),
+            "studio" => studio(),
+            "principled" => principled(),
+            "clay" => clay(),
+            "brushed" => brushed(),
+            "glass" => glass(),
+            _ => Scene::new(random_scene()),
+        };
+        if let Some(path) = arg_value("--env") {
+            let image = HdrImage::load(&path).unwrap();
+            let rotation = arg_value("--env-rotation").map_or(0.0, |r| r.parse().unwrap());
+            let intensity = arg_value("--env-intensity").map_or(1.0, |i| i.parse().unwrap());
+            scene.background = Arc::new(EnvironmentLight::new(image, rotation, intensity));
+        } else if let Some(elevation) = arg_value("--sky") {
+            // Sun position in degrees above the horizon and around from +x
+            let elevation: N = elevation.parse::<N>().unwrap().to_radians();
+            let azimuth = arg_value("--sun-azimuth").map_or(0.0, |a| a.parse::<N>().unwrap());
+            let azimuth = azimuth.to_radians();
+            let to_sun = Vector3D::new(
+                elevation.cos() * azimuth.cos(),
+                elevation.sin(),
+                elevation.cos() * azimuth.sin(),
+            );
+            let turbidity = arg_value("--turbidity").map_or(3.0, |t| t.parse().unwrap());
+            scene.background = Arc::new(Sky::new(to_sun, turbidity, Color::new(0.3, 0.3, 0.3)));
+        }
+        scene
+    };
+    static ref CAMERA: Camera = match scene_name().as_str() {
+        "cornell" | "volumes" => Camera::new(
+            Point3D::new(278.0, 278.0, -800.0),
+            Point3D::new(278.0, 278.0, 0.0),
+            Vector3D::new(0.0, 1.0, 0.0),
+            40.0,
             ASPECT_RATIO,
-            aperature,
-            focus_distance,
-            Some(0.0),
-            Some(1.0)
-        )
+            0.0,
+            10.0,
+            None,
+            None,
+        ),
+        "studio" | "principled" | "clay" | "brushed" | "glass" => Camera::new(
+            Point3D::new(0.0, 2.0, 9.0),
+            Point3D::new(0.0, 1.0, 0.0),
+            Vector3D::new(0.0, 1.0, 0.0),
+            30.0,
+            ASPECT_RATIO,
+            0.0,
+            9.0,
+            None,
+            None,
+        ),
+        _ => {
+            // Camera setup
+            let lookat = Point3D::new(0.0, 0.0, 0.0);
+            let lookfrom = Point3D::new(13.0, 2.0, 3.0);
+            let vup = Vector3D::new(0.0, 1.0, 0.0);
+            let focus_distance = 10.0;
+            let aperature = 0.1;
+
+            Camera::new(
+                lookfrom,
+                lookat,
+                vup,
+                20.0,
+                ASPECT_RATIO,
+                aperature,
+                focus_distance,
+                Some(0.0),
+                Some(1.0)
+            )
+        }
     };
 }
 
+// Length of the diagonal of the scene's bounding box
+fn scene_size() -> N {
+    let mut bounds = AABB::default();
+    SCENE.world.bounding_box(0.0, 0.0, &mut bounds);
+    (bounds.max() - bounds.min()).length()
+}
+
+fn integrator(image_width: usize, image_height: usize) -> SharedIntegrator {
+    let debug = |view| -> SharedIntegrator { Arc::new(DebugIntegrator::new(view)) };
+    match arg_value("--integrator").as_deref() {
+        Some("brute") => Arc::new(BruteForce),
+        Some("bdpt") => Arc::new(Bdpt::new(CAMERA.clone(), image_width, image_height)),
+        Some("mlt") => {
+            let bootstrap = arg_value("--bootstrap").map_or(100_000, |n| n.parse().unwrap());
+            Arc::new(Mlt::new(
+                CAMERA.clone(),
+                image_width,
+                image_height,
+                bootstrap,
+            ))
+        }
+        Some("ao") => {
+            // By default a tenth of the size of the scene
+            let distance = arg_value("--ao-distance")
+                .map_or_else(|| scene_size() / 10.0, |d| d.parse().unwrap());
+            Arc::new(AmbientOcclusion::new(distance, 4))
+        }
+        Some("direct") => Arc::new(DirectLighting),
+        Some("guided") => Arc::new(GuidedPathTracer),
+        Some("volumetric") => Arc::new(VolumetricPathTracer),
+        Some("sppm") => {
+            let photons = arg_value("--photons").map_or(200_000, |n| n.parse().unwrap());
+            // By default a hundredth of the size of the scene
+            let radius = arg_value("--photon-radius")
+                .map_or_else(|| scene_size() / 100.0, |r| r.parse().unwrap());
+            Arc::new(PhotonMapping::new(photons, radius))
+        }
+        Some("normals") => debug(DebugView::Normals),
+        Some("depth") => {
+            let distance =
+                arg_value("--max-distance").map_or_else(scene_size, |d| d.parse().unwrap());
+            debug(DebugView::Depth(distance))
+        }
+        Some("albedo") => debug(DebugView::Albedo),
+        Some("uv") => debug(DebugView::Uv),
+        Some("object-id") => debug(DebugView::ObjectId),
+        Some("material-id") => debug(DebugView::MaterialId),
+        Some("box-tests") => {
+            let max_tests = arg_value("--max-box-tests").map_or(100, |n| n.parse().unwrap());
+            debug(DebugView::BoxTests(max_tests))
+        }
+        _ => Arc::new(PathTracer),
+    }
+}
+
 fn main() {
+    let mode = if std::env::args().any(|arg| arg == "--packets") {
+        RenderMode::Packet
+    } else {
+        RenderMode::Single
+    };
+
     // Image dimensions
     let image_width: usize = 1200;
     let image_height = ((image_width as N) / ASPECT_RATIO) as usize;
@@ -125,7 +565,7 @@ fn main() {
     // Set up PNG encoder
     let path = Path::new(r"./output.png");
     let file = File::create(path).unwrap();
-    let ref mut w = BufWriter::new(file);
+    let w = &mut BufWriter::new(file);
     let mut encoder = png::Encoder::new(w, image_width as u32, image_height as u32);
     encoder.set_color(png::ColorType::RGB);
     encoder.set_depth(png::BitDepth::Eight);
@@ -133,16 +573,38 @@ fn main() {
 
     let progress = Arc::new(ProgressBar::new((image_height * num_cpus::get()) as u64));
 
-    let buf = render::sample(
+    // Comma separated, or all of them
+    let aovs: Vec<Aov> = match arg_value("--aovs").as_deref() {
+        Some("all") => Aov::ALL.to_vec(),
+        Some(names) => names
+            .split(',')
+            .map(|name| Aov::from_name(name).expect("unknown AOV"))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let (buf, aov_bufs) = render::sample(
         image_height,
         image_width,
         SAMPLES_PER_PIXEL,
-        MAX_DEPTH,
         &CAMERA,
-        &WORLD,
+        &SCENE,
+        integrator(image_width, image_height),
+        &aovs,
+        mode,
         progress,
     );
 
-    // Write buffer
+    // Write buffers
     writer.write_image_data(&buf).unwrap();
+    for (aov, aov_buf) in aovs.iter().zip(aov_bufs) {
+        let file = File::create(format!("./output.{}.pfm", aov.name())).unwrap();
+        aov::write_pfm(
+            &mut BufWriter::new(file),
+            image_width,
+            image_height,
+            &aov_buf,
+        )
+        .unwrap();
+    }
 }
@@ -0,0 +1,32 @@
+use super::Material;
+use crate::hittables::HitRecord;
+use crate::ray::Ray;
+use crate::vector::Color;
+
+/// Emits light equally in all directions from both sides of a surface
+pub struct DiffuseLight(Color);
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self(emit)
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _: &Ray, _: &mut HitRecord, _: &mut Color, _: &mut Ray) -> bool {
+        false
+    }
+
+    fn emitted(&self, _: &HitRecord) -> Color {
+        self.0
+    }
+
+    // Scaled down to show the hue of bright lights
+    fn albedo(&self, _: &HitRecord) -> Color {
+        self.0 / self.0.max_element().max(1.0)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
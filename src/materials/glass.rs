@@ -1,16 +1,68 @@
+use super::microfacet::{fresnel_dielectric, mirror, transmit, Ggx};
 use super::Material;
 use crate::hittables::HitRecord;
+use crate::media::SharedMedium;
 use crate::ray::Ray;
 use crate::utils::random_n;
-use crate::vector::{Color, N};
+use crate::vector::{Color, Vector3D, N};
 
+/// Dielectric that reflects and refracts by the Fresnel equations, smooth and
+/// clear unless made otherwise by chaining the options below onto `new`, like
+/// `Glass::new(1.5).rough(0.3).absorbing(absorption)`
 pub struct Glass {
     ior: N,
+    interior: Option<SharedMedium>,
+    /// Chance per unit distance, for each channel, of light travelling inside
+    /// being absorbed
+    absorption: Color,
+    /// Color light passing through thin-walled glass is tinted by, which
+    /// doesn't bend it either. `None` for solid glass.
+    thin: Option<Color>,
+    /// Microfacets of frosted glass, `None` for smooth glass
+    distribution: Option<Ggx>,
 }
 
 impl Glass {
     pub fn new(ior: N) -> Self {
-        Self { ior }
+        Self {
+            ior,
+            interior: None,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            thin: None,
+            distribution: None,
+        }
+    }
+
+    /// Fill the glass with `interior`, like a liquid in a bottle
+    pub fn filled(mut self, interior: SharedMedium) -> Self {
+        self.interior = Some(interior);
+        self
+    }
+
+    /// Color the glass, absorbing light by the Beer-Lambert law so thicker
+    /// parts are darker and more saturated. Light is absorbed by how far it
+    /// travelled when it reaches the inside of this glass's surface, so a
+    /// path inside that ends on another object, like a liquid in a bottle,
+    /// isn't absorbed on the way there. Such objects should be given the
+    /// glass's absorption themselves.
+    pub fn absorbing(mut self, absorption: Color) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    /// Make the glass thin-walled, like a window pane or a bubble, whose sides
+    /// are too close together to bend light or absorb it by thickness. Light
+    /// passing through is tinted by `tint` instead.
+    pub fn thin(mut self, tint: Color) -> Self {
+        self.thin = Some(tint);
+        self
+    }
+
+    /// Frost the glass, making its surface a GGX distribution of microfacets
+    /// that each reflect and refract like smooth glass
+    pub fn rough(mut self, roughness: N) -> Self {
+        self.distribution = Some(Ggx::new(roughness)).filter(|ggx| !ggx.is_smooth());
+        self
     }
 
     fn reflectance(cosine: N, ref_idx: N) -> N {
@@ -18,6 +70,109 @@ impl Glass {
         r0 *= r0;
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
+
+    // Reflectance of thin-walled glass, where light bouncing back and forth
+    // between the two sides adds to what each reflects
+    fn thin_reflectance(&self, cosine: N) -> N {
+        let r = Self::reflectance(cosine, 1.0 / self.ior);
+        if r < 1.0 {
+            r + (1.0 - r) * (1.0 - r) * r / (1.0 - r * r)
+        } else {
+            1.0
+        }
+    }
+
+    // Index of refraction on the far side of the surface hit over that on the
+    // near side
+    fn eta(&self, hit_record: &HitRecord) -> N {
+        if hit_record.front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+
+    // Microfacet normal that reflects or refracts the local `wo` to `wi`,
+    // facing up, and the relative index of refraction along the way, 1 for
+    // reflection. `None` if no microfacet facing both of them does.
+    fn half_vector(
+        &self,
+        hit_record: &HitRecord,
+        wi: &Vector3D,
+        wo: &Vector3D,
+    ) -> Option<(Vector3D, N)> {
+        let (cos_i, cos_o) = (*wi.z(), *wo.z());
+        if cos_i == 0.0 || cos_o == 0.0 {
+            return None;
+        }
+        let eta = self.eta(hit_record);
+        let etap = if cos_i * cos_o > 0.0 {
+            1.0
+        } else if cos_o > 0.0 {
+            eta
+        } else {
+            1.0 / eta
+        };
+        let wm = *wi * etap + *wo;
+        if wm.near_zero() {
+            return None;
+        }
+        let mut wm = wm.unit();
+        if *wm.z() < 0.0 {
+            wm = -wm;
+        }
+        if wm.dot(wi) * cos_i < 0.0 || wm.dot(wo) * cos_o < 0.0 {
+            return None;
+        }
+        Some((wm, etap))
+    }
+
+    // Rough thin-walled glass lets through what it doesn't reflect as if
+    // mirrored by the microfacet and then through the surface, unbent. The
+    // local `wi` on the side of `wo`, the microfacet normal between them,
+    // facing up, and whether `wi` was on the other side. `None` if no
+    // microfacet facing both of them does.
+    fn thin_half_vector(wi: &Vector3D, wo: &Vector3D) -> Option<(Vector3D, Vector3D, bool)> {
+        let transmitted = wi.z() * wo.z() < 0.0;
+        let wi = if transmitted { flip(wi) } else { *wi };
+        let wm = wi + *wo;
+        if wm.near_zero() || *wi.z() == 0.0 || *wo.z() == 0.0 {
+            return None;
+        }
+        let mut wm = wm.unit();
+        if *wm.z() < 0.0 {
+            wm = -wm;
+        }
+        if wm.dot(&wi) * wi.z() < 0.0 || wm.dot(wo) * wo.z() < 0.0 {
+            return None;
+        }
+        Some((wi, wm, transmitted))
+    }
+
+    // Fraction of the light travelling `distance` inside that gets through
+    fn transmittance(&self, distance: N) -> Color {
+        let channel = |absorption: N| (-absorption * distance).exp();
+        Color::new(
+            channel(*self.absorption.x()),
+            channel(*self.absorption.y()),
+            channel(*self.absorption.z()),
+        )
+    }
+
+    // What's left of light that reached the hit point, which has travelled
+    // through the glass if it hit the inside of the surface
+    fn absorbed(&self, hit_record: &HitRecord) -> Color {
+        if hit_record.front_face || self.thin.is_some() {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            self.transmittance(hit_record.distance)
+        }
+    }
+}
+
+// `w` mirrored through the local surface
+fn flip(w: &Vector3D) -> Vector3D {
+    Vector3D::new(*w.x(), *w.y(), -w.z())
 }
 
 impl Material for Glass {
@@ -28,15 +183,74 @@ impl Material for Glass {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+        let unit_direction = r_in.direction().unit();
+        let cos_theta = (-unit_direction).dot(&hit_record.normal).min(1.0);
+
+        if let Some(distribution) = &self.distribution {
+            // Reflect off or pass through a microfacet picked by how much of
+            // it can be seen, by its Fresnel reflectance
+            let frame = hit_record.shading_frame();
+            let wo = frame.to_local(&-unit_direction);
+            let wm = distribution.sample_visible(&wo);
+            let wi = if self.thin.is_some() {
+                let wi = mirror(&wo, &wm);
+                if wi.z() * wo.z() <= 0.0 {
+                    return false;
+                }
+                if self.thin_reflectance(wo.dot(&wm)) > random_n() {
+                    wi
+                } else {
+                    flip(&wi)
+                }
+            } else {
+                let eta = self.eta(hit_record);
+                let wi = if fresnel_dielectric(wo.dot(&wm), eta) > random_n() {
+                    mirror(&wo, &wm)
+                } else {
+                    match transmit(&wo, &wm, eta) {
+                        Some((wi, _)) => wi,
+                        None => return false,
+                    }
+                };
+                // Bounced back to the side it came from by the microfacet, or
+                // back through, which the lobe it picked doesn't account for
+                let reflected = wi.z() * wo.z() > 0.0;
+                if reflected != (wi.dot(&wm) * wo.dot(&wm) > 0.0) {
+                    return false;
+                }
+                wi
+            };
+            let wi = frame.to_world(&wi);
+            let wo = -unit_direction;
+            let pdf = self.pdf(hit_record, &wi, &wo);
+            if pdf <= 0.0 {
+                return false;
+            }
+            *scattered = hit_record.spawn_ray(wi, *r_in.time());
+            *attenuation = self.eval(hit_record, &wi, &wo) / pdf;
+            return true;
+        }
+
+        if let Some(tint) = self.thin {
+            let direction = if self.thin_reflectance(cos_theta) > random_n() {
+                *attenuation = Color::new(1.0, 1.0, 1.0);
+                super::reflect(&unit_direction, &hit_record.normal)
+            } else {
+                *attenuation = tint;
+                unit_direction
+            };
+            *scattered = hit_record.spawn_ray(direction, *r_in.time());
+            return true;
+        }
+
+        // Hitting the inside of the surface ends a path through the glass
+        *attenuation = self.absorbed(hit_record);
         let refraction_ratio = if hit_record.front_face {
             1.0 / self.ior
         } else {
             self.ior
         };
 
-        let unit_direction = r_in.direction().unit();
-        let cos_theta = (-unit_direction).dot(&hit_record.normal).min(1.0);
         let sine_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sine_theta > 1.0;
@@ -48,8 +262,199 @@ impl Material for Glass {
                 super::refract(&unit_direction, &hit_record.normal, refraction_ratio)
             };
 
-        *scattered = Ray::new(hit_record.p, direction, *r_in.time());
+        *scattered = hit_record.spawn_ray(direction, *r_in.time());
+
+        true
+    }
+    fn albedo(&self, _: &HitRecord) -> Color {
+        self.thin.unwrap_or_else(|| Color::new(1.0, 1.0, 1.0))
+    }
+
+    fn interior(&self) -> Option<&SharedMedium> {
+        self.interior.as_ref()
+    }
+
+    // Microfacet BRDF on the side of `wo`, or BTDF through to the other side,
+    // after what the glass absorbed on the way to the hit point
+    fn eval(&self, hit_record: &HitRecord, wi: &Vector3D, wo: &Vector3D) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return black,
+        };
+        let frame = hit_record.shading_frame();
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+
+        if let Some(tint) = self.thin {
+            let (wi, wm, transmitted) = match Self::thin_half_vector(&wi, &wo) {
+                Some(half) => half,
+                None => return black,
+            };
+            let f = self.thin_reflectance(wo.dot(&wm).abs());
+            let value = distribution.d(&wm) * distribution.g(&wo, &wi) / (4.0 * wo.z().abs());
+            return if transmitted {
+                tint * (value * (1.0 - f))
+            } else {
+                Color::new(1.0, 1.0, 1.0) * (value * f)
+            };
+        }
+
+        let (wm, etap) = match self.half_vector(hit_record, &wi, &wo) {
+            Some(half) => half,
+            None => return black,
+        };
+        let f = fresnel_dielectric(wo.dot(&wm), self.eta(hit_record));
+        let d_g = distribution.d(&wm) * distribution.g(&wo, &wi);
+        let value = if etap == 1.0 {
+            d_g * f / (4.0 * wo.z().abs())
+        } else {
+            let denominator = wi.dot(&wm) + wo.dot(&wm) / etap;
+            d_g * (1.0 - f) * (wi.dot(&wm) * wo.dot(&wm)).abs()
+                / (denominator * denominator * wo.z().abs())
+        };
+        self.absorbed(hit_record) * value
+    }
 
+    // Density of the microfacet normal, over the change from normals to the
+    // directions they reflect or refract to, by the chance of each
+    fn pdf(&self, hit_record: &HitRecord, wi: &Vector3D, wo: &Vector3D) -> N {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.0,
+        };
+        let frame = hit_record.shading_frame();
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+
+        if self.thin.is_some() {
+            let (_, wm, transmitted) = match Self::thin_half_vector(&wi, &wo) {
+                Some(half) => half,
+                None => return 0.0,
+            };
+            let f = self.thin_reflectance(wo.dot(&wm).abs());
+            let pdf = distribution.pdf_visible(&wo, &wm) / (4.0 * wo.dot(&wm).abs());
+            return pdf * if transmitted { 1.0 - f } else { f };
+        }
+
+        let (wm, etap) = match self.half_vector(hit_record, &wi, &wo) {
+            Some(half) => half,
+            None => return 0.0,
+        };
+        let f = fresnel_dielectric(wo.dot(&wm), self.eta(hit_record));
+        let pdf = distribution.pdf_visible(&wo, &wm);
+        if etap == 1.0 {
+            pdf / (4.0 * wo.dot(&wm).abs()) * f
+        } else {
+            let denominator = wi.dot(&wm) + wo.dot(&wm) / etap;
+            pdf * wi.dot(&wm).abs() / (denominator * denominator) * (1.0 - f)
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        self.distribution.is_none()
+    }
+
+    fn is_specular(&self) -> bool {
         true
     }
 }
+
+#[test]
+fn absorbs_by_distance_inside() {
+    use crate::vector::{Point3D, Vector3D};
+
+    let absorption = Color::new(0.1, 0.5, 2.0);
+    let glass = Glass::new(1.5).absorbing(absorption);
+    let r_in = Ray::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        Vector3D::new(0.0, 0.0, 2.0),
+        0.0,
+    );
+    let mut hit_record = HitRecord {
+        p: Point3D::new(0.0, 0.0, 3.0),
+        normal: Vector3D::new(0.0, 0.0, -1.0),
+        t: 1.5,
+        distance: 3.0,
+        ..Default::default()
+    };
+    let mut attenuation = Color::default();
+    let mut scattered = Ray::default();
+
+    // Reflected or not, the 3 units travelled inside are absorbed
+    for _ in 0..100 {
+        glass.scatter(&r_in, &mut hit_record, &mut attenuation, &mut scattered);
+        let expected = Color::new((-0.3 as N).exp(), (-1.5 as N).exp(), (-6.0 as N).exp());
+        assert!((attenuation - expected).length() < 1e-6);
+    }
+
+    // Entering from outside, nothing has been absorbed yet
+    hit_record.front_face = true;
+    glass.scatter(&r_in, &mut hit_record, &mut attenuation, &mut scattered);
+    assert_eq!(attenuation, Color::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn rough_scatter_matches_eval_and_pdf() {
+    use super::microfacet::check_sampling;
+    use crate::vector::Point3D;
+
+    let r_in = Ray::new(
+        Point3D::new(-1.0, 2.0, 0.0),
+        Vector3D::new(1.0, -2.0, 0.0),
+        0.0,
+    );
+    let absorbing = Glass::new(1.5)
+        .rough(0.5)
+        .absorbing(Color::new(0.1, 0.5, 2.0));
+    let thin = Glass::new(1.5).rough(0.5).thin(Color::new(0.9, 0.6, 0.3));
+    for glass in &[Glass::new(1.5).rough(0.5), absorbing, thin] {
+        // From outside and from inside the glass
+        for &front_face in &[true, false] {
+            let mut hit_record = HitRecord {
+                normal: Vector3D::new(0.0, 1.0, 0.0),
+                distance: 1.0,
+                front_face,
+                ..Default::default()
+            };
+            let sampled = check_sampling(glass, &mut hit_record, &r_in);
+            assert!(sampled.max_element() <= 1.0);
+        }
+    }
+}
+
+#[test]
+fn thin_glass_tints_what_it_lets_through() {
+    use crate::vector::Point3D;
+
+    let tint = Color::new(0.9, 0.6, 0.3);
+    let glass = Glass::new(1.5).thin(tint);
+    let mut hit_record = HitRecord {
+        normal: Vector3D::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..Default::default()
+    };
+    let r_in = Ray::new(
+        Point3D::new(0.0, 1.0, 0.0),
+        Vector3D::new(0.0, -1.0, 0.0),
+        0.0,
+    );
+
+    // Head on, each side reflects 4%, and light bouncing between them adds to
+    // that. What isn't reflected goes straight through, tinted.
+    let samples = 100_000;
+    let mut reflected = 0;
+    for _ in 0..samples {
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        assert!(glass.scatter(&r_in, &mut hit_record, &mut attenuation, &mut scattered));
+        if *scattered.direction().y() > 0.0 {
+            assert_eq!(attenuation, Color::new(1.0, 1.0, 1.0));
+            reflected += 1;
+        } else {
+            assert_eq!(*scattered.direction(), *r_in.direction());
+            assert_eq!(attenuation, tint);
+        }
+    }
+    let expected = 0.04 + 0.96 * 0.96 * 0.04 / (1.0 - 0.04 * 0.04);
+    assert!((reflected as N / samples as N - expected).abs() < 0.005);
+    assert!((glass.thin_reflectance(0.0) - 1.0).abs() < 1e-6);
+}
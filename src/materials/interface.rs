@@ -0,0 +1,44 @@
+use super::Material;
+use crate::hittables::HitRecord;
+use crate::media::SharedMedium;
+use crate::ray::Ray;
+use crate::vector::Color;
+
+/// Invisible surface bounding a medium, like the extent of a cloud of smoke.
+/// Rays pass straight through it, entering or leaving the medium.
+pub struct Interface {
+    interior: SharedMedium,
+}
+
+impl Interface {
+    pub fn new(interior: SharedMedium) -> Self {
+        Self { interior }
+    }
+}
+
+impl Material for Interface {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        hit_record: &mut HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        *attenuation = Color::new(1.0, 1.0, 1.0);
+        *scattered = hit_record.spawn_ray(*r_in.direction(), *r_in.time());
+        true
+    }
+
+    // Passes light straight through
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn interior(&self) -> Option<&SharedMedium> {
+        Some(&self.interior)
+    }
+
+    fn is_interface(&self) -> bool {
+        true
+    }
+}
@@ -1,8 +1,8 @@
 use super::Material;
 use crate::hittables::HitRecord;
 use crate::ray::Ray;
-use crate::utils::random_unit_vector;
-use crate::vector::Color;
+use crate::utils::{random_unit_vector, PI};
+use crate::vector::{Color, Vector3D, N};
 
 pub struct Lambert(Color);
 
@@ -25,8 +25,21 @@ impl Material for Lambert {
             scatter_direction = hit_record.normal;
         }
 
-        *scattered = Ray::new(hit_record.p, scatter_direction, *r_in.time());
+        *scattered = hit_record.spawn_ray(scatter_direction, *r_in.time());
         *attenuation = self.0;
         true
     }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        self.0
+    }
+
+    fn eval(&self, hit_record: &HitRecord, wi: &Vector3D, wo: &Vector3D) -> Color {
+        self.0 * self.pdf(hit_record, wi, wo)
+    }
+
+    // `scatter` picks directions proportional to the cosine with the normal
+    fn pdf(&self, hit_record: &HitRecord, wi: &Vector3D, _: &Vector3D) -> N {
+        hit_record.normal.dot(wi).max(0.0) / PI
+    }
 }
@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
 use crate::hittables::HitRecord;
+use crate::media::SharedMedium;
 use crate::ray::Ray;
-use crate::vector::Color;
+use crate::vector::{Color, Vector3D, N};
 
 pub type SharedMaterial = Arc<dyn Material + Sync + Send>;
 
@@ -14,4 +15,58 @@ pub trait Material {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool;
+
+    /// Light given off by the surface at the hit point
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Base color of the surface, for inspecting scenes
+    fn albedo(&self, _hit_record: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Whether objects with this material should be sampled as lights
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// BSDF times the cosine of `wi` with the normal, for light arriving from
+    /// `wi` and leaving towards `wo` (both unit vectors pointing away from the
+    /// surface). Consistent with `scatter`, so that its attenuation is
+    /// `eval / pdf` for the direction it picked.
+    fn eval(&self, _hit_record: &HitRecord, _wi: &Vector3D, _wo: &Vector3D) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Density over solid angle of `scatter` picking `wi` for light leaving
+    /// towards `wo`
+    fn pdf(&self, _hit_record: &HitRecord, _wi: &Vector3D, _wo: &Vector3D) -> N {
+        0.0
+    }
+
+    /// Whether `scatter` only ever picks from a finite set of directions, like
+    /// a perfect mirror. `eval` and `pdf` are meaningless for such materials,
+    /// so lights aren't sampled at their hit points.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Whether light leaving the surface counts as specular rather than
+    /// diffuse when breaking the image down into AOVs
+    fn is_specular(&self) -> bool {
+        self.is_delta()
+    }
+
+    /// Medium filling the inside of closed objects with this material, which
+    /// paths passing through the surface enter or leave
+    fn interior(&self) -> Option<&SharedMedium> {
+        None
+    }
+
+    /// Whether the surface only marks the boundary of a medium, letting light
+    /// through untouched, so shadow rays pass it too
+    fn is_interface(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,272 @@
+use crate::utils::{random_n, PI};
+use crate::vector::{Color, Vector3D, N};
+
+// Shared by the microfacet materials, which model a rough surface as a mass
+// of tiny mirrors facing every which way. Directions are in a local frame
+// with the surface normal along z.
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals, with Smith's
+/// masking-shadowing
+#[derive(Clone, Copy)]
+pub(super) struct Ggx {
+    alpha_x: N,
+    alpha_y: N,
+}
+
+impl Ggx {
+    /// Isotropic distribution for a perceptual `roughness` in [0, 1], which
+    /// squares to the width of the distribution
+    pub(super) fn new(roughness: N) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        Self {
+            alpha_x: alpha,
+            alpha_y: alpha,
+        }
+    }
+
+    /// Distribution stretched along the local axes, rough by `roughness_x`
+    /// along x and `roughness_y` along y
+    pub(super) fn anisotropic(roughness_x: N, roughness_y: N) -> Self {
+        Self {
+            alpha_x: roughness_x.clamp(0.0, 1.0).powi(2),
+            alpha_y: roughness_y.clamp(0.0, 1.0).powi(2),
+        }
+    }
+
+    /// Whether the surface is so smooth it is better treated as a mirror
+    pub(super) fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    /// Density of microfacet normals `wm` per unit solid angle, projected onto
+    /// the surface
+    pub(super) fn d(&self, wm: &Vector3D) -> N {
+        if *wm.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let e = x * x + y * y + wm.z() * wm.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    // Ratio of hidden to visible microfacet area seen from `w`
+    fn lambda(&self, w: &Vector3D) -> N {
+        let x = self.alpha_x * w.x();
+        let y = self.alpha_y * w.y();
+        let z_sq = w.z() * w.z();
+        ((1.0 + (x * x + y * y) / z_sq).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of microfacets visible from `w`
+    pub(super) fn g1(&self, w: &Vector3D) -> N {
+        if *w.z() == 0.0 {
+            return 0.0;
+        }
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`
+    pub(super) fn g(&self, wo: &Vector3D, wi: &Vector3D) -> N {
+        if *wo.z() == 0.0 || *wi.z() == 0.0 {
+            return 0.0;
+        }
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of `sample_visible` picking `wm` as seen from `w`
+    pub(super) fn pdf_visible(&self, w: &Vector3D, wm: &Vector3D) -> N {
+        // Facing away from `w`, or seen from below
+        let cosine = w.dot(wm);
+        if cosine * w.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z().abs() * self.d(wm) * cosine.abs()
+    }
+
+    /// Pick a microfacet normal by the area of it visible from `w`, after
+    /// "Sampling the GGX Distribution of Visible Normals" (Heitz 2018)
+    pub(super) fn sample_visible(&self, w: &Vector3D) -> Vector3D {
+        // Stretch to where the distribution is a hemisphere
+        let mut wh = Vector3D::new(self.alpha_x * w.x(), self.alpha_y * w.y(), *w.z()).unit();
+        if *wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if *wh.z() < 0.99999 {
+            Vector3D::new(0.0, 0.0, 1.0).cross(&wh).unit()
+        } else {
+            Vector3D::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Uniform point on a disk, squashed onto the part of the hemisphere
+        // seen from `wh`
+        let r = random_n().sqrt();
+        let phi = 2.0 * PI * random_n();
+        let (x, mut y) = (r * phi.cos(), r * phi.sin());
+        let h = (1.0 - x * x).max(0.0).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        y = (1.0 - s) * h + s * y;
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        let nh = t1 * x + t2 * y + wh * z;
+
+        Vector3D::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit()
+    }
+}
+
+/// `w` mirrored about `n`, both pointing away from the surface
+pub(super) fn mirror(w: &Vector3D, n: &Vector3D) -> Vector3D {
+    n * (2.0 * w.dot(n)) - *w
+}
+
+/// `w` refracted through a surface with normal `n`, both pointing away from
+/// it, where `eta` is the index of refraction below over that above. Also the
+/// relative index along the way it went, `None` on total internal reflection.
+pub(super) fn transmit(w: &Vector3D, n: &Vector3D, eta: N) -> Option<(Vector3D, N)> {
+    let (mut n, mut eta) = (*n, eta);
+    let mut cos_i = w.dot(&n);
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+        n = -n;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((-*w / eta + n * (cos_i / eta - cos_t), eta))
+}
+
+/// Fraction of unpolarized light reflected off a dielectric, where `eta` is
+/// the index of refraction below the surface over that above
+pub(super) fn fresnel_dielectric(cos_i: N, eta: N) -> N {
+    let (mut cos_i, mut eta) = (cos_i.clamp(-1.0, 1.0), eta);
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+/// Schlick's approximation of the Fresnel reflectance, reflecting `f0` head
+/// on and white towards grazing angles
+pub(super) fn fresnel_schlick(cos_i: N, f0: &Color) -> Color {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    *f0 + (Color::new(1.0, 1.0, 1.0) - *f0) * (1.0 - cos_i).powi(5)
+}
+
+/// Fraction of light reflected off a conductor with the complex index of
+/// refraction `eta` + i`k`, for each channel
+pub(super) fn fresnel_conductor(cos_i: N, eta: &Color, k: &Color) -> Color {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let channel = |eta: N, k: N| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        (rp + rs) / 2.0
+    };
+    Color::new(
+        channel(*eta.x(), *k.x()),
+        channel(*eta.y(), *k.y()),
+        channel(*eta.z(), *k.z()),
+    )
+}
+
+/// Integral of `f` over the sphere of directions, by the midpoint rule on an
+/// area-preserving grid
+#[cfg(test)]
+pub(super) fn integrate_sphere<T>(steps: usize, f: impl Fn(&Vector3D) -> T) -> T
+where
+    T: std::ops::Add<Output = T> + std::ops::Mul<N, Output = T> + Default,
+{
+    let area = (2.0 / steps as N) * (2.0 * PI / steps as N);
+    let mut total = T::default();
+    for i in 0..steps {
+        let z = -1.0 + 2.0 * (i as N + 0.5) / steps as N;
+        let r = (1.0 - z * z).sqrt();
+        for j in 0..steps {
+            let phi = 2.0 * PI * (j as N + 0.5) / steps as N;
+            total = total + f(&Vector3D::new(r * phi.cos(), r * phi.sin(), z)) * area;
+        }
+    }
+    total
+}
+
+/// Check that the attenuations `material` samples for `r_in` are `eval / pdf`,
+/// so average out to the integral of `eval`, and that paths survive as often
+/// as the integral of `pdf`. Returns the average attenuation.
+#[cfg(test)]
+pub(super) fn check_sampling(
+    material: &dyn super::Material,
+    hit_record: &mut crate::hittables::HitRecord,
+    r_in: &crate::ray::Ray,
+) -> Color {
+    use crate::ray::Ray;
+
+    let wo = -r_in.direction().unit();
+    // `scatter` and `eval` take different routes to the same value, which
+    // single precision rounds further apart where the density is tiny
+    let tolerance = if cfg!(feature = "f32") { 1e-2 } else { 1e-4 };
+    let samples = 200_000;
+    let mut sampled = Color::new(0.0, 0.0, 0.0);
+    let mut survived = 0;
+    for _ in 0..samples {
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        if material.scatter(r_in, hit_record, &mut attenuation, &mut scattered) {
+            let wi = scattered.direction().unit();
+            let expected = material.eval(hit_record, &wi, &wo) / material.pdf(hit_record, &wi, &wo);
+            assert!((attenuation - expected).length() < tolerance * expected.length().max(1.0));
+            sampled += attenuation;
+            survived += 1;
+        }
+    }
+    let sampled = sampled / samples as N;
+    let eval = integrate_sphere(1000, |wi| material.eval(hit_record, wi, &wo));
+    let pdf = integrate_sphere(1000, |wi| material.pdf(hit_record, wi, &wo));
+    assert!((sampled - eval).length() < 0.01);
+    assert!((survived as N / samples as N - pdf).abs() < 0.01);
+    sampled
+}
+
+#[test]
+fn visible_normals_match_their_density() {
+    let w = Vector3D::new(0.6, 0.0, 0.8);
+    for &roughness in &[0.5, 0.8] {
+        let ggx = Ggx::new(roughness);
+
+        // Integrate the density, and the mean normal under it, over the sphere
+        let total = integrate_sphere(500, |wm| ggx.pdf_visible(&w, wm));
+        let mean = integrate_sphere(500, |wm| *wm * ggx.pdf_visible(&w, wm));
+        assert!((total - 1.0).abs() < 0.01);
+
+        // Which sampling has to agree with
+        let samples = 100_000;
+        let mut sampled = Vector3D::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            sampled += ggx.sample_visible(&w);
+        }
+        assert!((sampled / samples as N - mean).length() < 0.01);
+    }
+}
@@ -1,11 +1,20 @@
+mod diffuse_light;
 mod glass;
+mod interface;
 mod lambert;
 mod material;
 mod metal;
+mod microfacet;
+mod oren_nayar;
+mod principled;
 mod utils;
 
+pub use diffuse_light::*;
 pub use glass::*;
+pub use interface::*;
 pub use lambert::*;
 pub use material::*;
 pub use metal::*;
+pub use oren_nayar::*;
+pub use principled::*;
 use utils::*;
@@ -0,0 +1,129 @@
+use super::Material;
+use crate::hittables::HitRecord;
+use crate::ray::Ray;
+use crate::utils::{random_unit_vector, PI};
+use crate::vector::{Color, Vector3D, N};
+
+/// Rough diffuse surface after "Generalization of Lambert's Reflectance Model"
+/// (Oren and Nayar 1994), in its qualitative form. The surface is a mass of
+/// tiny Lambertian V-shaped grooves, which scatter more light back towards
+/// where it came from and flatten the falloff towards the edges, like clay or
+/// unglazed ceramic.
+pub struct OrenNayar {
+    albedo: Color,
+    a: N,
+    b: N,
+}
+
+impl OrenNayar {
+    /// `sigma` is the standard deviation of the angle the grooves' sides lean
+    /// by, in degrees, where 0 is `Lambert`
+    pub fn new(albedo: Color, sigma: N) -> Self {
+        let sigma2 = sigma.to_radians().powi(2);
+        Self {
+            albedo,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+
+    // Reflectance relative to a Lambertian surface, for light arriving from
+    // `wi` and leaving towards `wo` on the side `n` faces
+    fn factor(&self, n: &Vector3D, wi: &Vector3D, wo: &Vector3D) -> N {
+        let (cos_i, cos_o) = (n.dot(wi), n.dot(wo).max(0.0));
+        let sin_i = (1.0 - cos_i * cos_i).max(0.0).sqrt();
+        let sin_o = (1.0 - cos_o * cos_o).max(0.0).sqrt();
+
+        // Cosine of the angle between the directions around the normal
+        let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+            let wi_perp = *wi - *n * cos_i;
+            let wo_perp = *wo - *n * cos_o;
+            (wi_perp.dot(&wo_perp) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        // Sine of the larger angle to the normal, tangent of the smaller
+        let (sin_alpha, tan_beta) = if cos_i > cos_o {
+            (sin_o, sin_i / cos_i)
+        } else {
+            (sin_i, sin_o / cos_o.max(1e-6))
+        };
+        self.a + self.b * cos_phi * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    // Picks directions like `Lambert`, where the cosine and 1/pi cancel out of
+    // `eval / pdf`
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        hit_record: &mut HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let mut scatter_direction = hit_record.normal + random_unit_vector();
+        if scatter_direction.near_zero() {
+            scatter_direction = hit_record.normal;
+        }
+        let wi = scatter_direction.unit();
+        let wo = -r_in.direction().unit();
+
+        *scattered = hit_record.spawn_ray(wi, *r_in.time());
+        *attenuation = self.albedo * self.factor(&hit_record.normal, &wi, &wo);
+        true
+    }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn eval(&self, hit_record: &HitRecord, wi: &Vector3D, wo: &Vector3D) -> Color {
+        let pdf = self.pdf(hit_record, wi, wo);
+        if pdf == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.albedo * (pdf * self.factor(&hit_record.normal, wi, wo))
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wi: &Vector3D, _: &Vector3D) -> N {
+        hit_record.normal.dot(wi).max(0.0) / PI
+    }
+}
+
+#[test]
+fn scatter_matches_eval_and_pdf() {
+    use super::microfacet::check_sampling;
+    use super::Lambert;
+    use crate::vector::Point3D;
+
+    let albedo = Color::new(0.8, 0.6, 0.4);
+    let mut hit_record = HitRecord {
+        normal: Vector3D::new(0.0, 1.0, 0.0),
+        ..Default::default()
+    };
+    let r_in = Ray::new(
+        Point3D::new(-1.0, 1.0, 0.5),
+        Vector3D::new(1.0, -1.0, -0.5),
+        0.0,
+    );
+    let wo = -r_in.direction().unit();
+
+    // Without grooves it's `Lambert`
+    let smooth = OrenNayar::new(albedo, 0.0);
+    let lambert = Lambert::new(albedo);
+    let wi = Vector3D::new(0.3, 0.8, -0.5).unit();
+    let difference = smooth.eval(&hit_record, &wi, &wo) - lambert.eval(&hit_record, &wi, &wo);
+    assert!(difference.length() < 1e-6);
+
+    let rough = OrenNayar::new(albedo, 30.0);
+    let sampled = check_sampling(&rough, &mut hit_record, &r_in);
+    // Light is lost between the grooves, but none is made
+    assert!(sampled.max_element() <= albedo.max_element());
+
+    // Retroreflects, more so than it does towards the mirror direction
+    let back = rough.eval(&hit_record, &wo, &wo);
+    let mirrored = Vector3D::new(-wo.x(), *wo.y(), -wo.z());
+    let forward = rough.eval(&hit_record, &mirrored, &wo);
+    assert!(back.x() > forward.x());
+}
@@ -0,0 +1,384 @@
+use super::microfacet::{fresnel_dielectric, fresnel_schlick, mirror, transmit, Ggx};
+use super::Material;
+use crate::hittables::HitRecord;
+use crate::ray::Ray;
+use crate::textures::{Constant, SharedTexture};
+use crate::utils::{random_n, random_unit_vector, PI};
+use crate::vector::{Color, Onb, Vector3D, N};
+
+/// Disney's principled BSDF ("Physically Based Shading at Disney", Burley
+/// 2012), mixing a diffuse base, sheen, a GGX specular layer, a clearcoat and
+/// rough transmission by a handful of artist friendly parameters in [0, 1].
+/// Every parameter is a texture, so they can vary over the surface like those
+/// imported from glTF or MTL files.
+pub struct Principled {
+    pub base_color: SharedTexture<Color>,
+    /// Blends from a dielectric to a metal tinted by the base color
+    pub metallic: SharedTexture<N>,
+    pub roughness: SharedTexture<N>,
+    /// Reflectance of dielectrics head on, where 0.5 is the 4% of most
+    /// materials
+    pub specular: SharedTexture<N>,
+    /// Tints the specular reflection of dielectrics towards the base color
+    pub specular_tint: SharedTexture<N>,
+    /// Extra reflection towards grazing angles, like off cloth
+    pub sheen: SharedTexture<N>,
+    /// Tints the sheen towards the base color
+    pub sheen_tint: SharedTexture<N>,
+    /// Strength of a second, white specular layer on top, like varnish
+    pub clearcoat: SharedTexture<N>,
+    /// Smoothness of the clearcoat
+    pub clearcoat_gloss: SharedTexture<N>,
+    /// Blends from an opaque dielectric to one refracting light through,
+    /// tinted by the base color
+    pub transmission: SharedTexture<N>,
+    /// Stretches the specular highlight along the tangent of the surface
+    pub anisotropic: SharedTexture<N>,
+    /// Index of refraction of the transmitting material
+    pub ior: N,
+}
+
+impl Principled {
+    /// Rough dielectric of `base_color`, the other parameters at Disney's
+    /// defaults
+    pub fn new(base_color: SharedTexture<Color>) -> Self {
+        Self {
+            base_color,
+            metallic: Constant::shared(0.0),
+            roughness: Constant::shared(0.5),
+            specular: Constant::shared(0.5),
+            specular_tint: Constant::shared(0.0),
+            sheen: Constant::shared(0.0),
+            sheen_tint: Constant::shared(0.5),
+            clearcoat: Constant::shared(0.0),
+            clearcoat_gloss: Constant::shared(1.0),
+            transmission: Constant::shared(0.0),
+            anisotropic: Constant::shared(0.0),
+            ior: 1.5,
+        }
+    }
+
+    /// The metallic-roughness model of glTF
+    pub fn metallic_roughness(
+        base_color: SharedTexture<Color>,
+        metallic: SharedTexture<N>,
+        roughness: SharedTexture<N>,
+    ) -> Self {
+        Self {
+            metallic,
+            roughness,
+            ..Self::new(base_color)
+        }
+    }
+}
+
+fn mix(a: Color, b: Color, t: N) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+// Generalized Trowbridge-Reitz distribution with a long tail, for the
+// clearcoat's normals
+fn gtr1(cos_h: N, alpha: N) -> N {
+    if alpha >= 1.0 {
+        return 1.0 / PI;
+    }
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+// The parameters at a hit point, worked out into weights and colors for each
+// lobe. Directions are local to the surface, facing `wo`.
+struct Lobes {
+    frame: Onb,
+    base_color: Color,
+    roughness: N,
+    /// Weight of the diffuse and sheen lobes
+    diffuse: N,
+    sheen: Color,
+    /// Specular reflectance head on, where it follows Schlick's approximation
+    specular: Color,
+    specular_distribution: Ggx,
+    /// Weight of the dielectric, which refracts what it doesn't reflect
+    transmission: N,
+    clearcoat: N,
+    clearcoat_alpha: N,
+    /// Index of refraction on the far side of the surface over the near side
+    eta: N,
+}
+
+impl Lobes {
+    fn new(material: &Principled, hit_record: &HitRecord) -> Self {
+        let base_color = material.base_color.value(hit_record);
+        let metallic = material.metallic.value(hit_record).clamp(0.0, 1.0);
+        // Kept off a perfect mirror, which would need sampling as a delta
+        let roughness = material.roughness.value(hit_record).clamp(0.03, 1.0);
+        let transmission = material.transmission.value(hit_record).clamp(0.0, 1.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            white
+        };
+
+        let specular = mix(white, tint, material.specular_tint.value(hit_record))
+            * (0.08 * material.specular.value(hit_record));
+        let aspect = (1.0 - 0.9 * material.anisotropic.value(hit_record).clamp(0.0, 1.0)).sqrt();
+        Self {
+            frame: hit_record.shading_frame(),
+            base_color,
+            roughness,
+            diffuse: (1.0 - metallic) * (1.0 - transmission),
+            sheen: mix(white, tint, material.sheen_tint.value(hit_record))
+                * material.sheen.value(hit_record),
+            specular: mix(specular, base_color, metallic),
+            specular_distribution: Ggx::anisotropic(
+                roughness / aspect.sqrt(),
+                roughness * aspect.sqrt(),
+            ),
+            transmission: (1.0 - metallic) * transmission,
+            clearcoat: 0.25 * material.clearcoat.value(hit_record).clamp(0.0, 1.0),
+            clearcoat_alpha: 0.1 - 0.099 * material.clearcoat_gloss.value(hit_record),
+            eta: if hit_record.front_face {
+                material.ior
+            } else {
+                1.0 / material.ior
+            },
+        }
+    }
+
+    // Specular reflectance, by the full Fresnel equations for the part that
+    // transmits so it reflects exactly what doesn't get through
+    fn fresnel(&self, cos_i: N) -> Color {
+        let f = fresnel_dielectric(cos_i, self.eta);
+        fresnel_schlick(cos_i, &self.specular) * (1.0 - self.transmission)
+            + Color::new(f, f, f) * self.transmission
+    }
+
+    // Chances of sampling the diffuse, specular, clearcoat and transmission
+    // lobes
+    fn probabilities(&self, wo: &Vector3D) -> [N; 4] {
+        let f = fresnel_dielectric(*wo.z(), self.eta);
+        let weights = [
+            self.diffuse,
+            1.0 - self.transmission * (1.0 - f),
+            self.clearcoat,
+            self.transmission * (1.0 - f),
+        ];
+        let total: N = weights.iter().sum();
+        weights.map(|weight| weight / total)
+    }
+
+    fn eval(&self, wi: &Vector3D, wo: &Vector3D) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let (cos_i, cos_o) = (*wi.z(), *wo.z());
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return black;
+        }
+
+        if cos_i < 0.0 {
+            // Refracted through a microfacet
+            if self.transmission == 0.0 {
+                return black;
+            }
+            let wm = match self.refracting_normal(wi, wo) {
+                Some(wm) => wm,
+                None => return black,
+            };
+            let distribution = &self.specular_distribution;
+            let f = fresnel_dielectric(wo.dot(&wm), self.eta);
+            let denominator = wi.dot(&wm) + wo.dot(&wm) / self.eta;
+            let value = distribution.d(&wm)
+                * distribution.g(wo, wi)
+                * (1.0 - f)
+                * (wi.dot(&wm) * wo.dot(&wm)).abs()
+                / (denominator * denominator * cos_o);
+            return self.base_color * (self.transmission * value);
+        }
+
+        let wh = *wi + *wo;
+        if wh.near_zero() {
+            return black;
+        }
+        let wh = wh.unit();
+        let cos_d = wi.dot(&wh);
+
+        // Diffuse, brighter towards grazing angles on rough surfaces and
+        // darker on smooth ones
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * (1.0 - cos_i).powi(5))
+            * (1.0 + (fd90 - 1.0) * (1.0 - cos_o).powi(5));
+        let diffuse = self.base_color * (fd / PI) + self.sheen * (1.0 - cos_d).powi(5);
+        let mut value = diffuse * (self.diffuse * cos_i);
+
+        // The cosine with `wi` cancels out of the microfacet BRDFs
+        let distribution = &self.specular_distribution;
+        value += self.fresnel(wo.dot(&wh)) * distribution.d(&wh) * distribution.g(wo, wi)
+            / (4.0 * cos_o);
+        if self.clearcoat > 0.0 {
+            let f = fresnel_schlick(wo.dot(&wh), &Color::new(0.04, 0.04, 0.04));
+            let g = Ggx::new(0.5).g(wo, wi);
+            value += f * (self.clearcoat * gtr1(*wh.z(), self.clearcoat_alpha) * g / (4.0 * cos_o));
+        }
+        value
+    }
+
+    fn pdf(&self, wi: &Vector3D, wo: &Vector3D) -> N {
+        let (cos_i, cos_o) = (*wi.z(), *wo.z());
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return 0.0;
+        }
+        let [diffuse, specular, clearcoat, transmission] = self.probabilities(wo);
+
+        if cos_i < 0.0 {
+            if transmission == 0.0 {
+                return 0.0;
+            }
+            return match self.refracting_normal(wi, wo) {
+                Some(wm) => {
+                    let denominator = wi.dot(&wm) + wo.dot(&wm) / self.eta;
+                    transmission
+                        * self.specular_distribution.pdf_visible(wo, &wm)
+                        * wi.dot(&wm).abs()
+                        / (denominator * denominator)
+                }
+                None => 0.0,
+            };
+        }
+
+        let wh = *wi + *wo;
+        if wh.near_zero() {
+            return 0.0;
+        }
+        let wh = wh.unit();
+        // Over the change from normals to mirrored directions
+        let jacobian = 4.0 * wo.dot(&wh);
+        diffuse * cos_i / PI
+            + specular * self.specular_distribution.pdf_visible(wo, &wh) / jacobian
+            + clearcoat * gtr1(*wh.z(), self.clearcoat_alpha) * wh.z() / jacobian
+    }
+
+    // Pick a direction from one of the lobes, `None` if the path ends
+    fn sample(&self, wo: &Vector3D) -> Option<Vector3D> {
+        let [diffuse, specular, clearcoat, _] = self.probabilities(wo);
+        let xi = random_n();
+        if xi < diffuse {
+            let wi = Vector3D::new(0.0, 0.0, 1.0) + random_unit_vector();
+            Some(if wi.near_zero() {
+                Vector3D::new(0.0, 0.0, 1.0)
+            } else {
+                wi.unit()
+            })
+        } else if xi < diffuse + specular {
+            Some(mirror(wo, &self.specular_distribution.sample_visible(wo)))
+        } else if xi < diffuse + specular + clearcoat {
+            let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+            let cos_theta = ((1.0 - a2.powf(1.0 - random_n())) / (1.0 - a2))
+                .max(0.0)
+                .sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * random_n();
+            let wh = Vector3D::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            Some(mirror(wo, &wh))
+        } else {
+            let wm = self.specular_distribution.sample_visible(wo);
+            transmit(wo, &wm, self.eta).map(|(wi, _)| wi)
+        }
+    }
+
+    // Microfacet normal refracting `wo` to `wi` below the surface, facing up
+    fn refracting_normal(&self, wi: &Vector3D, wo: &Vector3D) -> Option<Vector3D> {
+        let wm = *wi * self.eta + *wo;
+        if wm.near_zero() {
+            return None;
+        }
+        let mut wm = wm.unit();
+        if *wm.z() < 0.0 {
+            wm = -wm;
+        }
+        if wm.dot(wi) * wi.z() < 0.0 || wm.dot(wo) * wo.z() < 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        hit_record: &mut HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        // Textures are looked up once for sampling and weighing the direction
+        let lobes = Lobes::new(self, hit_record);
+        let wo = lobes.frame.to_local(&-r_in.direction().unit());
+        let wi = match lobes.sample(&wo) {
+            Some(wi) => wi,
+            None => return false,
+        };
+        let pdf = lobes.pdf(&wi, &wo);
+        if pdf <= 0.0 {
+            return false;
+        }
+        *scattered = hit_record.spawn_ray(lobes.frame.to_world(&wi), *r_in.time());
+        *attenuation = lobes.eval(&wi, &wo) / pdf;
+        true
+    }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.base_color.value(hit_record)
+    }
+
+    fn eval(&self, hit_record: &HitRecord, wi: &Vector3D, wo: &Vector3D) -> Color {
+        let lobes = Lobes::new(self, hit_record);
+        lobes.eval(&lobes.frame.to_local(wi), &lobes.frame.to_local(wo))
+    }
+
+    // Mixture of the lobes' densities, by the chance of sampling each
+    fn pdf(&self, hit_record: &HitRecord, wi: &Vector3D, wo: &Vector3D) -> N {
+        let lobes = Lobes::new(self, hit_record);
+        lobes.pdf(&lobes.frame.to_local(wi), &lobes.frame.to_local(wo))
+    }
+}
+
+#[test]
+fn scatter_matches_eval_and_pdf() {
+    use super::microfacet::check_sampling;
+    use crate::vector::Point3D;
+
+    let red = Constant::shared(Color::new(0.8, 0.2, 0.1));
+    let plastic = Principled {
+        sheen: Constant::shared(1.0),
+        clearcoat: Constant::shared(1.0),
+        clearcoat_gloss: Constant::shared(0.5),
+        ..Principled::new(red.clone())
+    };
+    let brushed = Principled {
+        anisotropic: Constant::shared(0.8),
+        ..Principled::metallic_roughness(red.clone(), Constant::shared(1.0), Constant::shared(0.4))
+    };
+    let frosted = Principled {
+        transmission: Constant::shared(0.7),
+        roughness: Constant::shared(0.3),
+        ..Principled::new(red)
+    };
+
+    let r_in = Ray::new(
+        Point3D::new(-1.0, 2.0, 0.5),
+        Vector3D::new(1.0, -2.0, -0.5),
+        0.0,
+    );
+    for material in &[plastic, brushed, frosted] {
+        // From outside and from inside
+        for &front_face in &[true, false] {
+            let mut hit_record = HitRecord {
+                normal: Vector3D::new(0.0, 1.0, 0.0),
+                front_face,
+                ..Default::default()
+            };
+            check_sampling(material, &mut hit_record, &r_in);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::vector::{Vector3D, N};
 
 pub fn reflect(v: &Vector3D, normal: &Vector3D) -> Vector3D {
-    normal - &(normal * v.dot(normal) * 2.0)
+    v - &(normal * v.dot(normal) * 2.0)
 }
 
 pub fn refract(uv: &Vector3D, n: &Vector3D, etai_over_etat: N) -> Vector3D {
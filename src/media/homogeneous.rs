@@ -0,0 +1,130 @@
+use super::{HenyeyGreenstein, Medium, MediumSample};
+use crate::ray::Ray;
+use crate::utils::{random_int_range, random_n};
+use crate::vector::{Color, Vector3D, N};
+
+/// Medium with the same density everywhere. `sigma_a` and `sigma_s` are the
+/// chances per unit distance, for each channel, of light being absorbed or
+/// scattered.
+pub struct Homogeneous {
+    sigma_a: Color,
+    sigma_s: Color,
+    phase: HenyeyGreenstein,
+}
+
+impl Homogeneous {
+    pub fn new(sigma_a: Color, sigma_s: Color, g: N) -> Self {
+        Self {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g),
+        }
+    }
+
+    /// Medium which doesn't interact with light at all, for keeping the inside
+    /// of an object clear of the medium around it
+    #[allow(dead_code)]
+    pub fn vacuum() -> Self {
+        let black = Color::new(0.0, 0.0, 0.0);
+        Self::new(black, black, 0.0)
+    }
+
+    fn sigma_t(&self) -> Color {
+        self.sigma_a + self.sigma_s
+    }
+
+    // Beer-Lambert law over `distance`
+    fn transmittance_over(&self, distance: N) -> Color {
+        let sigma_t = self.sigma_t();
+        let channel = |sigma: N| {
+            if sigma <= 0.0 {
+                1.0
+            } else {
+                (-sigma * distance).exp()
+            }
+        };
+        Color::new(
+            channel(*sigma_t.x()),
+            channel(*sigma_t.y()),
+            channel(*sigma_t.z()),
+        )
+    }
+}
+
+impl Medium for Homogeneous {
+    // Picks one channel, then the distance by its exponential falloff. The
+    // density is averaged over the channels that could have been picked.
+    fn sample(&self, ray: &Ray, t_max: N) -> MediumSample {
+        let sigma_t = self.sigma_t();
+        let sigma = match random_int_range(0, 3) {
+            0 => *sigma_t.x(),
+            1 => *sigma_t.y(),
+            _ => *sigma_t.z(),
+        };
+        let length = ray.direction().length();
+        let distance = if sigma > 0.0 {
+            -(1.0 - random_n()).ln() / sigma
+        } else {
+            N::INFINITY
+        };
+
+        let average = |c: Color| (c.x() + c.y() + c.z()) / 3.0;
+        if distance < t_max * length {
+            let transmittance = self.transmittance_over(distance);
+            let density = average(sigma_t * transmittance);
+            MediumSample {
+                t: Some(distance / length),
+                weight: transmittance * self.sigma_s / density,
+            }
+        } else {
+            let transmittance = self.transmittance_over(t_max * length);
+            let density = average(transmittance);
+            MediumSample {
+                t: None,
+                weight: if density > 0.0 {
+                    transmittance / density
+                } else {
+                    Color::new(0.0, 0.0, 0.0)
+                },
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: N) -> Color {
+        self.transmittance_over(t_max * ray.direction().length())
+    }
+
+    fn phase(&self, wi: &Vector3D, wo: &Vector3D) -> N {
+        self.phase.eval(wi, wo)
+    }
+
+    fn sample_phase(&self, wo: &Vector3D) -> Vector3D {
+        self.phase.sample(wo)
+    }
+}
+
+#[test]
+fn sampling_estimates_transmittance() {
+    use crate::vector::Point3D;
+
+    // Throughput of paths getting through, or scattering and being counted
+    // as lost, averages out to what gets through
+    let medium = Homogeneous::new(Color::new(0.1, 0.5, 1.0), Color::new(0.3, 0.2, 0.1), 0.0);
+    let ray = Ray::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        Vector3D::new(0.0, 2.0, 0.0),
+        0.0,
+    );
+    let t_max = 1.0;
+    let samples = 200_000;
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    for _ in 0..samples {
+        let sample = medium.sample(&ray, t_max);
+        if sample.t.is_none() {
+            sum += sample.weight;
+        }
+    }
+    let expected = medium.transmittance(&ray, t_max);
+    assert!((sum / samples as N - expected).length() < 0.01);
+    assert!((*expected.x() - (-0.8 as N).exp()).abs() < 1e-6);
+}
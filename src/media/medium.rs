@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use crate::ray::Ray;
+use crate::vector::{Color, Vector3D, N};
+
+pub type SharedMedium = Arc<dyn Medium + Sync + Send>;
+
+/// Where a ray travelling through a medium was picked to scatter
+pub struct MediumSample {
+    /// Distance along the ray at which it scatters, `None` if it got through
+    /// to the end of the segment
+    pub t: Option<N>,
+    /// What the path's throughput is scaled by: the transmittance up to the
+    /// point picked, times the scattering coefficient there if it scatters,
+    /// over the density of picking it
+    pub weight: Color,
+}
+
+/// Participating medium which absorbs and scatters light travelling through
+/// it, like fog, smoke or a tinted liquid
+pub trait Medium {
+    /// Pick where a ray scatters before `t_max`, by sampling the free-flight
+    /// distance through the medium
+    fn sample(&self, ray: &Ray, t_max: N) -> MediumSample;
+
+    /// Fraction of the light which gets through along a ray up to `t_max`
+    fn transmittance(&self, ray: &Ray, t_max: N) -> Color;
+
+    /// Share of the light scattered at a point which arrives from `wi` and
+    /// leaves towards `wo` (both unit vectors pointing away from the point),
+    /// per unit solid angle. Also the density of `sample_phase` picking `wi`.
+    fn phase(&self, wi: &Vector3D, wo: &Vector3D) -> N;
+
+    /// Pick a direction for light leaving towards `wo` to arrive from
+    fn sample_phase(&self, wo: &Vector3D) -> Vector3D;
+}
@@ -0,0 +1,9 @@
+mod homogeneous;
+mod medium;
+mod phase;
+mod stack;
+
+pub use homogeneous::*;
+pub use medium::*;
+pub use phase::*;
+pub use stack::*;
@@ -0,0 +1,66 @@
+use crate::utils::{coordinate_system, random_n, PI};
+use crate::vector::{Vector3D, N};
+
+/// Henyey-Greenstein phase function, scattering light forwards for positive
+/// `g` and backwards for negative, with `g` the mean cosine of the angle it
+/// turns by
+#[derive(Clone, Copy)]
+pub struct HenyeyGreenstein {
+    g: N,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: N) -> Self {
+        Self {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// For `wi` and `wo` both pointing away from the point, so light going
+    /// straight on has them opposite
+    pub fn eval(&self, wi: &Vector3D, wo: &Vector3D) -> N {
+        let cos_theta = wi.dot(wo);
+        let g = self.g;
+        let denominator = 1.0 + g * g + 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(0.0).sqrt())
+    }
+
+    /// Inverts the distribution of the cosine between `wi` and `wo`, with a
+    /// uniform angle around `wo`
+    pub fn sample(&self, wo: &Vector3D) -> Vector3D {
+        let g = self.g;
+        let u = random_n();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let term = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+            -(1.0 + g * g - term * term) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_n();
+
+        let (u, v) = coordinate_system(wo);
+        u * (phi.cos() * sin_theta) + v * (phi.sin() * sin_theta) + wo * cos_theta
+    }
+}
+
+#[test]
+fn phase_sampling_matches_eval() {
+    let wo = Vector3D::new(0.0, 0.0, 1.0);
+    for &g in &[-0.5, 0.0, 0.3, 0.8] {
+        let phase = HenyeyGreenstein::new(g);
+        let samples = 100_000;
+        let mut cosine = 0.0;
+        let mut inverse_pdf = 0.0;
+        for _ in 0..samples {
+            let wi = phase.sample(&wo);
+            assert!((wi.length() - 1.0).abs() < 1e-6);
+            // Light arriving from `wi` carries on along `-wi`
+            cosine += -wi.dot(&wo);
+            inverse_pdf += 1.0 / phase.eval(&wi, &wo);
+        }
+        assert!((cosine / samples as N - g).abs() < 0.01);
+        // Averaging 1 / pdf over samples gives the solid angle they cover
+        assert!((inverse_pdf / samples as N / (4.0 * PI) - 1.0).abs() < 0.03);
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use super::SharedMedium;
+use crate::hittables::HitRecord;
+use crate::vector::Vector3D;
+
+/// The media a path is inside of, innermost last. Crossing into an object
+/// whose material bounds a medium pushes it, and crossing back out removes
+/// it, so objects can nest, like a glass of liquid in a foggy room.
+///
+/// The stack is kept by the integrator following the path, not by `Ray`:
+/// rays are small `Copy` values made by the million, and only the
+/// volumetric integrator ever asks which medium one is in.
+#[derive(Clone, Default)]
+pub struct MediumStack {
+    // Medium outside all others, if any
+    ambient: Option<SharedMedium>,
+    inside: Vec<SharedMedium>,
+}
+
+impl MediumStack {
+    pub fn new(ambient: Option<SharedMedium>) -> Self {
+        Self {
+            ambient,
+            inside: Vec::new(),
+        }
+    }
+
+    /// Medium a ray leaving now travels through, `None` for empty space
+    pub fn current(&self) -> Option<&SharedMedium> {
+        self.inside.last().or(self.ambient.as_ref())
+    }
+
+    /// Update the stack for a path leaving the surface hit in `rec` towards
+    /// `direction`. Nothing changes unless it passes through to the other side
+    /// of a surface bounding a medium.
+    pub fn cross(&mut self, rec: &HitRecord, direction: &Vector3D) {
+        let interior = match rec.material.interior() {
+            Some(interior) => interior,
+            None => return,
+        };
+        // The normal faces the side the path arrived from
+        if direction.dot(&rec.normal) >= 0.0 {
+            return;
+        }
+        if rec.front_face {
+            self.inside.push(interior.clone());
+        } else {
+            let id = |medium: &SharedMedium| Arc::as_ptr(medium) as *const ();
+            if let Some(index) = self
+                .inside
+                .iter()
+                .rposition(|medium| id(medium) == id(interior))
+            {
+                self.inside.remove(index);
+            }
+        }
+    }
+}
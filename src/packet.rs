@@ -0,0 +1,96 @@
+use crate::ray::Ray;
+
+pub const PACKET_WIDTH: usize = 8;
+pub const PACKET_SIZE: usize = PACKET_WIDTH * PACKET_WIDTH;
+
+/// A tile of coherent rays traced together. Bit `i` of an active mask refers
+/// to `rays[i]`.
+pub struct RayPacket {
+    pub rays: [Ray; PACKET_SIZE],
+    pub active: u64,
+}
+
+impl RayPacket {
+    pub fn new() -> Self {
+        Self {
+            rays: [Ray::default(); PACKET_SIZE],
+            active: 0,
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, lane: usize, ray: Ray) {
+        self.rays[lane] = ray;
+        self.active |= 1 << lane;
+    }
+}
+
+/// Iterate over the indices of the set bits of a mask
+#[inline]
+pub fn lanes(mut mask: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if mask == 0 {
+            None
+        } else {
+            let lane = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            Some(lane)
+        }
+    })
+}
+
+#[test]
+fn packet_traversal_matches_single_rays() {
+    use std::sync::Arc;
+
+    use crate::hittables::{HitRecord, Hittable, Hittables, Sphere, Triangle};
+    use crate::materials::Lambert;
+    use crate::scene::Scene;
+    use crate::vector::{Color, Point3D, Vector3D, N};
+
+    let material = Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5)));
+    let mut objects = Hittables::new();
+    for _ in 0..20 {
+        objects.add(Arc::new(Sphere::new(
+            Point3D::random_range(-5.0, 5.0),
+            0.5,
+            material.clone(),
+        )));
+        let v0 = Point3D::random_range(-5.0, 5.0);
+        objects.add(Arc::new(Triangle::new(
+            v0,
+            v0 + Vector3D::random_range(-1.0, 1.0),
+            v0 + Vector3D::random_range(-1.0, 1.0),
+            material.clone(),
+        )));
+    }
+    // Built over whichever BVH the `simd` feature picks
+    let world = Scene::new(objects).world;
+
+    let mut packet = RayPacket::new();
+    let origin = Point3D::new(0.0, 0.0, 12.0);
+    for lane in 0..PACKET_SIZE {
+        // Leave a few lanes inactive
+        if lane % 7 != 3 {
+            let target = Point3D::random_range(-5.0, 5.0);
+            packet.set(lane, Ray::new(origin, target - origin, 0.0));
+        }
+    }
+
+    let mut t_max = [N::MAX; PACKET_SIZE];
+    let mut recs = vec![HitRecord::default(); PACKET_SIZE];
+    let hits = world.hit_packet(&packet, packet.active, 0.001, &mut t_max, &mut recs);
+    assert_eq!(hits & !packet.active, 0);
+
+    let occluded = world.occluded_packet(&packet, packet.active, 0.001, &[N::MAX; PACKET_SIZE]);
+    assert_eq!(occluded, hits);
+
+    for lane in lanes(packet.active) {
+        let mut rec = HitRecord::default();
+        let expected = world.hit(&packet.rays[lane], 0.001, N::MAX, &mut rec);
+        assert_eq!(expected, hits & (1 << lane) != 0);
+        if expected {
+            assert_eq!(rec.t, recs[lane].t);
+        }
+    }
+}
@@ -1,10 +1,50 @@
-use crate::hittables::{HitRecord, Hittable};
+use crate::scene::Scene;
+use crate::utils;
 use crate::vector::{Color, Point3D, Vector3D, N};
 
+/// Closest distance along a ray at which hits are accepted. Spawned rays are
+/// moved off the surface they leave instead, see `offset_ray_origin`.
+pub const T_MIN: N = 0.0;
+
+/// Move a hit point along the geometric normal just far enough that the true
+/// surface, which lies within `p_error` of `p`, is behind a ray leaving in
+/// `direction`. Works at any scene scale since the error bounds scale with it.
+#[inline]
+pub fn offset_ray_origin(
+    p: &Point3D,
+    p_error: &Vector3D,
+    normal: &Vector3D,
+    direction: &Vector3D,
+) -> Point3D {
+    let distance = normal.abs().dot(p_error);
+    let mut offset = normal * distance;
+    if direction.dot(normal) < 0.0 {
+        offset = -offset;
+    }
+    let po = p + &offset;
+
+    // Round away from `p` so the offset can't be lost to rounding
+    let round = |x: N, o: N| {
+        if o > 0.0 {
+            utils::next_float_up(x)
+        } else if o < 0.0 {
+            utils::next_float_down(x)
+        } else {
+            x
+        }
+    };
+    Point3D::new(
+        round(*po.x(), *offset.x()),
+        round(*po.y(), *offset.y()),
+        round(*po.z(), *offset.z()),
+    )
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct Ray {
     origin: Point3D,
     direction: Vector3D,
+    inv_direction: Vector3D,
     time: N,
 }
 
@@ -13,6 +53,11 @@ impl Ray {
         Self {
             origin,
             direction,
+            inv_direction: Vector3D::new(
+                1.0 / direction.x(),
+                1.0 / direction.y(),
+                1.0 / direction.z(),
+            ),
             time,
         }
     }
@@ -27,6 +72,12 @@ impl Ray {
         &self.direction
     }
 
+    /// Component-wise reciprocal of the direction, precomputed for slab tests
+    #[inline]
+    pub fn inv_direction(&self) -> &Vector3D {
+        &self.inv_direction
+    }
+
     #[inline]
     pub fn time(&self) -> &N {
         &self.time
@@ -34,28 +85,83 @@ impl Ray {
 
     #[inline]
     pub fn at(&self, t: N) -> Point3D {
-        &self.origin + &(&self.direction * t)
+        self.origin + self.direction * t
     }
 
-    pub fn color(&self, world: &dyn Hittable, depth: usize) -> Color {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
-        }
+    /// Color of a ray that escapes the scene
+    #[inline]
+    pub fn background(&self, scene: &Scene) -> Color {
+        scene.background.radiance(self)
+    }
+}
+
+#[test]
+fn spawned_rays_do_not_self_intersect_at_any_scale() {
+    use std::sync::Arc;
+
+    use crate::hittables::{HitRecord, Hittable, Quad, Sphere, Triangle};
+    use crate::materials::Lambert;
+    use crate::utils::random_unit_vector;
 
-        let mut hit_record = HitRecord::default();
-        if world.hit(self, 0.001, N::MAX, &mut hit_record) {
-            let mut scattered = Ray::default();
-            let mut attenuation = Color::default();
-            let material = hit_record.material.clone();
-            if material.scatter(&self, &mut hit_record, &mut attenuation, &mut scattered) {
-                attenuation * scattered.color(world, depth - 1)
-            } else {
-                Color::new(0.0, 0.0, 0.0)
+    let material = Arc::new(Lambert::new(Color::new(0.5, 0.5, 0.5)));
+    // Millimeters to kilometers, and far from the origin
+    for &scale in &[1e-3, 1.0, 1e3, 1e5] {
+        let center = Point3D::new(3.0, -2.0, 7.0) * scale;
+        let sphere = Sphere::new(center, scale, material.clone());
+        let triangle = Triangle::new(
+            center + Point3D::new(-2.0, 1.0, -1.0) * scale,
+            center + Point3D::new(2.0, 1.5, -1.0) * scale,
+            center + Point3D::new(0.0, 1.2, 2.0) * scale,
+            material.clone(),
+        );
+
+        // Axis-aligned through the origin, so its hit points have no error
+        let quad = Quad::new(
+            Point3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(10.0, 0.0, 0.0) * scale,
+            Vector3D::new(0.0, 0.0, 10.0) * scale,
+            material.clone(),
+        );
+        let surfaces = [
+            (&sphere as &dyn Hittable, true),
+            (&triangle as &dyn Hittable, false),
+            (&quad as &dyn Hittable, false),
+        ];
+        for &(surface, is_sphere) in surfaces.iter() {
+            let mut self_hits = 0;
+            for _ in 0..10_000 {
+                let origin = center + random_unit_vector() * (4.0 * scale);
+                let target = center + Vector3D::random_range(-0.5, 0.5) * scale;
+                let ray = Ray::new(origin, target - origin, 0.0);
+
+                let mut rec = HitRecord::default();
+                if !surface.hit(&ray, T_MIN, N::MAX, &mut rec) {
+                    continue;
+                }
+
+                // Leave to the side the ray came from, and into the other side
+                let mut direction = random_unit_vector();
+                if direction.dot(&rec.normal) < 0.0 {
+                    direction = -direction;
+                }
+                for &direction in &[direction, -direction] {
+                    // Going into a sphere legitimately hits its far side after a
+                    // chord of length 2r|cos|, anything else is the surface itself
+                    let cos = direction.dot(&rec.normal).abs();
+                    let legitimate = if is_sphere && direction.dot(&rec.normal) < 0.0 {
+                        scale * cos
+                    } else {
+                        N::MAX
+                    };
+
+                    let spawned = rec.spawn_ray(direction, 0.0);
+                    let mut next = HitRecord::default();
+                    if surface.hit(&spawned, T_MIN, N::MAX, &mut next) && next.t < legitimate {
+                        self_hits += 1;
+                    }
+                }
             }
-        } else {
-            let unit_dir = self.direction.unit();
-            let t = 0.5 * (unit_dir.y() + 1.0);
-            Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
+            assert_eq!(self_hits, 0, "self-intersections at scale {}", scale);
         }
     }
 }
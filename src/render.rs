@@ -2,48 +2,93 @@ use indicatif::ProgressBar;
 use std::sync::Arc;
 use std::thread::spawn;
 
+use crate::aov::{Aov, Aovs};
 use crate::camera::Camera;
-use crate::hittables::Hittables;
+use crate::hittables::{HitRecord, Hittable};
+use crate::integrators::{sample_direct_packet, Integrator, SharedIntegrator};
+use crate::packet::{lanes, RayPacket, PACKET_SIZE, PACKET_WIDTH};
+use crate::ray::T_MIN;
+use crate::scene::Scene;
 use crate::utils;
 use crate::vector::{Color, N};
 
+/// How camera rays are traced
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    /// Trace every camera ray on its own
+    Single,
+    /// Trace camera rays in tiles of 8x8 sharing one BVH traversal, and so
+    /// the shadow rays from their first hits for integrators which take them.
+    /// Bounces after the first hit are incoherent, so they are still traced
+    /// one at a time.
+    Packet,
+}
+
+/// Render the image as 8-bit RGB, along with the average of each of `aovs`
+/// for every pixel
+#[allow(clippy::too_many_arguments)]
 pub fn sample(
     image_height: usize,
     image_width: usize,
     samples_per_pixel: usize,
-    max_depth: usize,
     camera: &'static Camera,
-    world: &'static Hittables,
+    scene: &'static Scene,
+    integrator: SharedIntegrator,
+    aovs: &[Aov],
+    mode: RenderMode,
     progress: Arc<ProgressBar>,
-) -> Vec<u8> {
+) -> (Vec<u8>, Vec<Vec<Color>>) {
+    // Each thread counts the rows of every pass it makes
+    let samples = samples_per_pixel / num_cpus::get();
+    progress.set_length((image_height * samples * num_cpus::get()) as u64);
+
     let mut threads = Vec::with_capacity(num_cpus::get());
     for _ in 0..num_cpus::get() {
         let new_progress = Arc::clone(&progress);
-        threads.push(spawn(move || {
-            let mut buf = Vec::with_capacity(image_height * image_width);
-            for j in (0..image_height).rev() {
-                if j % 100 == 0 {
-                    new_progress.inc(100);
-                }
-                for i in 0..image_width {
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                    for _ in 0..(samples_per_pixel / num_cpus::get()) {
-                        let u = ((i as N) + utils::random_n()) / ((image_width - 1) as N);
-                        let v = ((j as N) + utils::random_n()) / ((image_height - 1) as N);
-                        pixel_color += camera.get_ray(u, v).color(world, max_depth);
-                    }
-                    buf.push(pixel_color);
-                }
-            }
-            buf
+        let integrator = Arc::clone(&integrator);
+        let aovs = aovs.to_vec();
+        threads.push(spawn(move || match mode {
+            RenderMode::Single => sample_single(
+                image_height,
+                image_width,
+                samples,
+                camera,
+                scene,
+                integrator.as_ref(),
+                &aovs,
+                &new_progress,
+            ),
+            RenderMode::Packet => sample_packets(
+                image_height,
+                image_width,
+                samples,
+                camera,
+                scene,
+                integrator.as_ref(),
+                &aovs,
+                &new_progress,
+            ),
         }));
     }
 
     let mut new_buf = vec![Color::new(0.0, 0.0, 0.0); image_height * image_width];
+    let mut aov_bufs = vec![new_buf.clone(); aovs.len()];
     for thread in threads.into_iter() {
-        for (i, pixel) in thread.join().unwrap().into_iter().enumerate() {
+        let (buf, thread_aov_bufs) = thread.join().unwrap();
+        for (i, pixel) in buf.into_iter().enumerate() {
             new_buf[i] += pixel;
         }
+        for (aov_buf, thread_aov_buf) in aov_bufs.iter_mut().zip(thread_aov_bufs) {
+            for (i, value) in thread_aov_buf.into_iter().enumerate() {
+                aov_buf[i] += value / samples_per_pixel as N;
+            }
+        }
+    }
+
+    if let Some(film) = integrator.film() {
+        for (i, pixel) in new_buf.iter_mut().enumerate() {
+            *pixel += film.get(i);
+        }
     }
 
     let mut image_buf = Vec::with_capacity(image_height * image_width * 3);
@@ -57,7 +102,122 @@ pub fn sample(
         }
     }
 
-    image_buf
+    (image_buf, aov_bufs)
+}
+
+// Every pass over the image takes one sample per pixel, so integrators can
+// prepare for them together
+#[allow(clippy::too_many_arguments)]
+fn sample_single(
+    image_height: usize,
+    image_width: usize,
+    samples: usize,
+    camera: &Camera,
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    aovs: &[Aov],
+    progress: &ProgressBar,
+) -> (Vec<Color>, Vec<Vec<Color>>) {
+    let mut buf = vec![Color::new(0.0, 0.0, 0.0); image_height * image_width];
+    let mut aov_bufs = vec![buf.clone(); aovs.len()];
+    for _ in 0..samples {
+        integrator.begin_pass(scene);
+        for j in (0..image_height).rev() {
+            progress.inc(1);
+            for i in 0..image_width {
+                let u = ((i as N) + utils::random_n()) / ((image_width - 1) as N);
+                let v = ((j as N) + utils::random_n()) / ((image_height - 1) as N);
+                let mut sample_aovs = Aovs::default();
+                let pixel = (image_height - 1 - j) * image_width + i;
+                buf[pixel] += integrator.color_aovs(&camera.get_ray(u, v), scene, &mut sample_aovs);
+                for (aov_buf, &aov) in aov_bufs.iter_mut().zip(aovs) {
+                    aov_buf[pixel] += sample_aovs.get(aov);
+                }
+            }
+        }
+    }
+    (buf, aov_bufs)
+}
+
+// Pixels are laid out the same way as in `sample_single`, top row first
+#[allow(clippy::too_many_arguments)]
+fn sample_packets(
+    image_height: usize,
+    image_width: usize,
+    samples: usize,
+    camera: &Camera,
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    aovs: &[Aov],
+    progress: &ProgressBar,
+) -> (Vec<Color>, Vec<Vec<Color>>) {
+    let mut buf = vec![Color::new(0.0, 0.0, 0.0); image_height * image_width];
+    let mut aov_bufs = vec![buf.clone(); aovs.len()];
+    let mut packet = RayPacket::new();
+    let mut recs = vec![HitRecord::default(); PACKET_SIZE];
+    let mut direct = [Color::new(0.0, 0.0, 0.0); PACKET_SIZE];
+
+    for _ in 0..samples {
+        integrator.begin_pass(scene);
+        for tile_j in (0..image_height).step_by(PACKET_WIDTH) {
+            progress.inc(PACKET_WIDTH.min(image_height - tile_j) as u64);
+            for tile_i in (0..image_width).step_by(PACKET_WIDTH) {
+                packet.active = 0;
+                for lane in 0..PACKET_SIZE {
+                    let i = tile_i + lane % PACKET_WIDTH;
+                    let j = tile_j + lane / PACKET_WIDTH;
+                    if i < image_width && j < image_height {
+                        let u = ((i as N) + utils::random_n()) / ((image_width - 1) as N);
+                        let v = ((j as N) + utils::random_n()) / ((image_height - 1) as N);
+                        packet.set(lane, camera.get_ray(u, v));
+                    }
+                }
+
+                let mut t_max = [N::MAX; PACKET_SIZE];
+                let hits =
+                    scene
+                        .world
+                        .hit_packet(&packet, packet.active, T_MIN, &mut t_max, &mut recs);
+
+                // Light is sampled at the hits the integrator would sample it at
+                let mut lit = 0;
+                if integrator.takes_direct() {
+                    lit = lanes(hits)
+                        .filter(|&lane| !recs[lane].material.is_delta())
+                        .fold(0, |lit, lane| lit | 1 << lane);
+                    direct = [Color::new(0.0, 0.0, 0.0); PACKET_SIZE];
+                    sample_direct_packet(scene, &packet, lit, &recs, &mut direct);
+                }
+
+                for lane in lanes(packet.active) {
+                    let ray = &packet.rays[lane];
+                    let mut sample_aovs = Aovs::default();
+                    let color = if lit & (1 << lane) != 0 {
+                        let rec = &mut recs[lane];
+                        integrator.shade_direct_aovs(
+                            ray,
+                            scene,
+                            rec,
+                            direct[lane],
+                            &mut sample_aovs,
+                        )
+                    } else if hits & (1 << lane) != 0 {
+                        integrator.shade_aovs(ray, scene, &mut recs[lane], &mut sample_aovs)
+                    } else {
+                        integrator.miss_aovs(ray, scene, &mut sample_aovs)
+                    };
+                    let i = tile_i + lane % PACKET_WIDTH;
+                    let j = tile_j + lane / PACKET_WIDTH;
+                    let pixel = (image_height - 1 - j) * image_width + i;
+                    buf[pixel] += color;
+                    for (aov_buf, &aov) in aov_bufs.iter_mut().zip(aovs) {
+                        aov_buf[pixel] += sample_aovs.get(aov);
+                    }
+                }
+            }
+        }
+    }
+    (buf, aov_bufs)
 }
 
 #[inline]
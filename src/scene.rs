@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::backgrounds::{Gradient, SharedBackground};
+#[cfg(feature = "simd")]
+use crate::hittables::Bvh4;
+#[cfg(not(feature = "simd"))]
+use crate::hittables::BvhNode;
+use crate::hittables::{Hittables, LightTree};
+use crate::lights::SharedLight;
+use crate::media::SharedMedium;
+use crate::vector::N;
+
+/// Everything a ray can interact with
+pub struct Scene {
+    /// All objects, behind a bounding volume hierarchy
+    pub world: Hittables,
+    /// The emissive objects, for starting paths from
+    pub emitters: Hittables,
+    /// Surface area of each emissive object, by its object ID
+    pub emitter_areas: HashMap<usize, N>,
+    /// The emissive objects again, sampled for direct lighting by how much
+    /// they are expected to light each point
+    pub light_tree: LightTree,
+    /// Lights without geometry
+    pub lights: Vec<SharedLight>,
+    /// What rays that escape see
+    pub background: SharedBackground,
+    /// Medium filling all the space outside objects bounding their own, like
+    /// fog filling a room. Only the volumetric integrator takes media into
+    /// account.
+    pub medium: Option<SharedMedium>,
+    /// Most surfaces a path can hit
+    pub max_depth: usize,
+    /// Surfaces a path hits before it can be ended by Russian roulette
+    pub min_depth: usize,
+}
+
+impl Scene {
+    pub fn new(objects: Hittables) -> Self {
+        let mut emitters = Hittables::new();
+        let mut emitter_areas = HashMap::new();
+        for object in objects.iter().filter(|object| object.is_emissive()) {
+            emitters.add(Arc::clone(object));
+            emitter_areas.insert(Arc::as_ptr(object) as *const () as usize, object.area());
+        }
+
+        let light_tree = LightTree::new(emitters.iter().cloned().collect(), 0.0, 0.0);
+
+        let mut world = Hittables::new();
+        #[cfg(feature = "simd")]
+        world.add(Arc::new(Bvh4::new(objects.into_vec(), 0.0, 0.0)));
+        #[cfg(not(feature = "simd"))]
+        {
+            let mut objects = objects.into_vec();
+            let len = objects.len();
+            world.add(Arc::new(BvhNode::new(&mut objects, 0, len, 0.0, 0.0)));
+        }
+
+        Self {
+            world,
+            emitters,
+            emitter_areas,
+            light_tree,
+            lights: Vec::new(),
+            background: Arc::new(Gradient::default()),
+            medium: None,
+            max_depth: 50,
+            min_depth: 3,
+        }
+    }
+
+    pub fn add_light(&mut self, light: SharedLight) {
+        self.lights.push(light);
+    }
+}
@@ -0,0 +1,47 @@
+use super::{SharedTexture, Texture};
+use crate::hittables::HitRecord;
+use crate::vector::N;
+
+/// Alternates between two textures in squares over the surface coordinates
+pub struct Checker<T> {
+    even: SharedTexture<T>,
+    odd: SharedTexture<T>,
+    // Squares along each side of the unit square of surface coordinates
+    scale: N,
+}
+
+impl<T> Checker<T> {
+    pub fn new(even: SharedTexture<T>, odd: SharedTexture<T>, scale: N) -> Self {
+        Self { even, odd, scale }
+    }
+}
+
+impl<T> Texture<T> for Checker<T> {
+    fn value(&self, rec: &HitRecord) -> T {
+        let u = (rec.u * self.scale).floor() as i64;
+        let v = (rec.v * self.scale).floor() as i64;
+        if (u + v) % 2 == 0 {
+            self.even.value(rec)
+        } else {
+            self.odd.value(rec)
+        }
+    }
+}
+
+#[test]
+fn alternates_over_surface_coordinates() {
+    use super::Constant;
+
+    let checker = Checker::new(Constant::shared(1.0), Constant::shared(0.0), 4.0);
+    let at = |u: N, v: N| {
+        checker.value(&HitRecord {
+            u,
+            v,
+            ..Default::default()
+        })
+    };
+    assert_eq!(at(0.1, 0.1), 1.0);
+    assert_eq!(at(0.3, 0.1), 0.0);
+    assert_eq!(at(0.3, 0.3), 1.0);
+    assert_eq!(at(0.1, 0.8), 0.0);
+}
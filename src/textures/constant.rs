@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use super::{SharedTexture, Texture};
+use crate::hittables::HitRecord;
+
+/// The same value everywhere
+pub struct Constant<T>(T);
+
+impl<T> Constant<T> {
+    #[allow(dead_code)]
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Copy + Sync + Send + 'static> Constant<T> {
+    /// Constant texture ready to share between materials
+    pub fn shared(value: T) -> SharedTexture<T> {
+        Arc::new(Self(value))
+    }
+}
+
+impl<T: Copy> Texture<T> for Constant<T> {
+    fn value(&self, _: &HitRecord) -> T {
+        self.0
+    }
+}
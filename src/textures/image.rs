@@ -0,0 +1,36 @@
+use super::Texture;
+use crate::hdr::HdrImage;
+use crate::hittables::HitRecord;
+use crate::vector::{Color, N};
+
+/// Looks values up in an image by the surface coordinates, with v going from
+/// the bottom of the image to the top. As a scalar, gives the luminance.
+pub struct ImageTexture {
+    image: HdrImage,
+}
+
+impl ImageTexture {
+    pub fn new(image: HdrImage) -> Self {
+        Self { image }
+    }
+
+    // Nearest pixel, wrapping around at the edges
+    fn pixel(&self, u: N, v: N) -> Color {
+        let (width, height) = (self.image.width, self.image.height);
+        let x = ((u - u.floor()) * width as N) as usize;
+        let y = ((1.0 - (v - v.floor())) * height as N) as usize;
+        self.image.get(x.min(width - 1), y.min(height - 1))
+    }
+}
+
+impl Texture<Color> for ImageTexture {
+    fn value(&self, rec: &HitRecord) -> Color {
+        self.pixel(rec.u, rec.v)
+    }
+}
+
+impl Texture<N> for ImageTexture {
+    fn value(&self, rec: &HitRecord) -> N {
+        self.pixel(rec.u, rec.v).luminance()
+    }
+}
@@ -0,0 +1,9 @@
+mod checker;
+mod constant;
+mod image;
+mod texture;
+
+pub use checker::*;
+pub use constant::*;
+pub use image::*;
+pub use texture::*;
@@ -0,0 +1,12 @@
+use std::sync::Arc;
+
+use crate::hittables::HitRecord;
+
+pub type SharedTexture<T> = Arc<dyn Texture<T> + Sync + Send>;
+
+/// A material parameter which varies over surfaces, like a color or a
+/// roughness
+pub trait Texture<T> {
+    /// Value at the hit point
+    fn value(&self, rec: &HitRecord) -> T;
+}
@@ -1,11 +1,36 @@
 use rand::Rng;
+use std::cell::RefCell;
 
 use crate::vector::Vector3D;
 use crate::vector::N;
 
+pub const PI: N = std::f64::consts::PI as N;
+
+/// Source of the numbers in [0, 1) that `random_n`, and so every other
+/// `random_*` function, returns
+pub trait Sampler {
+    fn next_n(&mut self) -> N;
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Option<Box<dyn Sampler>>> = const { RefCell::new(None) };
+}
+
+/// Draw the random numbers on this thread from `sampler` until `f` returns,
+/// rather than from `rand::thread_rng()`
+pub fn with_sampler<R>(sampler: Box<dyn Sampler>, f: impl FnOnce() -> R) -> R {
+    let previous = SAMPLER.with(|current| current.replace(Some(sampler)));
+    let result = f();
+    SAMPLER.with(|current| current.replace(previous));
+    result
+}
+
 #[inline]
 pub fn random_n() -> N {
-    rand::thread_rng().gen()
+    SAMPLER.with(|sampler| match sampler.borrow_mut().as_mut() {
+        Some(sampler) => sampler.next_n(),
+        None => rand::thread_rng().gen(),
+    })
 }
 
 #[inline]
@@ -15,7 +40,8 @@ pub fn random_range(start: N, end: N) -> N {
 
 #[inline]
 pub fn random_int_range(start: usize, end: usize) -> usize {
-    rand::thread_rng().gen_range(start..end)
+    let offset = (random_n() * (end - start) as N) as usize;
+    start + offset.min(end - start - 1)
 }
 
 #[inline]
@@ -29,6 +55,37 @@ pub fn clamp(x: N, start: N, end: N) -> N {
     }
 }
 
+/// Conservative bound on the relative error accumulated by `n` floating point
+/// operations, γn in pbrt
+#[inline]
+pub fn gamma(n: u32) -> N {
+    let e = (n as N) * N::EPSILON * 0.5;
+    e / (1.0 - e)
+}
+
+/// Smallest representable value greater than `x`
+#[inline]
+pub fn next_float_up(x: N) -> N {
+    if x.is_infinite() && x > 0.0 {
+        return x;
+    }
+    // -0.0 and 0.0 step to the same value
+    let x = if x == 0.0 { 0.0 } else { x };
+    let bits = x.to_bits();
+    N::from_bits(if x >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+/// Largest representable value less than `x`
+#[inline]
+pub fn next_float_down(x: N) -> N {
+    if x.is_infinite() && x < 0.0 {
+        return x;
+    }
+    let x = if x == 0.0 { -0.0 } else { x };
+    let bits = x.to_bits();
+    N::from_bits(if x > 0.0 { bits - 1 } else { bits + 1 })
+}
+
 #[inline]
 pub fn random_in_unit_sphere() -> Vector3D {
     loop {
@@ -53,3 +110,58 @@ pub fn random_in_unit_disk() -> Vector3D {
         }
     }
 }
+
+/// Two unit vectors which form an orthonormal basis together with the unit vector `w`
+#[inline]
+pub fn coordinate_system(w: &Vector3D) -> (Vector3D, Vector3D) {
+    let a = if w.x().abs() > 0.9 {
+        Vector3D::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3D::new(1.0, 0.0, 0.0)
+    };
+    let v = w.cross(&a).unit();
+    let u = w.cross(&v);
+    (u, v)
+}
+
+/// Multiple importance sampling weight of a sample taken with density `f_pdf`
+/// when `g_pdf` could also have produced it, using the power heuristic
+#[inline]
+pub fn power_heuristic(f_pdf: N, g_pdf: N) -> N {
+    let (f, g) = (f_pdf * f_pdf, g_pdf * g_pdf);
+    if f + g == 0.0 {
+        return 1.0;
+    }
+    f / (f + g)
+}
+
+/// Direction uniformly distributed over the cone around the unit vector `w`
+/// whose half angle has the given 1 - cos, which stays accurate for narrow cones
+#[inline]
+pub fn random_in_cone(w: &Vector3D, one_minus_cos_max: N) -> Vector3D {
+    let z = 1.0 - random_n() * one_minus_cos_max;
+    let phi = 2.0 * PI * random_n();
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+
+    let (u, v) = coordinate_system(w);
+    u * (phi.cos() * sin_theta) + v * (phi.sin() * sin_theta) + w * z
+}
+
+#[test]
+fn coordinate_system_is_orthonormal() {
+    for w in [
+        Vector3D::new(0.0, 0.0, 1.0),
+        Vector3D::new(1.0, 0.0, 0.0),
+        Vector3D::new(-0.6, 0.0, 0.8),
+        Vector3D::new(0.3, -0.4, 0.5).unit(),
+    ] {
+        let (u, v) = coordinate_system(&w);
+        for (a, b) in [(&u, &v), (&u, &w), (&v, &w)] {
+            assert!(a.dot(b).abs() < 1e-6);
+        }
+        assert!((u.length() - 1.0).abs() < 1e-6);
+        assert!((v.length() - 1.0).abs() < 1e-6);
+        // The second vector turns to the first about `w`
+        assert!((v.cross(&u) - w).length() < 1e-6);
+    }
+}
@@ -1,7 +1,11 @@
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-use crate::utils::{random_n, random_range};
+use crate::utils::{coordinate_system, random_n, random_range};
 
+/// Scalar type used throughout the renderer, selected with the `f32` feature
+#[cfg(feature = "f32")]
+pub type N = f32;
+#[cfg(not(feature = "f32"))]
 pub type N = f64;
 pub type Point3D = Vector3D;
 pub type Color = Vector3D;
@@ -75,6 +79,24 @@ impl Vector3D {
         self / len
     }
 
+    /// Absolute value of each element
+    #[inline]
+    pub fn abs(&self) -> Self {
+        Self(self.0.abs(), self.1.abs(), self.2.abs())
+    }
+
+    /// Largest of the three elements
+    #[inline]
+    pub fn max_element(&self) -> N {
+        self.0.max(self.1).max(self.2)
+    }
+
+    /// Perceived brightness of a linear RGB color
+    #[inline]
+    pub fn luminance(&self) -> N {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
     #[inline]
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
@@ -82,6 +104,68 @@ impl Vector3D {
     }
 }
 
+/// Orthonormal basis around a surface normal, for working in a local frame
+/// with the normal along z
+#[derive(Clone, Debug)]
+pub struct Onb {
+    u: Vector3D,
+    v: Vector3D,
+    n: Vector3D,
+}
+
+impl Onb {
+    /// Basis around the unit vector `n`, turned any which way about it
+    pub fn new(n: &Vector3D) -> Self {
+        // Swapped, so that u x v = n as with a tangent
+        let (v, u) = coordinate_system(n);
+        Self { u, v, n: *n }
+    }
+
+    /// Basis around the unit vector `n` with x along `tangent`, once the part
+    /// of it along `n` is taken out. Falls back to `new` where `tangent` is
+    /// zero or parallel to `n`.
+    pub fn with_tangent(n: &Vector3D, tangent: &Vector3D) -> Self {
+        let u = *tangent - *n * n.dot(tangent);
+        if u.length_sq() < 1e-12 {
+            return Self::new(n);
+        }
+        let u = u.unit();
+        Self {
+            u,
+            v: n.cross(&u),
+            n: *n,
+        }
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub fn u(&self) -> &Vector3D {
+        &self.u
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub fn v(&self) -> &Vector3D {
+        &self.v
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub fn n(&self) -> &Vector3D {
+        &self.n
+    }
+
+    #[inline]
+    pub fn to_local(&self, w: &Vector3D) -> Vector3D {
+        Vector3D::new(w.dot(&self.u), w.dot(&self.v), w.dot(&self.n))
+    }
+
+    #[inline]
+    pub fn to_world(&self, w: &Vector3D) -> Vector3D {
+        self.u * *w.x() + self.v * *w.y() + self.n * *w.z()
+    }
+}
+
 // Scalar multiplication
 impl Mul<N> for &Vector3D {
     type Output = Vector3D;
@@ -361,7 +445,7 @@ fn vector_add_scalar_assign() {
 fn vector_sub_scalar_assign() {
     let mut vector1 = Vector3D::new(0.1, 0.2, 0.3);
     vector1 -= 0.1;
-    assert_eq!(Vector3D::new(0.0, 0.1, 0.20000002), vector1);
+    assert_eq!(Vector3D::new(0.0, 0.2 - 0.1, 0.3 - 0.1), vector1);
 }
 
 #[test]
@@ -382,41 +466,41 @@ fn vector_div_scalar_assign() {
 #[test]
 fn vector_add_assign() {
     let mut vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    vector1 += vector1.clone();
+    vector1 += vector1;
     assert_eq!(Vector3D::new(0.2, 0.4, 0.6), vector1);
 }
 
 #[test]
 fn vector_sub_assign() {
     let mut vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    vector1 -= vector1.clone();
+    vector1 -= vector1;
     assert_eq!(Vector3D::new(0.0, 0.0, 0.0), vector1);
 }
 
 #[test]
 fn vector_div_assign() {
     let mut vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    vector1 /= vector1.clone();
+    vector1 /= vector1;
     assert_eq!(Vector3D::new(1.0, 1.0, 1.0), vector1);
 }
 
 #[test]
 fn vector_mul_assign() {
     let mut vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    vector1 *= vector1.clone();
-    assert_eq!(Vector3D::new(0.010000001, 0.040000003, 0.09), vector1);
+    vector1 *= vector1;
+    assert_eq!(Vector3D::new(0.1 * 0.1, 0.2 * 0.2, 0.3 * 0.3), vector1);
 }
 
 #[test]
 fn vector_add_scalar() {
     let vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    assert_eq!(Vector3D::new(1.1, 1.2, 1.3), vector1.clone() + 1.0);
+    assert_eq!(Vector3D::new(1.1, 1.2, 1.3), vector1 + 1.0);
 }
 
 #[test]
 fn vector_sub_scalar() {
     let vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    assert_eq!(Vector3D::new(0.0, 0.1, 0.20000002), vector1.clone() - 0.1);
+    assert_eq!(Vector3D::new(0.0, 0.2 - 0.1, 0.3 - 0.1), vector1 - 0.1);
 }
 
 #[test]
@@ -434,13 +518,13 @@ fn vector_div_scalar() {
 #[test]
 fn vector_add() {
     let vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    assert_eq!(Vector3D::new(0.2, 0.4, 0.6), vector1.clone() + vector1);
+    assert_eq!(Vector3D::new(0.2, 0.4, 0.6), vector1 + vector1);
 }
 
 #[test]
 fn vector_sub() {
     let vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    assert_eq!(Vector3D::new(0.0, 0.0, 0.0), vector1.clone() - vector1);
+    assert_eq!(Vector3D::new(0.0, 0.0, 0.0), vector1 - vector1);
 }
 
 #[test]
@@ -452,15 +536,15 @@ fn vector_neg() {
 #[test]
 fn vector_div() {
     let vector1 = Vector3D::new(0.1, 0.2, 0.3);
-    assert_eq!(Vector3D::new(1.0, 1.0, 1.0), vector1.clone() / vector1);
+    assert_eq!(Vector3D::new(1.0, 1.0, 1.0), vector1 / vector1);
 }
 
 #[test]
 fn vector_mul() {
     let vector1 = Vector3D::new(0.1, 0.2, 0.3);
     assert_eq!(
-        Vector3D::new(0.010000001, 0.040000003, 0.09),
-        vector1.clone() * vector1
+        Vector3D::new(0.1 * 0.1, 0.2 * 0.2, 0.3 * 0.3),
+        vector1 * vector1
     );
 }
 
@@ -480,3 +564,30 @@ fn dot_product() {
     let vector2 = Vector3D::new(1.0, 5.0, 7.0);
     assert_eq!(Vector3D::new(-1.0, -4.0, 3.0), vector1.cross(&vector2));
 }
+
+#[test]
+fn onb_with_tangent() {
+    let n = Vector3D::new(0.0, 0.0, 1.0);
+    let onb = Onb::with_tangent(&n, &Vector3D::new(2.0, 0.0, 1.0));
+    assert_eq!(&Vector3D::new(1.0, 0.0, 0.0), onb.u());
+    assert_eq!(&Vector3D::new(0.0, 1.0, 0.0), onb.v());
+
+    // Round trips, and falls back to any basis without a tangent
+    let w = Vector3D::new(0.3, -0.4, 0.5);
+    for onb in [onb, Onb::with_tangent(&n, &Vector3D::default())] {
+        assert!((onb.to_world(&onb.to_local(&w)) - w).length() < 1e-6);
+        assert!((onb.u().cross(onb.v()) - n).length() < 1e-6);
+    }
+}
+
+#[test]
+fn onb_is_right_handed() {
+    for n in [
+        Vector3D::new(0.0, 0.0, 1.0),
+        Vector3D::new(0.0, -1.0, 0.0),
+        Vector3D::new(0.6, 0.0, 0.8),
+    ] {
+        let onb = Onb::new(&n);
+        assert!((onb.u().cross(onb.v()) - n).length() < 1e-6);
+    }
+}